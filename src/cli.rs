@@ -1,9 +1,25 @@
 use crate::git::{
-    helpers::{cmd_cat_file, cmd_hash_object, cmd_log, cmd_repo_create},
+    hash::ObjectFormat,
+    helpers::{
+        cmd_add, cmd_cat_file, cmd_check_ignore, cmd_checkout, cmd_commit, cmd_fsck, cmd_gc,
+        cmd_hash_object, cmd_index_pack, cmd_log, cmd_ls_files, cmd_ls_tree, cmd_pack_objects,
+        cmd_pack_refs, cmd_prune, cmd_repack, cmd_repo_create, cmd_rev_list, cmd_rev_parse, cmd_rm,
+        cmd_show_ref, cmd_status, cmd_tag_create, cmd_tag_delete, cmd_tag_list, cmd_verify_pack,
+        AddOptions, CheckIgnoreOptions, CheckoutOptions, CommitOptions, FsckOptions, GcOptions,
+        IndexPackOptions, LogDecorate, LogFormat, LogOptions, LsFilesOptions, LsTreeOptions,
+        PackObjectsOptions, PruneOptions, RepackOptions, RevListOptions, RevOptions, RmOptions,
+        ShowRefOptions, StatusOptions, VerifyPackOptions,
+    },
+    ident::{date_approx, date_expiry},
     repo::repo_find,
+    revision::RevSort,
+    status::UntrackedMode,
 };
 use clap::{Parser, Subcommand, ValueEnum};
+use std::fmt;
 use std::path::PathBuf;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// gitr: Git in Rust
 #[derive(Parser, Debug)]
@@ -18,12 +34,33 @@ pub enum Commands {
     /// Initialize an empty git repository
     ///
     Init {
+        /// Hash function naming the objects: sha1 or sha256
+        #[arg(long, value_name = "FORMAT", default_value = "sha1", value_parser = parse_object_format)]
+        object_format: ObjectFormat,
         /// Where to create the repository
         path: Option<PathBuf>,
     },
     /// Add a file to the staging area
     ///
-    Add,
+    Add {
+        /// Stage all changes, including removals, in the whole tree
+        #[arg(short = 'A', long, action = clap::ArgAction::SetTrue)]
+        all: bool,
+        /// Only stage changes to files that are already tracked
+        #[arg(short, long, action = clap::ArgAction::SetTrue, conflicts_with = "all")]
+        update: bool,
+        /// Don't actually add the files, just show what would be done
+        #[arg(short = 'n', long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+        /// Record only the fact that the path will be added later
+        #[arg(short = 'N', long, action = clap::ArgAction::SetTrue)]
+        intent_to_add: bool,
+        /// Allow adding otherwise ignored files
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// Files to add content from
+        pathspecs: Vec<String>,
+    },
     /// Provide content or details of repository objects
     ///
     CatFile {
@@ -35,13 +72,88 @@ pub enum Commands {
     },
     /// Debug gitignore / exclude files
     ///
-    CheckIgnore,
+    CheckIgnore {
+        /// Show the matching pattern and the file it comes from
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+        /// Do not skip paths tracked in the index
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        no_index: bool,
+        /// Read paths from standard input, one per line
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        stdin: bool,
+        /// Separate input and output paths with NUL characters
+        #[arg(short = 'z', action = clap::ArgAction::SetTrue)]
+        null_terminated: bool,
+        /// Paths to check
+        paths: Vec<String>,
+    },
     /// Switch branches or restore working tree files
     ///
-    Checkout,
+    Checkout {
+        /// Create a new branch and switch to it
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+        /// Detach `HEAD` at the commit, even if it names a branch
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "new_branch")]
+        detach: bool,
+        /// Throw away local changes when switching
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// Branch or commit to switch to, optionally followed by paths
+        args: Vec<String>,
+        /// Paths to restore, given after `--`
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
     /// Record changes to the repository
     ///
-    Commit,
+    Commit {
+        /// Use the given message; several are joined as separate paragraphs
+        #[arg(short, long)]
+        message: Vec<String>,
+        /// Take the commit message from the given file, `-` for stdin
+        #[arg(short = 'F', long, conflicts_with = "message")]
+        file: Option<PathBuf>,
+        /// Replace the tip of the current branch with a new commit
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        amend: bool,
+        /// Allow a commit that records the same tree as its parent
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        allow_empty: bool,
+    },
+    /// Verify the connectivity and validity of the objects in the database
+    ///
+    Fsck {
+        /// Only check that reachable objects are present
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        connectivity_only: bool,
+        /// Show all unreachable objects, not only dangling ones
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        unreachable: bool,
+        /// Write dangling objects into .git/lost-found
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        lost_found: bool,
+    },
+    /// Cleanup unnecessary files and optimize the local repository
+    ///
+    Gc {
+        /// Only run when there are too many loose objects or packs
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        auto: bool,
+        /// Spend more time looking for deltas
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        aggressive: bool,
+        /// Prune loose objects older than the date, two weeks ago by default
+        #[arg(long, num_args = 0..=1, require_equals = true, value_name = "DATE")]
+        prune: Option<Option<String>>,
+        /// Keep all unreachable loose objects
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "prune")]
+        no_prune: bool,
+        /// Do not report anything
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        quiet: bool,
+    },
     /// Compute object ID and optionally create an object from a file
     ///
     HashObject {
@@ -54,31 +166,392 @@ pub enum Commands {
         /// Read object from <file>
         path: PathBuf,
     },
+    /// Build a pack index file for an existing packed archive
+    ///
+    IndexPack {
+        /// Read the pack from standard input and store it in the repository
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        stdin: bool,
+        /// Complete a thin pack with delta bases from the object store
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        fix_thin: bool,
+        /// Write the index to this file
+        #[arg(short = 'o')]
+        index_file: Option<String>,
+        /// The pack to index, or where to store the one read from stdin
+        pack_file: Option<String>,
+    },
     /// Display history of a given commit
     ///
     Log {
-        /// Commit to start at
-        #[arg(default_value = "HEAD")]
-        commit: String,
+        /// Pretty-print in the given format: oneline, short, medium, full,
+        /// fuller, format:<template> or tformat:<template>
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "medium")]
+        pretty: Option<LogFormat>,
+        /// Like --pretty; a bare template acts as tformat:, and `dot` draws
+        /// a Graphviz digraph
+        #[arg(long, require_equals = true, conflicts_with = "pretty")]
+        format: Option<LogFormat>,
+        /// Shorthand for --pretty=oneline --abbrev-commit
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        oneline: bool,
+        /// Show abbreviated commit ids
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        abbrev_commit: bool,
+        /// Show commits oldest first
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        reverse: bool,
+        /// Draw a text-based graph of the history
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        graph: bool,
+        /// Show the refs pointing at each commit: short, full, auto or no
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "short")]
+        decorate: Option<LogDecorate>,
+        /// Do not show the refs pointing at commits
+        #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "decorate")]
+        no_decorate: bool,
+        #[command(flatten)]
+        rev: RevArgs,
     },
     /// Show information about files in the index and the working tree
     ///
-    LsFiles,
+    LsFiles {
+        /// Show cached files in the output (the default)
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        cached: bool,
+        /// Show files with an unstaged deletion
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        deleted: bool,
+        /// Show files with an unstaged modification
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        modified: bool,
+        /// Show other (i.e. untracked) files in the output
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        others: bool,
+        /// Add the standard git exclusions
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        exclude_standard: bool,
+        /// Show only unmerged files, in the --stage format
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        unmerged: bool,
+        /// Show staged contents' mode bits, object name and stage number
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        stage: bool,
+        /// Terminate entries with NUL instead of newline
+        #[arg(short = 'z', action = clap::ArgAction::SetTrue)]
+        null_terminated: bool,
+        /// Files to show
+        pathspecs: Vec<String>,
+    },
+    /// List the contents of a tree object
+    ///
+    LsTree {
+        /// Recurse into sub-trees
+        #[arg(short, action = clap::ArgAction::SetTrue)]
+        recursive: bool,
+        /// Show tree entries even when going to recurse them
+        #[arg(short = 't', action = clap::ArgAction::SetTrue)]
+        show_trees: bool,
+        /// List only filenames, one per line
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        name_only: bool,
+        /// Terminate entries with NUL instead of newline
+        #[arg(short = 'z', action = clap::ArgAction::SetTrue)]
+        null_terminated: bool,
+        /// A tree-ish object
+        tree: String,
+    },
+    /// Create a packed archive of objects
+    ///
+    PackObjects {
+        /// Write the pack to standard output
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        stdout: bool,
+        /// Number of objects each object is tried against as a delta base
+        #[arg(long, default_value_t = 10)]
+        window: usize,
+        /// Maximum length of a delta chain
+        #[arg(long, default_value_t = 50)]
+        depth: usize,
+        /// Do not show progress, gitr never does
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        quiet: bool,
+        /// Write `<base-name>-<checksum>.pack` and its `.idx`
+        base_name: Option<String>,
+    },
+    /// Pack heads and tags for efficient repository access
+    ///
+    PackRefs {
+        /// Pack all refs, not only tags and refs already packed
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        all: bool,
+        /// Keep the loose files of the packed refs
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        no_prune: bool,
+    },
+    /// Prune all unreachable objects from the object database
+    ///
+    Prune {
+        /// Only report what would be removed
+        #[arg(short = 'n', long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+        /// Report all removed objects
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+        /// Only expire loose objects older than the date
+        #[arg(long)]
+        expire: Option<String>,
+    },
+    /// Pack unpacked objects in a repository
+    ///
+    Repack {
+        /// Pack everything into a single pack
+        #[arg(short = 'a', action = clap::ArgAction::SetTrue)]
+        all: bool,
+        /// Like -a, but unreachable objects of the old packs are made loose
+        #[arg(short = 'A', action = clap::ArgAction::SetTrue)]
+        all_loosen: bool,
+        /// Remove redundant packs and loose objects
+        #[arg(short = 'd', action = clap::ArgAction::SetTrue)]
+        delete: bool,
+        /// Number of objects each object is tried against as a delta base
+        #[arg(long, default_value_t = 10)]
+        window: usize,
+        /// Maximum length of a delta chain
+        #[arg(long, default_value_t = 50)]
+        depth: usize,
+        /// Do not report anything
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        quiet: bool,
+    },
+    /// List commit objects in reverse chronological order
+    ///
+    RevList {
+        /// Print the number of commits instead of listing them
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        count: bool,
+        /// Mark which side of a symmetric difference each commit is on
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        left_right: bool,
+        /// Also list the objects reachable from the listed commits
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        objects: bool,
+        /// Print the parents of each commit
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        parents: bool,
+        #[command(flatten)]
+        rev: RevArgs,
+    },
     /// Pick out and massage parameters
     ///
-    RevParse,
+    RevParse {
+        /// Revisions and options, handled in order: --verify, -q/--quiet,
+        /// --short[=N], --show-toplevel, --git-dir and --is-inside-work-tree
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        args: Vec<String>,
+    },
     /// Remove files from the working tree and from the index
     ///
-    Rm,
+    Rm {
+        /// Only remove from the index, keeping the working tree files
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        cached: bool,
+        /// Allow recursive removal when a leading directory name is given
+        #[arg(short = 'r', action = clap::ArgAction::SetTrue)]
+        recursive: bool,
+        /// Override the up-to-date check
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+        /// Don't actually remove any file, just show what would be done
+        #[arg(short = 'n', long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+        /// Suppress the "rm <file>" line for each removed file
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        quiet: bool,
+        /// Files to remove
+        pathspecs: Vec<String>,
+    },
     /// List references in a local repository
     ///
-    ShowRef,
+    ShowRef {
+        /// Show the HEAD reference, even if it would normally be filtered out
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        head: bool,
+        /// Limit to local branches
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        heads: bool,
+        /// Limit to tags
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        tags: bool,
+        /// Dereference tags into object IDs as well
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        dereference: bool,
+        /// Only show the object ID, optionally abbreviated to the given length
+        #[arg(short = 's', long, num_args = 0..=1, require_equals = true)]
+        hash: Option<Option<usize>>,
+        /// Enable stricter reference checking by requiring an exact ref path
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        verify: bool,
+        /// Do not print any results to stdout
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        quiet: bool,
+        /// Show references matching these patterns
+        patterns: Vec<String>,
+    },
     /// Show the working tree status
     ///
-    Status,
+    Status {
+        /// Give the output in the short format
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        short: bool,
+        /// Show the branch and tracking info in the short formats
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        branch: bool,
+        /// Give the output in a stable, machine readable format
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "v1", value_parser = ["v1", "v2", "1", "2"])]
+        porcelain: Option<String>,
+        /// Terminate entries with NUL instead of newline
+        #[arg(short = 'z', action = clap::ArgAction::SetTrue)]
+        null_terminated: bool,
+        /// Show untracked files: no, normal or all
+        #[arg(short, long, num_args = 0..=1, default_value = "normal", default_missing_value = "all", value_parser = ["no", "normal", "all"])]
+        untracked_files: String,
+    },
     /// Create, list, delete or verify a tag object signed with GPG
     ///
-    Tag,
+    Tag {
+        /// Make an unsigned, annotated tag object
+        #[arg(short, action = clap::ArgAction::SetTrue)]
+        annotate: bool,
+        /// Use the given tag message (implies -a)
+        #[arg(short)]
+        message: Option<String>,
+        /// Delete the tag
+        #[arg(short, action = clap::ArgAction::SetTrue, conflicts_with_all = ["annotate", "message", "object"])]
+        delete: bool,
+        /// The name of the tag to create or delete
+        name: Option<String>,
+        /// The object the new tag will refer to
+        object: Option<String>,
+    },
+    /// Validate packed git archive files
+    ///
+    VerifyPack {
+        /// List each object with its type, sizes, offset and delta base
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        verbose: bool,
+        /// Only show the histogram of delta chain lengths
+        #[arg(short, long, action = clap::ArgAction::SetTrue)]
+        stat_only: bool,
+        /// The `.idx` files of the packs to check
+        packs: Vec<String>,
+    },
+}
+
+/// Revision and history limiting arguments shared by the commands that
+/// walk the history
+#[derive(clap::Args, Debug)]
+pub struct RevArgs {
+    /// Limit the number of commits to output
+    #[arg(short = 'n', long)]
+    max_count: Option<usize>,
+    /// Follow only the first parent of merge commits
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    first_parent: bool,
+    /// Start from all refs and HEAD as well
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    all: bool,
+    /// Show no parents before all of their children, lines of history
+    /// together
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    topo_order: bool,
+    /// Show no parents before all of their children, otherwise by commit
+    /// date
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    date_order: bool,
+    /// Show no parents before all of their children, otherwise by author
+    /// date
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    author_date_order: bool,
+    /// Only show commits that descend from the excluded ones
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    ancestry_path: bool,
+    /// Show commits more recent than a date
+    #[arg(long, visible_alias = "after")]
+    since: Option<String>,
+    /// Show commits older than a date
+    #[arg(long, visible_alias = "before")]
+    until: Option<String>,
+    /// Only show commits by an author matching the pattern
+    #[arg(long)]
+    author: Vec<String>,
+    /// Only show commits with a message matching the pattern
+    #[arg(long)]
+    grep: Vec<String>,
+    /// Only show merge commits
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    merges: bool,
+    /// Do not show merge commits
+    #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "merges")]
+    no_merges: bool,
+    /// Exclude the commits reachable from these, or include them if they
+    /// start with `^`
+    #[arg(long = "not", num_args = 1.., value_name = "REV")]
+    not: Vec<String>,
+    /// Commits to include, `^<rev>` to exclude, or ranges like `A..B` and
+    /// `A...B`. `HEAD` by default.
+    revs: Vec<String>,
+    /// Only show commits touching these paths
+    #[arg(last = true)]
+    paths: Vec<String>,
+}
+
+impl RevArgs {
+    /// The revisions in the form `RevWalk::push_revs` takes them
+    fn revs(&self) -> Vec<String> {
+        let mut revs = self.revs.clone();
+        if !self.not.is_empty() {
+            revs.push("--not".to_string());
+            revs.extend(self.not.iter().cloned());
+        }
+        revs
+    }
+
+    fn options(&self) -> RevOptions {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as i64;
+        let date = |date: &String| match date_approx(date, now) {
+            Some(secs) => secs,
+            None => {
+                eprintln!("fatal: invalid date '{date}'");
+                process::exit(128);
+            }
+        };
+        let sort = match (self.topo_order, self.date_order, self.author_date_order) {
+            (true, _, _) => RevSort::Topo,
+            (_, true, _) => RevSort::Date,
+            (_, _, true) => RevSort::AuthorDate,
+            _ => RevSort::Default,
+        };
+        RevOptions {
+            all: self.all,
+            first_parent: self.first_parent,
+            sort,
+            ancestry_path: self.ancestry_path,
+            since: self.since.as_ref().map(date),
+            until: self.until.as_ref().map(date),
+            authors: self.author.clone(),
+            greps: self.grep.clone(),
+            merges: match (self.merges, self.no_merges) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            max_count: self.max_count,
+        }
+    }
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -89,17 +562,19 @@ pub enum ObjectType {
     Tree,
 }
 
-impl ObjectType {
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
             ObjectType::Blob => "blob",
             ObjectType::Commit => "commit",
             ObjectType::Tag => "tag",
             ObjectType::Tree => "tree",
-        }
-        .to_string()
+        };
+        write!(f, "{s}")
     }
+}
 
+impl ObjectType {
     pub fn as_bytes(&self) -> Vec<u8> {
         self.to_string().into_bytes()
     }
@@ -115,14 +590,22 @@ impl ObjectType {
     }
 }
 
+/// Parse the value of `--object-format`
+fn parse_object_format(name: &str) -> Result<ObjectFormat, String> {
+    ObjectFormat::from_name(name).ok_or_else(|| format!("unknown hash algorithm '{name}'"))
+}
+
 impl Commands {
     pub fn execute(&self) {
         match self {
-            Commands::Init { path } => {
+            Commands::Init {
+                object_format,
+                path,
+            } => {
                 let repo = if path.is_none() {
-                    cmd_repo_create(".")
+                    cmd_repo_create(".", *object_format)
                 } else {
-                    cmd_repo_create(path.as_ref().unwrap().to_str().unwrap())
+                    cmd_repo_create(path.as_ref().unwrap().to_str().unwrap(), *object_format)
                 };
 
                 println!(
@@ -130,16 +613,397 @@ impl Commands {
                     repo.worktree.canonicalize().unwrap().display()
                 );
             }
+            Commands::Add {
+                all,
+                update,
+                dry_run,
+                intent_to_add,
+                force,
+                pathspecs,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = AddOptions {
+                    all: *all,
+                    update: *update,
+                    dry_run: *dry_run,
+                    intent_to_add: *intent_to_add,
+                    force: *force,
+                };
+
+                cmd_add(&repo, pathspecs, &opts);
+            }
             Commands::CatFile { type_, object } => {
                 let repo = repo_find(".", true).expect("Not a git repository");
 
                 cmd_cat_file(repo, object, Some(type_.clone()));
             }
+            Commands::CheckIgnore {
+                verbose,
+                no_index,
+                stdin,
+                null_terminated,
+                paths,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = CheckIgnoreOptions {
+                    verbose: *verbose,
+                    no_index: *no_index,
+                    stdin: *stdin,
+                    null_terminated: *null_terminated,
+                };
+
+                cmd_check_ignore(&repo, paths, &opts);
+            }
+            Commands::Checkout {
+                new_branch,
+                detach,
+                force,
+                args,
+                paths,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = CheckoutOptions {
+                    new_branch: new_branch.clone(),
+                    detach: *detach,
+                    force: *force,
+                };
+
+                cmd_checkout(&repo, args, paths, &opts);
+            }
+            Commands::Commit {
+                message,
+                file,
+                amend,
+                allow_empty,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = CommitOptions {
+                    message: message.clone(),
+                    file: file.clone(),
+                    amend: *amend,
+                    allow_empty: *allow_empty,
+                };
+
+                cmd_commit(&repo, &opts);
+            }
+            Commands::Fsck {
+                connectivity_only,
+                unreachable,
+                lost_found,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = FsckOptions {
+                    connectivity_only: *connectivity_only,
+                    unreachable: *unreachable,
+                    lost_found: *lost_found,
+                };
+
+                cmd_fsck(&repo, &opts);
+            }
+            Commands::Gc {
+                auto,
+                aggressive,
+                prune,
+                no_prune,
+                quiet,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let prune = match no_prune {
+                    true => Some("never".to_string()),
+                    false => prune.clone().flatten(),
+                };
+                let opts = GcOptions {
+                    auto: *auto,
+                    aggressive: *aggressive,
+                    prune,
+                    quiet: *quiet,
+                };
+
+                cmd_gc(&repo, &opts);
+            }
             Commands::HashObject { type_, write, path } => {
                 cmd_hash_object(type_, *write, path);
             }
-            Commands::Log { commit } => cmd_log(commit.clone()),
-            _ => unimplemented!(),
+            Commands::IndexPack {
+                stdin,
+                fix_thin,
+                index_file,
+                pack_file,
+            } => {
+                let repo = repo_find(".", false);
+                let opts = IndexPackOptions {
+                    stdin: *stdin,
+                    fix_thin: *fix_thin,
+                    index_file: index_file.clone(),
+                };
+
+                cmd_index_pack(repo.as_ref(), pack_file.as_deref(), &opts);
+            }
+            Commands::Log {
+                pretty,
+                format,
+                oneline,
+                abbrev_commit,
+                reverse,
+                graph,
+                decorate,
+                no_decorate,
+                rev,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let mut opts = LogOptions {
+                    abbrev_commit: *abbrev_commit,
+                    reverse: *reverse,
+                    graph: *graph,
+                    decorate: decorate.unwrap_or_default(),
+                    rev: rev.options(),
+                    ..Default::default()
+                };
+                if *no_decorate {
+                    opts.decorate = LogDecorate::No;
+                }
+                if *oneline {
+                    opts.format = LogFormat::Oneline;
+                    opts.abbrev_commit = true;
+                }
+                if let Some(format) = pretty.as_ref().or(format.as_ref()) {
+                    opts.format = format.clone();
+                }
+
+                cmd_log(&repo, &rev.revs(), &rev.paths, &opts);
+            }
+            Commands::LsFiles {
+                cached,
+                deleted,
+                modified,
+                others,
+                exclude_standard,
+                unmerged,
+                stage,
+                null_terminated,
+                pathspecs,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = LsFilesOptions {
+                    cached: *cached,
+                    deleted: *deleted,
+                    modified: *modified,
+                    others: *others,
+                    exclude_standard: *exclude_standard,
+                    unmerged: *unmerged,
+                    stage: *stage,
+                };
+
+                cmd_ls_files(&repo, pathspecs, &opts, *null_terminated);
+            }
+            Commands::LsTree {
+                recursive,
+                show_trees,
+                name_only,
+                null_terminated,
+                tree,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+
+                let opts = LsTreeOptions {
+                    recursive: *recursive,
+                    show_trees: *show_trees,
+                    name_only: *name_only,
+                    null_terminated: *null_terminated,
+                };
+
+                cmd_ls_tree(&repo, tree, &opts);
+            }
+            Commands::PackObjects {
+                stdout,
+                window,
+                depth,
+                quiet: _,
+                base_name,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = PackObjectsOptions {
+                    stdout: *stdout,
+                    window: *window,
+                    depth: *depth,
+                };
+
+                cmd_pack_objects(&repo, base_name.as_deref(), &opts);
+            }
+            Commands::PackRefs { all, no_prune } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                cmd_pack_refs(&repo, *all, !no_prune);
+            }
+            Commands::Prune {
+                dry_run,
+                verbose,
+                expire,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_secs() as i64;
+                let expire = expire.as_ref().map(|date| match date_expiry(date, now) {
+                    Some(secs) => secs,
+                    None => {
+                        eprintln!("fatal: malformed expiration date '{date}'");
+                        process::exit(128);
+                    }
+                });
+                let opts = PruneOptions {
+                    dry_run: *dry_run,
+                    verbose: *verbose,
+                    expire,
+                };
+
+                cmd_prune(&repo, &opts);
+            }
+            Commands::Repack {
+                all,
+                all_loosen,
+                delete,
+                window,
+                depth,
+                quiet,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = RepackOptions {
+                    all: *all || *all_loosen,
+                    unpack_unreachable: all_loosen.then_some(i64::MIN),
+                    delete: *delete,
+                    window: *window,
+                    depth: *depth,
+                    quiet: *quiet,
+                };
+
+                cmd_repack(&repo, &opts);
+            }
+            Commands::RevList {
+                count,
+                left_right,
+                objects,
+                parents,
+                rev,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = RevListOptions {
+                    count: *count,
+                    left_right: *left_right,
+                    objects: *objects,
+                    parents: *parents,
+                    rev: rev.options(),
+                };
+
+                cmd_rev_list(&repo, &rev.revs(), &rev.paths, &opts);
+            }
+            Commands::RevParse { args } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+
+                cmd_rev_parse(&repo, args);
+            }
+            Commands::Rm {
+                cached,
+                recursive,
+                force,
+                dry_run,
+                quiet,
+                pathspecs,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = RmOptions {
+                    cached: *cached,
+                    recursive: *recursive,
+                    force: *force,
+                    dry_run: *dry_run,
+                    quiet: *quiet,
+                };
+
+                cmd_rm(&repo, pathspecs, &opts);
+            }
+            Commands::ShowRef {
+                head,
+                heads,
+                tags,
+                dereference,
+                hash,
+                verify,
+                quiet,
+                patterns,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = ShowRefOptions {
+                    head: *head,
+                    heads: *heads,
+                    tags: *tags,
+                    dereference: *dereference,
+                    // Without a length the ids are shown in full
+                    hash: hash.map(|len| len.unwrap_or(repo.object_format.hex_len())),
+                    verify: *verify,
+                    quiet: *quiet,
+                };
+
+                cmd_show_ref(&repo, patterns, &opts);
+            }
+            Commands::Status {
+                short,
+                branch,
+                porcelain,
+                null_terminated,
+                untracked_files,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+                let opts = StatusOptions {
+                    short: *short,
+                    branch: *branch,
+                    porcelain: porcelain.as_ref().map(|v| match v.as_str() {
+                        "v2" | "2" => 2,
+                        _ => 1,
+                    }),
+                    null_terminated: *null_terminated,
+                    untracked: match untracked_files.as_str() {
+                        "no" => UntrackedMode::No,
+                        "all" => UntrackedMode::All,
+                        _ => UntrackedMode::Normal,
+                    },
+                };
+
+                cmd_status(&repo, &opts);
+            }
+            Commands::Tag {
+                annotate,
+                message,
+                delete,
+                name,
+                object,
+            } => {
+                let repo = repo_find(".", true).expect("Not a git repository");
+
+                match name {
+                    None => cmd_tag_list(&repo),
+                    Some(name) if *delete => cmd_tag_delete(&repo, name),
+                    Some(name) => cmd_tag_create(
+                        &repo,
+                        name,
+                        object.as_deref().unwrap_or("HEAD"),
+                        *annotate || message.is_some(),
+                        message.as_deref(),
+                    ),
+                }
+            }
+            Commands::VerifyPack {
+                verbose,
+                stat_only,
+                packs,
+            } => {
+                let opts = VerifyPackOptions {
+                    verbose: *verbose,
+                    stat_only: *stat_only,
+                };
+
+                let repo = repo_find(".", false);
+                cmd_verify_pack(repo.as_ref(), packs, &opts);
+            }
         }
     }
 }
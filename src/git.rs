@@ -1,4 +1,15 @@
 pub mod config;
+pub mod fsck;
+pub mod graph;
+pub mod hash;
 pub mod helpers;
+pub mod ident;
+pub mod ignore;
+pub mod index;
 pub mod objects;
+pub mod pack;
+pub mod refs;
 pub mod repo;
+pub mod revision;
+pub mod status;
+pub mod worktree;
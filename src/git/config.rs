@@ -1,13 +1,306 @@
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
 
 /// Represents a Git Config file located in the .git directory
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct GitConfig {
     pub core: Core,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+    /// Every other section, keyed by its raw header such as `branch "master"`
+    #[serde(flatten)]
+    pub sections: BTreeMap<String, BTreeMap<String, String>>,
+    /// Every value in file order as `section[.subsection].key`, section and
+    /// key lowercased, keeping the keys that are given more than once
+    #[serde(skip)]
+    pub values: Vec<(String, String)>,
+}
+
+impl GitConfig {
+    /// Build the config from the values of `config_parse`, the last one
+    /// given for a key taking effect
+    pub fn from_values(values: Vec<(String, String)>) -> Self {
+        let mut config = GitConfig {
+            values,
+            ..Default::default()
+        };
+        let get = |name: &str| config.get(name);
+        let core = Core {
+            repositoryformatversion: get("core.repositoryformatversion"),
+            filemode: get("core.filemode"),
+            bare: get("core.bare"),
+            logallrefupdates: get("core.logallrefupdates"),
+            symlinks: get("core.symlinks"),
+            ignorecase: get("core.ignorecase"),
+            excludesfile: get("core.excludesfile"),
+        };
+        let (name, email) = (get("user.name"), get("user.email"));
+        config.core = core;
+        if name.is_some() || email.is_some() {
+            config.user = Some(User { name, email });
+        }
+
+        for (name, value) in &config.values {
+            let (section, key) = name.rsplit_once('.').unwrap();
+            let header = match section.split_once('.') {
+                Some((section, subsection)) => format!("{section} \"{subsection}\""),
+                None if section == "core" || section == "user" => continue,
+                None => section.to_string(),
+            };
+            config
+                .sections
+                .entry(header)
+                .or_default()
+                .insert(key.to_string(), value.clone());
+        }
+        config
+    }
+
+    /// Look up a value by its dotted name, e.g. `branch.master.remote`.
+    /// The last value wins when the key is given more than once.
+    ///
+    /// Section and key names are case-insensitive, subsection names are not.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.get_all(name).pop()
+    }
+
+    /// Every value of a key that may be given more than once, such as
+    /// `remote.origin.fetch`, in order
+    pub fn get_all(&self, name: &str) -> Vec<String> {
+        let name = config_name(name);
+        self.values
+            .iter()
+            .filter(|(n, _)| *n == name)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// Add a value after the ones already given for its key
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.values.push((config_name(name), value.to_string()));
+    }
+}
+
+/// `name` with its section and key lowercased
+fn config_name(name: &str) -> String {
+    let Some((section, key)) = name.rsplit_once('.') else {
+        return name.to_lowercase();
+    };
+    let section = match section.split_once('.') {
+        Some((section, subsection)) => format!("{}.{subsection}", section.to_lowercase()),
+        None => section.to_lowercase(),
+    };
+    format!("{section}.{}", key.to_lowercase())
+}
+
+/// Parse a config file into its values in order, named like
+/// `GitConfig::values`. A key without `=` is a boolean `true`.
+pub fn config_parse(text: &str) -> Result<Vec<(String, String)>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut parser = ConfigParser {
+        chars: &chars,
+        pos: 0,
+        line: 1,
+    };
+    let mut section: Option<String> = None;
+    let mut ret = Vec::new();
+    while let Some(c) = parser.skip_whitespace() {
+        match c {
+            '#' | ';' => parser.skip_line(),
+            '[' => section = Some(parser.section()?),
+            c if c.is_ascii_alphabetic() => {
+                let section = section.as_ref().ok_or_else(|| parser.error())?;
+                let key = parser.name(|c| c == '-');
+                parser.skip_blanks();
+                let value = match parser.peek() {
+                    None | Some('\n' | '#' | ';') => "true".to_string(),
+                    Some('=') => {
+                        parser.pos += 1;
+                        parser.value()?
+                    }
+                    Some(_) => return Err(parser.error()),
+                };
+                ret.push((format!("{section}.{}", key.to_lowercase()), value));
+            }
+            _ => return Err(parser.error()),
+        }
+    }
+    Ok(ret)
+}
+
+/// Files of the global layer, read before the repository's own config:
+/// `$XDG_CONFIG_HOME/git/config`, or `~/.config/git/config` without it,
+/// then `~/.gitconfig`
+pub fn config_global_paths() -> Vec<PathBuf> {
+    config_global_paths_from(|name| env::var_os(name))
+}
+
+/// Like `config_global_paths`, looking variables up with `var`
+fn config_global_paths_from(var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    let home = var("HOME").filter(|h| !h.is_empty()).map(PathBuf::from);
+    let xdg = var("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    let mut ret: Vec<PathBuf> = xdg.into_iter().map(|x| x.join("git/config")).collect();
+    ret.extend(home.map(|h| h.join(".gitconfig")));
+    ret
+}
+
+/// The values of a config file, or `None` when it can't be read. A file
+/// that doesn't parse is fatal, as with git.
+pub fn config_read(path: &Path) -> Option<Vec<(String, String)>> {
+    let text = fs::read_to_string(path).ok()?;
+    let values = config_parse(&text).unwrap_or_else(|e| {
+        eprintln!("fatal: {e} in file {}", path.display());
+        process::exit(128);
+    });
+    Some(values)
+}
+
+struct ConfigParser<'a> {
+    chars: &'a [char],
+    pos: usize,
+    line: usize,
+}
+
+impl ConfigParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self) -> String {
+        format!("bad config line {}", self.line)
+    }
+
+    /// Skip whitespace and newlines, returning the next character
+    fn skip_whitespace(&mut self) -> Option<char> {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            if c == '\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+        self.peek()
+    }
+
+    fn skip_blanks(&mut self) {
+        while self.peek().is_some_and(|c| c == ' ' || c == '\t') {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.pos += 1;
+        }
+    }
+
+    /// Alphanumerics and the characters `extra` allows
+    fn name(&mut self, extra: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || extra(c))
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// `[section]`, `[section "subsection"]` or the older
+    /// `[section.subsection]`, which is lowercased
+    fn section(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let name = self.name(|c| c == '-' || c == '.').to_lowercase();
+        if name.is_empty() {
+            return Err(self.error());
+        }
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(name);
+        }
+        self.skip_blanks();
+        if self.peek() != Some('"') || name.contains('.') {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        let mut subsection = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error()),
+                Some('"') => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    subsection.extend(self.peek().filter(|c| *c != '\n'));
+                }
+                Some(c) => subsection.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        if self.peek() != Some(']') {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        Ok(format!("{name}.{subsection}"))
+    }
+
+    /// A value after `=`: double quotes keep whitespace and comment
+    /// characters, `\` escapes and continues lines, and unquoted
+    /// whitespace at either end is dropped and becomes spaces within
+    fn value(&mut self) -> Result<String, String> {
+        self.skip_blanks();
+        let mut value = String::new();
+        // Length of the value up to its last character that is kept
+        let mut keep = 0;
+        let mut quoted = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' if quoted => return Err(self.error()),
+                '\n' => break,
+                '#' | ';' if !quoted => {
+                    self.skip_line();
+                    break;
+                }
+                '"' => quoted = !quoted,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('\n') => self.line += 1,
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('b') => value.push('\u{8}'),
+                        Some(c @ ('"' | '\\')) => value.push(c),
+                        _ => return Err(self.error()),
+                    }
+                    keep = value.len();
+                }
+                c if quoted => {
+                    value.push(c);
+                    keep = value.len();
+                }
+                c if c.is_whitespace() => value.push(' '),
+                c => {
+                    value.push(c);
+                    keep = value.len();
+                }
+            }
+            self.pos += 1;
+        }
+        if quoted {
+            return Err(self.error());
+        }
+        value.truncate(keep);
+        Ok(value)
+    }
 }
 
 /// Represents the core section of the Git Config file
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Core {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub repositoryformatversion: Option<String>,
@@ -21,6 +314,17 @@ pub struct Core {
     pub symlinks: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ignorecase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excludesfile: Option<String>,
+}
+
+/// Represents the user section of the Git Config file
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
 }
 
 impl Default for GitConfig {
@@ -28,12 +332,109 @@ impl Default for GitConfig {
         GitConfig {
             core: Core {
                 repositoryformatversion: Some("0".to_string()),
-                filemode: Some("false".to_string()),
+                filemode: Some("true".to_string()),
                 bare: Some("false".to_string()),
-                logallrefupdates: None,
+                logallrefupdates: Some("true".to_string()),
                 symlinks: None,
                 ignorecase: None,
+                excludesfile: None,
             },
+            user: None,
+            sections: BTreeMap::new(),
+            values: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parse() {
+        let text = "[core]\n\trepositoryformatversion = 0\n\tbare = false\n\
+            [remote \"origin\"]\n\turl = /tmp/up\n\
+            \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+            \tfetch = +refs/tags/*:refs/tags/*\n\
+            # a second core section adds to the first\n\
+            [Core]\n\tfileMode = true ; trailing comment\n\
+            [branch.Topic]\n\tremote\n\
+            [alias]\n\tlg = \"log  --oneline\" \\\n  -n\\t5 # more\n";
+        let config = GitConfig::from_values(config_parse(text).unwrap());
+        assert_eq!(config.core.bare.as_deref(), Some("false"));
+        assert_eq!(config.core.filemode.as_deref(), Some("true"));
+        assert_eq!(config.get("core.fileMode").as_deref(), Some("true"));
+        assert_eq!(
+            config.get_all("remote.origin.fetch"),
+            vec![
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*"
+            ]
+        );
+        // The last value of a key given more than once wins
+        assert_eq!(
+            config.get("remote.origin.fetch").as_deref(),
+            Some("+refs/tags/*:refs/tags/*")
+        );
+        // The older dotted header lowercases its subsection
+        assert_eq!(config.get("branch.topic.remote").as_deref(), Some("true"));
+        assert_eq!(
+            config.get("Alias.LG").as_deref(),
+            Some("log  --oneline   -n\t5")
+        );
+        assert_eq!(config.get("remote.Origin.url"), None);
+
+        assert_eq!(
+            config_parse("[core]\n\tbare = \"false\n").unwrap_err(),
+            "bad config line 2"
+        );
+        assert_eq!(
+            config_parse("bare = false\n").unwrap_err(),
+            "bad config line 1"
+        );
+    }
+
+    #[test]
+    fn test_config_global() {
+        let paths = |pairs: &'static [(&str, &str)]| {
+            config_global_paths_from(|name| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.into())
+            })
+        };
+        assert_eq!(
+            paths(&[("HOME", "/home/me")]),
+            vec![
+                PathBuf::from("/home/me/.config/git/config"),
+                PathBuf::from("/home/me/.gitconfig")
+            ]
+        );
+        assert_eq!(
+            paths(&[("HOME", "/home/me"), ("XDG_CONFIG_HOME", "/xdg")]),
+            vec![
+                PathBuf::from("/xdg/git/config"),
+                PathBuf::from("/home/me/.gitconfig")
+            ]
+        );
+        assert_eq!(paths(&[]), Vec::<PathBuf>::new());
+
+        // The repository's values win over the global ones
+        let dir = env::temp_dir().join(format!("gitr-test-{}-config-global", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".gitconfig"),
+            "[user]\n\tname = Global\n\temail = global@example.com\n",
+        )
+        .unwrap();
+        fs::write(dir.join("config"), "[user]\n\tname = Local\n").unwrap();
+        assert_eq!(config_read(&dir.join("missing")), None);
+        let mut values = config_read(&dir.join(".gitconfig")).unwrap();
+        values.extend(config_read(&dir.join("config")).unwrap());
+        let user = GitConfig::from_values(values).user.unwrap();
+        assert_eq!(user.name.as_deref(), Some("Local"));
+        assert_eq!(user.email.as_deref(), Some("global@example.com"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,565 @@
+use crate::git::hash::ObjectFormat;
+use crate::git::objects::tree::{tree_parse, GitTreeLeaf};
+use crate::git::refs::ref_check_format;
+use std::collections::HashSet;
+use std::fmt;
+
+/// How serious a problem found by `fsck` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckLevel {
+    Error,
+    Warning,
+}
+
+/// A problem with the contents of an object, named with git's message ids
+/// such as `treeNotSorted`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckMessage {
+    pub level: FsckLevel,
+    pub id: &'static str,
+    pub text: String,
+}
+
+impl FsckMessage {
+    fn error(id: &'static str, text: &str) -> Self {
+        FsckMessage {
+            level: FsckLevel::Error,
+            id,
+            text: text.to_string(),
+        }
+    }
+
+    fn warning(id: &'static str, text: &str) -> Self {
+        FsckMessage {
+            level: FsckLevel::Warning,
+            id,
+            text: text.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FsckMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.id, self.text)
+    }
+}
+
+/// The object types, as names that live long enough to be kept around
+const OBJECT_TYPES: [&str; 4] = ["blob", "tree", "commit", "tag"];
+
+/// Check the contents of an object of type `fmt`. Unlike the object
+/// parsers this never panics, whatever the data.
+pub fn fsck_object(fmt: &[u8], data: &[u8], format: ObjectFormat) -> Vec<FsckMessage> {
+    match fmt {
+        b"tree" => fsck_tree(data, format),
+        b"commit" => fsck_commit(data, format).err().into_iter().collect(),
+        b"tag" => fsck_tag(data, format),
+        _ => Vec::new(),
+    }
+}
+
+/// The objects an object points to, with the type they should have, as far
+/// as they can be made out: tree entries other than submodules, the tree
+/// and parents of a commit, and the target of a tag
+pub fn fsck_links(fmt: &[u8], data: &[u8], format: ObjectFormat) -> Vec<(String, &'static str)> {
+    let mut ret = Vec::new();
+    match fmt {
+        b"tree" => {
+            for leaf in tree_parse(data, format).unwrap_or_default() {
+                match leaf.mode.trim_start_matches('0') {
+                    "40000" => ret.push((leaf.sha, "tree")),
+                    "160000" => {}
+                    _ => ret.push((leaf.sha, "blob")),
+                }
+            }
+        }
+        b"commit" | b"tag" => {
+            let mut target = None;
+            for (key, value) in header_lines(data) {
+                match (fmt, key) {
+                    (b"commit", "tree") => ret.push((value.to_string(), "tree")),
+                    (b"commit", "parent") => ret.push((value.to_string(), "commit")),
+                    (b"tag", "object") => target = Some(value.to_string()),
+                    (b"tag", "type") => {
+                        if let (Some(sha), Some(kind)) =
+                            (target.take(), OBJECT_TYPES.iter().find(|t| **t == value))
+                        {
+                            ret.push((sha, kind));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    ret.retain(|(sha, _)| is_hex_sha(sha, format));
+    ret
+}
+
+/// Whether `s` is a full lowercase hex object id
+fn is_hex_sha(s: &str, format: ObjectFormat) -> bool {
+    s.len() == format.hex_len() && s.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The `key value` lines of a commit or tag header, leaving out
+/// continuation lines
+fn header_lines(data: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    let end = find(data, b"\n\n").unwrap_or(data.len());
+    data[..end]
+        .split(|&c| c == b'\n')
+        .filter(|line| !line.starts_with(b" "))
+        .filter_map(|line| std::str::from_utf8(line).ok())
+        .filter_map(|line| line.split_once(' '))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn fsck_tree(data: &[u8], format: ObjectFormat) -> Vec<FsckMessage> {
+    let entries = match tree_parse(data, format) {
+        Ok(entries) => entries,
+        Err(e) => return vec![FsckMessage::error("badTree", &e)],
+    };
+
+    let mut found = Vec::new();
+    let mut names = HashSet::new();
+    let mut prev: Option<Vec<u8>> = None;
+    for GitTreeLeaf {
+        mode,
+        path: name,
+        sha,
+    } in &entries
+    {
+        let is_dir = mode.trim_start_matches('0') == "40000";
+        if sha.bytes().all(|c| c == b'0') {
+            found.push("nullSha1");
+        }
+        if name.contains(&b'/') {
+            found.push("fullPathname");
+        }
+        if name == b"." {
+            found.push("hasDot");
+        }
+        if name == b".." {
+            found.push("hasDotdot");
+        }
+        if name.eq_ignore_ascii_case(b".git") {
+            found.push("hasDotgit");
+        }
+        if mode.starts_with('0') {
+            found.push("zeroPaddedFilemode");
+        } else if !matches!(
+            mode.as_str(),
+            "100644" | "100755" | "100664" | "120000" | "40000" | "160000"
+        ) {
+            found.push("badFilemode");
+        }
+
+        // Directories sort as if their name ended with a slash
+        let mut key = name.clone();
+        if is_dir {
+            key.push(b'/');
+        }
+        // A file and a directory of the same name need not be adjacent
+        if !names.insert(name) {
+            found.push("duplicateEntries");
+        }
+        if prev.as_ref().is_some_and(|prev| *prev > key) {
+            found.push("treeNotSorted");
+        }
+        prev = Some(key);
+    }
+
+    let messages = [
+        ("nullSha1", "contains entries pointing to null sha1", false),
+        ("fullPathname", "contains full pathnames", false),
+        ("hasDot", "contains '.'", false),
+        ("hasDotdot", "contains '..'", false),
+        ("hasDotgit", "contains '.git'", false),
+        (
+            "zeroPaddedFilemode",
+            "contains zero-padded file modes",
+            false,
+        ),
+        ("badFilemode", "contains bad file modes", false),
+        ("duplicateEntries", "contains duplicate file entries", true),
+        ("treeNotSorted", "not properly sorted", true),
+    ];
+    messages
+        .into_iter()
+        .filter(|(id, _, _)| found.contains(id))
+        .map(|(id, text, error)| match error {
+            true => FsckMessage::error(id, text),
+            false => FsckMessage::warning(id, text),
+        })
+        .collect()
+}
+
+/// Check that the header of a commit or tag ends, and has no NUL in it
+fn fsck_headers(data: &[u8]) -> Result<(), FsckMessage> {
+    for (i, &c) in data.iter().enumerate() {
+        if c == 0 {
+            return Err(FsckMessage::error(
+                "nulInHeader",
+                &format!("unterminated header: NUL at offset {i}"),
+            ));
+        }
+        if c == b'\n' && data.get(i + 1) == Some(&b'\n') {
+            return Ok(());
+        }
+    }
+    match data.last() {
+        Some(b'\n') => Ok(()),
+        _ => Err(FsckMessage::error(
+            "unterminatedHeader",
+            "unterminated header",
+        )),
+    }
+}
+
+/// Take a `key <value>\n` line off the front of `data`, returning the value
+fn header_take<'a>(data: &mut &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let rest = data.strip_prefix(key.as_bytes())?.strip_prefix(b" ")?;
+    let eol = rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+    *data = rest.get(eol + 1..).unwrap_or_default();
+    Some(&rest[..eol])
+}
+
+fn is_hex_value(value: &[u8], format: ObjectFormat) -> bool {
+    std::str::from_utf8(value).is_ok_and(|s| is_hex_sha(s, format))
+}
+
+/// Like git, stop at the first error
+fn fsck_commit(data: &[u8], format: ObjectFormat) -> Result<(), FsckMessage> {
+    fsck_headers(data)?;
+    let mut rest = data;
+
+    match header_take(&mut rest, "tree") {
+        None => Err(FsckMessage::error(
+            "missingTree",
+            "invalid format - expected 'tree' line",
+        ))?,
+        Some(sha) if !is_hex_value(sha, format) => Err(FsckMessage::error(
+            "badTreeSha1",
+            "invalid 'tree' line format - bad sha1",
+        ))?,
+        _ => {}
+    }
+    while let Some(sha) = header_take(&mut rest, "parent") {
+        if !is_hex_value(sha, format) {
+            Err(FsckMessage::error(
+                "badParentSha1",
+                "invalid 'parent' line format - bad sha1",
+            ))?;
+        }
+    }
+    let mut authors = 0;
+    while let Some(ident) = header_take(&mut rest, "author") {
+        authors += 1;
+        fsck_ident(ident)?;
+    }
+    match authors {
+        0 => Err(FsckMessage::error(
+            "missingAuthor",
+            "invalid format - expected 'author' line",
+        ))?,
+        1 => {}
+        _ => Err(FsckMessage::error(
+            "multipleAuthors",
+            "invalid format - multiple 'author' lines",
+        ))?,
+    }
+    match header_take(&mut rest, "committer") {
+        Some(ident) => fsck_ident(ident),
+        None => Err(FsckMessage::error(
+            "missingCommitter",
+            "invalid format - expected 'committer' line",
+        )),
+    }
+}
+
+fn fsck_tag(data: &[u8], format: ObjectFormat) -> Vec<FsckMessage> {
+    let mut ret = Vec::new();
+    if let Err(e) = fsck_tag_header(data, &mut ret, format) {
+        ret.push(e);
+    }
+    ret
+}
+
+/// Check the header of a tag, stopping at the first error. Problems that
+/// are only worth a warning are added to `warnings`.
+fn fsck_tag_header(
+    data: &[u8],
+    warnings: &mut Vec<FsckMessage>,
+    format: ObjectFormat,
+) -> Result<(), FsckMessage> {
+    fsck_headers(data)?;
+    let mut rest = data;
+
+    match header_take(&mut rest, "object") {
+        None => Err(FsckMessage::error(
+            "missingObject",
+            "invalid format - expected 'object' line",
+        ))?,
+        Some(sha) if !is_hex_value(sha, format) => Err(FsckMessage::error(
+            "badObjectSha1",
+            "invalid 'object' line format - bad sha1",
+        ))?,
+        _ => {}
+    }
+    match header_take(&mut rest, "type") {
+        None => Err(FsckMessage::error(
+            "missingTypeEntry",
+            "invalid format - expected 'type' line",
+        ))?,
+        Some(kind) if !OBJECT_TYPES.iter().any(|t| t.as_bytes() == kind) => {
+            Err(FsckMessage::error("badType", "invalid 'type' value"))?
+        }
+        _ => {}
+    }
+    match header_take(&mut rest, "tag") {
+        None => Err(FsckMessage::error(
+            "missingTagEntry",
+            "invalid format - expected 'tag' line",
+        ))?,
+        Some(name) => {
+            let name = String::from_utf8_lossy(name);
+            if !ref_check_format(&format!("refs/tags/{name}")) {
+                warnings.push(FsckMessage::warning(
+                    "badTagName",
+                    &format!("invalid 'tag' name: {name}"),
+                ));
+            }
+        }
+    }
+    match header_take(&mut rest, "tagger") {
+        Some(ident) => fsck_ident(ident),
+        None => {
+            warnings.push(FsckMessage::warning(
+                "missingTaggerEntry",
+                "invalid format - expected 'tagger' line",
+            ));
+            Ok(())
+        }
+    }
+}
+
+/// Check an `author`, `committer` or `tagger` value:
+/// `Name <email> <seconds> <+hhmm>`
+fn fsck_ident(ident: &[u8]) -> Result<(), FsckMessage> {
+    let bad = |id, what| {
+        Err(FsckMessage::error(
+            id,
+            &format!("invalid author/committer line - {what}"),
+        ))
+    };
+    let at = |i: usize| ident.get(i).copied().unwrap_or(b'\n');
+    let skip_to = |mut i: usize| {
+        while !matches!(at(i), b'<' | b'>' | b'\n') {
+            i += 1;
+        }
+        i
+    };
+
+    if at(0) == b'<' {
+        return bad("missingNameBeforeEmail", "missing space before email");
+    }
+    let mut p = skip_to(0);
+    if at(p) == b'>' {
+        return bad("badName", "bad name");
+    }
+    if at(p) != b'<' {
+        return bad("missingEmail", "missing email");
+    }
+    if at(p - 1) != b' ' {
+        return bad("missingSpaceBeforeEmail", "missing space before email");
+    }
+    p = skip_to(p + 1);
+    if at(p) != b'>' {
+        return bad("badEmail", "bad email");
+    }
+    p += 1;
+    if at(p) != b' ' {
+        return bad("missingSpaceBeforeDate", "missing space before date");
+    }
+    p += 1;
+    if at(p) == b'0' && at(p + 1) != b' ' {
+        return bad("zeroPaddedDate", "zero-padded date");
+    }
+    let start = p.min(ident.len());
+    let digits = ident[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let date = std::str::from_utf8(&ident[start..start + digits]).unwrap_or_default();
+    if digits > 0 && date.parse::<i64>().is_err() {
+        return bad("badDateOverflow", "date causes integer overflow");
+    }
+    p += digits;
+    if digits == 0 || at(p) != b' ' {
+        return bad("badDate", "bad date");
+    }
+    p += 1;
+    if !matches!(at(p), b'+' | b'-')
+        || !(1..5).all(|i| at(p + i).is_ascii_digit())
+        || p + 5 != ident.len()
+    {
+        return bad("badTimezone", "bad time zone");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ObjectFormat::{Sha1, Sha256};
+
+    fn ids(messages: Vec<FsckMessage>) -> Vec<&'static str> {
+        messages.into_iter().map(|m| m.id).collect()
+    }
+
+    fn tree(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (mode, name) in entries {
+            data.extend(format!("{mode} {name}\0").as_bytes());
+            data.extend([0x11; 20]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_fsck_tree() {
+        let good = tree(&[("100644", "a"), ("40000", "a-b"), ("40000", "a.c")]);
+        assert_eq!(fsck_object(b"tree", &good, Sha1), vec![]);
+        assert_eq!(
+            fsck_links(b"tree", &good, Sha1)[1],
+            ("11".repeat(20), "tree")
+        );
+
+        let bad = tree(&[
+            ("100644", "b"),
+            ("100644", "a"),
+            ("040000", "a"),
+            ("100600", ".GIT"),
+        ]);
+        assert_eq!(
+            ids(fsck_object(b"tree", &bad, Sha1)),
+            vec![
+                "hasDotgit",
+                "zeroPaddedFilemode",
+                "badFilemode",
+                "duplicateEntries",
+                "treeNotSorted"
+            ]
+        );
+        assert_eq!(ids(fsck_object(b"tree", &bad[..30], Sha1)), vec!["badTree"]);
+        assert_eq!(
+            ids(fsck_object(b"tree", b"10x644 a\0", Sha1)),
+            vec!["badTree"]
+        );
+
+        let mut wide = b"40000 a\0".to_vec();
+        wide.extend([0x11; 32]);
+        assert_eq!(fsck_object(b"tree", &wide, Sha256), vec![]);
+        assert_eq!(
+            fsck_links(b"tree", &wide, Sha256),
+            vec![("11".repeat(32), "tree")]
+        );
+        assert_eq!(ids(fsck_object(b"tree", &wide, Sha1)), vec!["badTree"]);
+    }
+
+    #[test]
+    fn test_fsck_commit() {
+        let tree = "11".repeat(20);
+        let commit = |author: &str| {
+            format!("tree {tree}\nauthor {author}\ncommitter A <a@b> 1 +0000\n\nmsg\n")
+        };
+        assert_eq!(
+            fsck_object(b"commit", commit("A U <a@b> 0 -0130").as_bytes(), Sha1),
+            vec![]
+        );
+        for (author, id) in [
+            ("nobody", "missingEmail"),
+            ("<a@b> 1 +0000", "missingNameBeforeEmail"),
+            ("A<a@b> 1 +0000", "missingSpaceBeforeEmail"),
+            ("A <a@b 1 +0000", "badEmail"),
+            ("A <a@b>1 +0000", "missingSpaceBeforeDate"),
+            ("A <a@b> 01 +0000", "zeroPaddedDate"),
+            ("A <a@b> x +0000", "badDate"),
+            ("A <a@b> 99999999999999999999 +0000", "badDateOverflow"),
+            ("A <a@b> 1 0000", "badTimezone"),
+            ("A <a@b> 1 +00000", "badTimezone"),
+        ] {
+            assert_eq!(
+                ids(fsck_object(b"commit", commit(author).as_bytes(), Sha1)),
+                vec![id]
+            );
+        }
+
+        let no_tree = b"author A <a@b> 1 +0000\n\nmsg\n";
+        assert_eq!(
+            ids(fsck_object(b"commit", no_tree, Sha1)),
+            vec!["missingTree"]
+        );
+        let no_committer = format!("tree {tree}\nparent {tree}\nauthor A <a@b> 1 +0000\n\nx");
+        assert_eq!(
+            ids(fsck_object(b"commit", no_committer.as_bytes(), Sha1)),
+            vec!["missingCommitter"]
+        );
+        assert_eq!(
+            fsck_links(b"commit", no_committer.as_bytes(), Sha1),
+            vec![(tree.clone(), "tree"), (tree.clone(), "commit")]
+        );
+        let bad_parent = format!("tree {tree}\nparent 1234\n\n");
+        assert_eq!(
+            ids(fsck_object(b"commit", bad_parent.as_bytes(), Sha1)),
+            vec!["badParentSha1"]
+        );
+        assert_eq!(
+            ids(fsck_object(b"commit", b"tree \0", Sha1)),
+            vec!["nulInHeader"]
+        );
+        assert_eq!(
+            ids(fsck_object(
+                b"commit",
+                commit("A <a@b> 1 +0000").as_bytes(),
+                Sha256
+            )),
+            vec!["badTreeSha1"]
+        );
+    }
+
+    #[test]
+    fn test_fsck_tag() {
+        let sha = "11".repeat(20);
+        let tag = |rest: &str| format!("object {sha}\ntype commit\n{rest}\nmsg\n");
+        assert_eq!(
+            fsck_object(
+                b"tag",
+                tag("tag v1\ntagger A <a@b> 1 +0000\n").as_bytes(),
+                Sha1
+            ),
+            vec![]
+        );
+        assert_eq!(
+            ids(fsck_object(b"tag", tag("tag v..1\n").as_bytes(), Sha1)),
+            vec!["badTagName", "missingTaggerEntry"]
+        );
+        assert_eq!(
+            ids(fsck_object(b"tag", tag("").as_bytes(), Sha1)),
+            vec!["missingTagEntry"]
+        );
+        assert_eq!(
+            fsck_links(b"tag", tag("").as_bytes(), Sha1),
+            vec![(sha.clone(), "commit")]
+        );
+        let bad_type = format!("object {sha}\ntype bogus\ntag v1\n\n");
+        assert_eq!(
+            ids(fsck_object(b"tag", bad_type.as_bytes(), Sha1)),
+            vec!["badType"]
+        );
+        assert!(fsck_links(b"tag", bad_type.as_bytes(), Sha1).is_empty());
+    }
+}
@@ -0,0 +1,594 @@
+//! ASCII history graph, as drawn by `git log --graph`.
+//!
+//! This follows the state machine of git's `graph.c` closely so that the
+//! output matches git's line for line: every commit is drawn as a `*` in
+//! one of several columns, and the lines in between join or split the
+//! columns with `|`, `/`, `\` and `_`.
+
+/// What the next line of graph output is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphState {
+    /// Lines that leave every column unchanged
+    Padding,
+    /// An ellipsis, when a commit's lines were not all shown
+    Skip,
+    /// Lines making room for an octopus merge
+    PreCommit,
+    /// The line with the commit itself
+    Commit,
+    /// The line after a merge that splits off its parents
+    PostMerge,
+    /// Lines moving columns left until each is in place
+    Collapsing,
+}
+
+/// Lays out commits, youngest first, into graph lines
+#[derive(Debug)]
+pub struct GitGraph {
+    /// The commit being drawn and its parents that are shown
+    commit: String,
+    parents: Vec<String>,
+    /// Width of the graph for this commit, for padding
+    width: usize,
+    /// Current row while expanding for an octopus merge
+    expansion_row: usize,
+    state: GraphState,
+    prev_state: GraphState,
+    /// Column of the current and previous commit
+    commit_index: usize,
+    prev_commit_index: usize,
+    /// Whether the first parent of a merge is to the left (0) or below
+    /// (1) the merge, -1 before it is known
+    merge_layout: i32,
+    /// Columns a merge adds to the right of the graph
+    edges_added: i32,
+    prev_edges_added: i32,
+    /// Commits expected in each column before and after this commit
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    /// For each character of the line, the new column it leads to
+    mapping: Vec<i32>,
+    old_mapping: Vec<i32>,
+}
+
+impl Default for GitGraph {
+    fn default() -> Self {
+        GitGraph {
+            commit: String::new(),
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: GraphState::Padding,
+            prev_state: GraphState::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+        }
+    }
+}
+
+impl GitGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move on to the next commit, given the parents that will be shown
+    pub fn update(&mut self, commit: &str, parents: Vec<String>) {
+        self.commit = commit.to_string();
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // If the previous commit was not drawn to the end, show an ellipsis
+        self.state = if self.state != GraphState::Padding {
+            GraphState::Skip
+        } else if self.needs_pre_commit_line() {
+            GraphState::PreCommit
+        } else {
+            GraphState::Commit
+        };
+    }
+
+    /// Whether all lines for the current commit have been output
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == GraphState::Padding
+    }
+
+    /// The next line of graph output, and whether it was the commit line
+    pub fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let commit_line = match self.state {
+            GraphState::Padding => {
+                self.padding(&mut line);
+                false
+            }
+            GraphState::Skip => {
+                self.skip(&mut line);
+                false
+            }
+            GraphState::PreCommit => {
+                self.pre_commit(&mut line);
+                false
+            }
+            GraphState::Commit => {
+                self.commit_line(&mut line);
+                true
+            }
+            GraphState::PostMerge => {
+                self.post_merge(&mut line);
+                false
+            }
+            GraphState::Collapsing => {
+                self.collapsing(&mut line);
+                false
+            }
+        };
+        self.pad(&mut line);
+        (line, commit_line)
+    }
+
+    /// A line to put between commits, that leaves the columns unchanged
+    pub fn padding_line(&mut self) -> String {
+        if self.state != GraphState::Commit {
+            return self.next_line().0;
+        }
+
+        let mut line = String::new();
+        for col in &self.columns {
+            line.push('|');
+            if *col == self.commit && self.parents.len() > 2 {
+                line.push_str(&" ".repeat((self.parents.len() - 2) * 2));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = GraphState::Padding;
+        line
+    }
+
+    /// The lines leading up to the commit, each ending with a newline, and
+    /// then the commit line itself without one
+    pub fn show_commit(&mut self) -> String {
+        let mut ret = String::new();
+        if self.is_commit_finished() {
+            return self.padding_line();
+        }
+        while !self.is_commit_finished() {
+            let (line, commit_line) = self.next_line();
+            ret.push_str(&line);
+            if commit_line {
+                break;
+            }
+            ret.push('\n');
+        }
+        ret
+    }
+
+    /// The remaining lines for the commit, separated by newlines
+    pub fn show_remainder(&mut self) -> String {
+        let mut ret = String::new();
+        while !self.is_commit_finished() {
+            ret.push_str(&self.next_line().0);
+            if !self.is_commit_finished() {
+                ret.push('\n');
+            }
+        }
+        ret
+    }
+
+    /// Output the message of the current commit next to the graph: each
+    /// line after the first gets a graph line in front of it, and the
+    /// commit's remaining lines follow the message
+    pub fn show_message(&mut self, message: &str) -> String {
+        let mut ret = String::new();
+        let mut rest = message;
+        while let Some(i) = rest.find('\n') {
+            ret.push_str(&rest[..=i]);
+            rest = &rest[i + 1..];
+            if !rest.is_empty() {
+                ret.push_str(&self.next_line().0);
+            }
+        }
+        ret.push_str(rest);
+
+        if !self.is_commit_finished() {
+            let terminated = message.ends_with('\n');
+            if !terminated {
+                ret.push('\n');
+            }
+            ret.push_str(&self.show_remainder());
+            if terminated {
+                ret.push('\n');
+            }
+        }
+        ret
+    }
+
+    fn update_state(&mut self, state: GraphState) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|c| c == commit)
+    }
+
+    /// Lay out the columns after the current commit, and how the columns
+    /// before it map onto them
+    fn update_columns(&mut self) {
+        self.columns = std::mem::take(&mut self.new_columns);
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping = vec![-1; 2 * max_new_columns];
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let mut seen_this = false;
+        let num_columns = self.columns.len();
+        for i in 0..=num_columns {
+            let col_commit = if i == num_columns {
+                if seen_this {
+                    break;
+                }
+                self.commit.clone()
+            } else {
+                self.columns[i].clone()
+            };
+
+            if col_commit == self.commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                // The commit always takes up at least two characters
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&col_commit, None);
+            }
+        }
+
+        while self.mapping.len() > 1 && *self.mapping.last().unwrap() < 0 {
+            self.mapping.pop();
+        }
+    }
+
+    fn insert_into_new_columns(&mut self, commit: &str, idx: Option<usize>) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit.to_string());
+                self.new_columns.len() - 1
+            }
+        };
+
+        let mapping_idx;
+        if let (true, Some(idx), -1) = (self.parents.len() > 1, idx, self.merge_layout) {
+            // The first parent of a merge decides whether the merge line
+            // goes to the left or straight down
+            let dist = idx as i32 - i as i32;
+            let shift = if dist > 1 { 2 * dist - 3 } else { 1 };
+            self.merge_layout = if dist > 0 { 0 } else { 1 };
+            self.edges_added = self.parents.len() as i32 + self.merge_layout - 2;
+            mapping_idx = (self.width as i32 + (self.merge_layout - 1) * shift) as usize;
+            self.width += 2 * self.merge_layout as usize;
+        } else if self.edges_added > 0
+            && self.width >= 2
+            && self.mapping[self.width - 2] == i as i32
+        {
+            // A parent found in the last existing column joins it at once
+            mapping_idx = self.width - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_idx = self.width;
+            self.width += 2;
+        }
+        self.mapping[mapping_idx] = i as i32;
+    }
+
+    /// Number of parents of an octopus merge drawn with dashes
+    fn dashed_parents(&self) -> usize {
+        (self.parents.len() as i32 + self.merge_layout - 3).max(0) as usize
+    }
+
+    fn expansion_rows(&self) -> usize {
+        self.dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && self.expansion_row < self.expansion_rows()
+    }
+
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target as usize == i / 2)
+    }
+
+    fn pad(&self, line: &mut String) {
+        let width = line.chars().count();
+        if width < self.width {
+            line.push_str(&" ".repeat(self.width - width));
+        }
+    }
+
+    fn padding(&self, line: &mut String) {
+        for _ in &self.new_columns {
+            line.push_str("| ");
+        }
+    }
+
+    fn skip(&mut self, line: &mut String) {
+        line.push_str("...");
+        if self.needs_pre_commit_line() {
+            self.update_state(GraphState::PreCommit);
+        } else {
+            self.update_state(GraphState::Commit);
+        }
+    }
+
+    fn pre_commit(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        for (i, col) in self.columns.iter().enumerate() {
+            if *col == self.commit {
+                seen_this = true;
+                line.push('|');
+                line.push_str(&" ".repeat(self.expansion_row));
+            } else if seen_this && self.expansion_row == 0 {
+                // Lines right of a merge drawn as `\` keep going that way
+                if self.prev_state == GraphState::PostMerge && self.prev_commit_index < i {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(GraphState::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        let num_columns = self.columns.len();
+        for i in 0..=num_columns {
+            let is_commit = match i == num_columns {
+                true if seen_this => break,
+                true => true,
+                false => self.columns[i] == self.commit,
+            };
+
+            if is_commit {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    self.octopus(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                // Keep a `\` from the previous merge's line going
+                if self.prev_state == GraphState::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if self.prev_state == GraphState::Collapsing
+                && self.old_mapping.get(2 * i + 1) == Some(&(i as i32))
+                && *self.mapping.get(2 * i).unwrap_or(&-1) < i as i32
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.update_state(GraphState::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(GraphState::Padding);
+        } else {
+            self.update_state(GraphState::Collapsing);
+        }
+    }
+
+    /// The dashes after an octopus merge's `*`, one pair per extra parent
+    fn octopus(&self, line: &mut String) {
+        let dashed = self.dashed_parents();
+        for i in 0..dashed {
+            line.push('-');
+            line.push(if i == dashed - 1 { '.' } else { '-' });
+        }
+    }
+
+    fn post_merge(&mut self, line: &mut String) {
+        const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+        let mut seen_this = false;
+        // Whether a column left of the merge holds its first parent
+        let mut parent_col = false;
+        let num_columns = self.columns.len();
+        for i in 0..=num_columns {
+            let col_commit = match i == num_columns {
+                true if seen_this => break,
+                true => &self.commit,
+                false => &self.columns[i],
+            };
+
+            if *col_commit == self.commit {
+                seen_this = true;
+                let mut idx = self.merge_layout.max(0) as usize;
+                for j in 0..self.parents.len() {
+                    line.push(MERGE_CHARS[idx]);
+                    if idx == 2 {
+                        if self.edges_added > 0 || j + 1 < self.parents.len() {
+                            line.push(' ');
+                        }
+                    } else {
+                        idx += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(if parent_col { '_' } else { ' ' });
+                }
+            }
+            if *col_commit == self.parents[0] {
+                parent_col = true;
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(GraphState::Padding);
+        } else {
+            self.update_state(GraphState::Collapsing);
+        }
+    }
+
+    fn collapsing(&mut self, line: &mut String) {
+        let size = self.mapping.len();
+        self.old_mapping = std::mem::replace(&mut self.mapping, vec![-1; size]);
+        let mut horizontal_edge: Option<usize> = None;
+        let mut horizontal_edge_target = -1;
+
+        for i in 0..size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            // Branches only ever move left
+            let t2 = target as usize * 2;
+            if t2 == i {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                // Nothing to the left: move one step left
+                self.mapping[i - 1] = target;
+                if horizontal_edge.is_none() {
+                    horizontal_edge = Some(i);
+                    horizontal_edge_target = target;
+                    let mut j = t2 + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = target;
+                        j += 2;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // Joins the branch line to its left, with the same parent
+            } else {
+                // Cross over the branch line to the left
+                self.mapping[i - 2] = target;
+                if horizontal_edge.is_none() {
+                    horizontal_edge = Some(i - 1);
+                    horizontal_edge_target = target;
+                    let mut j = t2 + 3;
+                    while j + 2 < i {
+                        self.mapping[j] = target;
+                        j += 2;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping = self.mapping.clone();
+        if self.mapping.last().is_some_and(|&m| m < 0) {
+            self.mapping.pop();
+        }
+
+        let mut used_horizontal = false;
+        for i in 0..self.mapping.len() {
+            let target = self.mapping[i];
+            if target < 0 {
+                line.push(' ');
+            } else if target as usize * 2 == i {
+                line.push('|');
+            } else if target == horizontal_edge_target && Some(i + 1) != horizontal_edge {
+                // Only the first segment of the edge continues below
+                if i != target as usize * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push('_');
+            } else {
+                if used_horizontal && horizontal_edge.is_some_and(|h| i < h) {
+                    self.mapping[i] = -1;
+                }
+                line.push('/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(GraphState::Padding);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(commits: &[(&str, &[&str])]) -> Vec<String> {
+        let mut graph = GitGraph::new();
+        let mut out = String::new();
+        for (commit, parents) in commits {
+            graph.update(commit, parents.iter().map(|p| p.to_string()).collect());
+            out.push_str(&graph.show_commit());
+            out.push_str(commit);
+            out.push_str(&graph.show_message(""));
+            out.push('\n');
+        }
+        out.lines().map(|l| l.trim_end().to_string()).collect()
+    }
+
+    #[test]
+    fn test_graph_merge() {
+        let lines = draw(&[("m", &["a", "b"]), ("b", &["r"]), ("a", &["r"]), ("r", &[])]);
+        assert_eq!(lines, vec!["*   m", "|\\", "| * b", "* | a", "|/", "* r"]);
+    }
+
+    #[test]
+    fn test_graph_octopus() {
+        let lines = draw(&[
+            ("m", &["a", "b", "c"]),
+            ("c", &["r"]),
+            ("b", &["r"]),
+            ("a", &["r"]),
+            ("r", &[]),
+        ]);
+        assert_eq!(
+            lines,
+            vec!["*-.   m", "|\\ \\", "| | * c", "| * | b", "| |/", "* / a", "|/", "* r"]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Hash function naming the objects of a repository, as set by
+/// `extensions.objectFormat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Some(ObjectFormat::Sha1),
+            "sha256" => Some(ObjectFormat::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// Length of a binary object id
+    pub fn raw_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    /// Length of a hex object id
+    pub fn hex_len(self) -> usize {
+        self.raw_len() * 2
+    }
+
+    /// The all-zero id, which stands for no object
+    pub fn null_sha(self) -> String {
+        "0".repeat(self.hex_len())
+    }
+
+    /// Whether `sha` is a full hex object id of this format
+    pub fn is_sha(self, sha: &str) -> bool {
+        sha.len() == self.hex_len() && sha.bytes().all(|c| c.is_ascii_hexdigit())
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    pub fn hasher(self) -> ObjectHasher {
+        match self {
+            ObjectFormat::Sha1 => ObjectHasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => ObjectHasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+/// Incremental hashing with the function of an `ObjectFormat`
+#[derive(Clone)]
+pub enum ObjectHasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl ObjectHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ObjectHasher::Sha1(h) => h.update(data),
+            ObjectHasher::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            ObjectHasher::Sha1(h) => h.finalize().to_vec(),
+            ObjectHasher::Sha256(h) => h.finalize().to_vec(),
+        }
+    }
+}
+
+/// Whether `sha` is the null id of any format
+pub fn sha_is_null(sha: &str) -> bool {
+    !sha.is_empty() && sha.bytes().all(|c| c == b'0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_formats() {
+        let hex = |raw: Vec<u8>| crate::git::objects::objects::sha_to_hex(&raw);
+        // The empty blob
+        assert_eq!(
+            hex(ObjectFormat::Sha1.digest(b"blob 0\0")),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        assert_eq!(
+            hex(ObjectFormat::Sha256.digest(b"blob 0\0")),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"
+        );
+        assert_eq!(
+            ObjectFormat::from_name("SHA256"),
+            Some(ObjectFormat::Sha256)
+        );
+        assert_eq!(ObjectFormat::from_name("md5"), None);
+        assert!(ObjectFormat::Sha256.is_sha(&ObjectFormat::Sha256.null_sha()));
+        assert!(!ObjectFormat::Sha1.is_sha(&ObjectFormat::Sha256.null_sha()));
+        assert!(sha_is_null(&ObjectFormat::Sha256.null_sha()));
+    }
+}
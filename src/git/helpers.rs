@@ -1,19 +1,62 @@
 use crate::cli::ObjectType;
-use crate::git::objects::objects::{object_read, object_write};
+use crate::git::objects::objects::{
+    object_loose_list, object_loose_name, object_loose_parse, object_read, object_read_raw,
+    object_sha, object_write, object_write_raw, sha_from_hex, sha_to_hex,
+};
 use crate::git::{
-    objects::{GitBlob, GitCommit, GitObject},
-    repo::{repo_dir, repo_file, repo_find, GitRepository},
+    fsck::{fsck_links, fsck_object, FsckLevel},
+    graph::GitGraph,
+    hash::{sha_is_null, ObjectFormat},
+    ident::{date_expiry, date_format, ident, ident_parse, DateStyle},
+    ignore::{wildmatch_bytes, GitIgnore},
+    index::{index_read, index_write, index_write_tree, GitIndex, GitIndexEntry},
+    objects::{
+        kvlm::Dict,
+        tree::{tree_flatten, tree_path_join, GitTreeLeaf},
+        GitBlob, GitCommit, GitObject, GitTag, GitTree,
+    },
+    pack::{
+        pack_append_objects, pack_find_deltas, pack_list, pack_list_reset, pack_name_hash,
+        pack_prefix_search, pack_resolve, pack_resolve_thin, pack_type_from_name, pack_type_name,
+        pack_unpack, pack_write, GitPack, GitPackIndex, GitPackObject,
+    },
+    refs::{
+        packed_refs_read, packed_refs_write, ref_check_format, ref_delete, ref_delete_loose,
+        ref_dwim, ref_list, ref_resolve, ref_symbolic, ref_symbolic_update, ref_update,
+        ref_update_no_deref, reflog_append, reflog_list, reflog_read, PackedRef,
+    },
+    repo::{repo_dir, repo_file, repo_find, repo_path, GitRepository},
+    revision::{commit_read, rev_parse, rev_relative, RevSort, RevWalk},
+    status::{status_read, GitStatus, StatusEntry, UntrackedMode},
+    worktree::{
+        path_quote, pathspec_any, pathspec_match, worktree_checkout, worktree_compare,
+        worktree_display, worktree_entry, worktree_files, worktree_mode, worktree_path,
+        worktree_prefix, worktree_relpath, worktree_remove, WorktreeState,
+    },
 };
+use regex::Regex;
 use serde_ini;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
 use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Create a new git repository at the given path
-pub fn cmd_repo_create(path: &str) -> GitRepository {
-    let repo = GitRepository::new(path, true);
+/// Create a new git repository at the given path, naming objects with the
+/// given hash function
+pub fn cmd_repo_create(path: &str, object_format: ObjectFormat) -> GitRepository {
+    let mut repo = GitRepository::new(path, true);
+    if object_format != ObjectFormat::Sha1 {
+        repo.object_format = object_format;
+        repo.config.core.repositoryformatversion = Some("1".to_string());
+        repo.config.sections.insert(
+            "extensions".to_string(),
+            [("objectformat".to_string(), object_format.name().to_string())].into(),
+        );
+    }
 
     if repo.worktree.exists() {
         if !repo.worktree.is_dir() {
@@ -22,7 +65,6 @@ pub fn cmd_repo_create(path: &str) -> GitRepository {
         if repo.gitdir.exists()
             && fs::read_dir(&repo.gitdir)
                 .expect("Failed to read .git directory")
-                .into_iter()
                 .next()
                 .is_some()
         {
@@ -43,11 +85,7 @@ pub fn cmd_repo_create(path: &str) -> GitRepository {
     )
     .expect("Failed to write .git/description file");
 
-    fs::write(
-        repo_file(&repo, "HEAD", false).expect("Failed to get .git/HEAD file"),
-        "ref: refs/heads/master\n",
-    )
-    .expect("Failed to write .git/HEAD file");
+    ref_symbolic_update(&repo, "HEAD", "refs/heads/master");
 
     fs::write(
         repo_file(&repo, "config", false).expect("Failed to get .git/config file"),
@@ -59,98 +97,4475 @@ pub fn cmd_repo_create(path: &str) -> GitRepository {
 }
 
 pub fn cmd_cat_file(repo: GitRepository, obj: &str, fmt: Option<ObjectType>) {
-    let obj = object_read(&repo, &object_find(&repo, obj, fmt, true)).unwrap();
-    print!(
-        "{}",
-        std::str::from_utf8(&obj.serialize(Some(repo)))
-            .unwrap()
-            .to_string()
-    );
+    let sha = object_find(&repo, obj, fmt, true)
+        .unwrap_or_else(|| panic!("Not a valid object name {obj}"));
+    let obj = object_read(&repo, &sha).unwrap();
+    io::stdout()
+        .write_all(&obj.serialize(Some(repo)))
+        .expect("Failed to write to stdout");
 }
 
-fn object_find(_: &GitRepository, name: &str, _: Option<ObjectType>, _: bool) -> String {
-    name.to_string()
+/// Options for `ls-tree`
+#[derive(Default)]
+pub struct LsTreeOptions {
+    pub recursive: bool,
+    /// Show trees too when recursing
+    pub show_trees: bool,
+    pub name_only: bool,
+    pub null_terminated: bool,
 }
 
-pub fn cmd_hash_object(type_: &ObjectType, write: bool, path: &Path) {
-    let mut repo: Option<GitRepository> = None;
-    if write {
-        repo = repo_find(".", true);
+pub fn cmd_ls_tree(repo: &GitRepository, name: &str, opts: &LsTreeOptions) {
+    let Some(sha) = object_find(repo, name, None, true) else {
+        eprintln!("fatal: Not a valid object name {name}");
+        process::exit(128);
+    };
+    let Some(sha) = object_peel(repo, &sha, "tree", true) else {
+        eprintln!("fatal: not a tree object");
+        process::exit(128);
+    };
+    let written = ls_tree_write(repo, &mut io::stdout().lock(), &sha, opts);
+    // A closed pipe, e.g. when piping into `head`, is not an error
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            panic!("Failed to write: {e}");
+        }
     }
+}
 
-    let fd = File::open(path).expect("Failed to open file {path}");
-    let sha = object_hash(fd, type_, repo);
-    println!("{sha}")
+fn ls_tree_write(
+    repo: &GitRepository,
+    out: &mut impl Write,
+    sha: &str,
+    opts: &LsTreeOptions,
+) -> io::Result<()> {
+    let term = if opts.null_terminated { '\0' } else { '\n' };
+    let mut written = Ok(());
+    ls_tree(repo, sha, b"", opts.recursive, &mut |leaf, path| {
+        // Like git, trees are hidden while recursing unless asked for
+        if written.is_err() || leaf.is_tree() && opts.recursive && !opts.show_trees {
+            return;
+        }
+        let mut line = Vec::new();
+        if !opts.name_only {
+            let (mode, kind) = (leaf.mode_padded(), leaf.object_type());
+            line.extend(format!("{mode} {kind} {}\t", leaf.sha).into_bytes());
+        }
+        line.extend(path_show(path, term));
+        line.push(term as u8);
+        written = out.write_all(&line);
+    });
+    written
 }
 
-fn object_hash(fd: File, fmt: &ObjectType, repo: Option<GitRepository>) -> String {
-    let mut data: Vec<u8> = Vec::new();
-    let mut reader = BufReader::new(fd);
-    reader.read_to_end(&mut data).expect("Failed to read file");
+/// A path as printed in output terminated by `term`: quoted like git unless
+/// entries are NUL terminated
+fn path_show(path: &[u8], term: char) -> Vec<u8> {
+    match term {
+        '\0' => path.to_vec(),
+        _ => path_quote(path).into_bytes(),
+    }
+}
 
-    let obj: Box<dyn GitObject> = match fmt {
-        ObjectType::Blob => Box::new(GitBlob {
-            fmt: fmt.as_bytes(),
-            blobdata: data,
-        }),
-        _ => panic!("Unknown type {}!", { fmt.to_string() }),
+/// Walk a tree, calling `show` with each leaf and its full path
+fn ls_tree(
+    repo: &GitRepository,
+    sha: &str,
+    prefix: &[u8],
+    recursive: bool,
+    show: &mut dyn FnMut(&GitTreeLeaf, &[u8]),
+) {
+    let obj = object_read(repo, sha).expect("Failed to read tree object");
+    let tree = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .expect("Not a tree object");
+
+    for leaf in &tree.items {
+        let path = tree_path_join(prefix, &leaf.path);
+
+        show(leaf, &path);
+        if recursive && leaf.is_tree() {
+            ls_tree(repo, &leaf.sha, &path, recursive, show);
+        }
+    }
+}
+
+/// Options for `ls-files`
+#[derive(Default)]
+pub struct LsFilesOptions {
+    pub cached: bool,
+    pub deleted: bool,
+    pub modified: bool,
+    pub others: bool,
+    pub exclude_standard: bool,
+    pub unmerged: bool,
+    pub stage: bool,
+}
+
+/// Why a path was listed by `ls-files`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsFilesKind {
+    Cached,
+    Deleted,
+    Modified,
+    Other,
+}
+
+/// A path listed by `ls-files`, with its index entry unless untracked
+#[derive(Debug, Clone)]
+pub struct LsFilesItem {
+    pub kind: LsFilesKind,
+    pub path: Vec<u8>,
+    pub entry: Option<GitIndexEntry>,
+}
+
+/// List files in the index and the worktree, in the order git prints them.
+///
+/// `pathspecs` are relative to the top of the worktree; an empty list
+/// selects every path. Without any of cached, deleted, modified, others,
+/// unmerged or stage, tracked files are listed.
+pub fn ls_files(
+    repo: &GitRepository,
+    pathspecs: &[String],
+    opts: &LsFilesOptions,
+) -> Vec<LsFilesItem> {
+    let index = index_read(repo);
+    let mut ret = Vec::new();
+    let show_cached = opts.cached
+        || opts.stage
+        || opts.unmerged
+        || !(opts.deleted || opts.modified || opts.others);
+
+    if opts.others {
+        let mut ignore = opts.exclude_standard.then(|| GitIgnore::new(repo));
+        let tracked: HashSet<&[u8]> = index.entries.iter().map(|e| e.name.as_slice()).collect();
+        ret.extend(
+            worktree_files(repo, ignore.as_mut())
+                .into_iter()
+                .filter(|path| !tracked.contains(path.as_slice()) && pathspec_any(pathspecs, path))
+                .map(|path| LsFilesItem {
+                    kind: LsFilesKind::Other,
+                    path,
+                    entry: None,
+                }),
+        );
+    }
+
+    for entry in &index.entries {
+        if !pathspec_any(pathspecs, &entry.name) {
+            continue;
+        }
+        let mut push = |kind| {
+            ret.push(LsFilesItem {
+                kind,
+                path: entry.name.clone(),
+                entry: Some(entry.clone()),
+            })
+        };
+
+        if show_cached && (!opts.unmerged || entry.stage > 0) {
+            push(LsFilesKind::Cached);
+        }
+        if entry.skip_worktree || !(opts.deleted || opts.modified) {
+            continue;
+        }
+        let state = match entry.stage {
+            0 => worktree_compare(repo, entry),
+            // Conflicted entries never match the worktree
+            _ if worktree_path(repo, &entry.name).exists() => WorktreeState::Modified,
+            _ => WorktreeState::Deleted,
+        };
+        if opts.deleted && state == WorktreeState::Deleted {
+            push(LsFilesKind::Deleted);
+        }
+        if opts.modified && state != WorktreeState::Unchanged {
+            push(LsFilesKind::Modified);
+        }
+    }
+
+    ret
+}
+
+pub fn cmd_ls_files(
+    repo: &GitRepository,
+    pathspecs: &[String],
+    opts: &LsFilesOptions,
+    null_terminated: bool,
+) {
+    let prefix = worktree_prefix(repo);
+    // Like git, only paths below the current directory are listed
+    let pathspecs: Vec<String> = match pathspecs.is_empty() {
+        true => vec![prefix.clone()],
+        false => pathspecs
+            .iter()
+            .map(|p| worktree_relpath(repo, p))
+            .collect(),
     };
+    let term = if null_terminated { '\0' } else { '\n' };
+
+    let mut out = io::stdout().lock();
+    for item in ls_files(repo, &pathspecs, opts) {
+        let path = worktree_display(&item.path, &prefix);
+        if let Some(e) = &item.entry {
+            if item.kind == LsFilesKind::Cached && (opts.stage || opts.unmerged) {
+                write!(out, "{:06o} {} {}\t", e.mode, e.sha, e.stage)
+                    .expect("Failed to write to stdout");
+            }
+        }
+        out.write_all(&[&path_show(&path, term)[..], &[term as u8]].concat())
+            .expect("Failed to write to stdout");
+    }
+}
 
-    object_write(obj, repo)
+/// Options for `add`
+#[derive(Default)]
+pub struct AddOptions {
+    /// Also stage removals and new files, in the whole tree when no
+    /// pathspec is given
+    pub all: bool,
+    /// Only stage changes to files that are already tracked
+    pub update: bool,
+    pub dry_run: bool,
+    pub intent_to_add: bool,
+    /// Allow adding otherwise ignored files
+    pub force: bool,
 }
 
-pub fn cmd_log(commit: String) {
-    let repo = repo_find(".", true);
+/// A change `add` makes to the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddChange {
+    Add(Vec<u8>),
+    Remove(Vec<u8>),
+}
 
-    print!("digraph gitrlog{{");
-    print!("  node[shape=rect]");
+/// Work out what `add` would stage for the given pathspecs, relative to the
+/// top of the worktree. Also returns the explicitly named paths that were
+/// skipped because they are ignored, and pathspecs that matched nothing.
+pub fn add_changes(
+    repo: &GitRepository,
+    index: &GitIndex,
+    pathspecs: &[String],
+    opts: &AddOptions,
+) -> (Vec<AddChange>, Vec<String>, Vec<String>) {
+    let mut changes = Vec::new();
+    let mut matched = vec![false; pathspecs.len()];
+    let mut mark = |path: &[u8]| {
+        for (i, spec) in pathspecs.iter().enumerate() {
+            matched[i] |= pathspec_match(spec, path);
+        }
+    };
 
-    let repo = repo.unwrap();
-    let mut seen = HashSet::new();
+    let mut tracked: Vec<&GitIndexEntry> = Vec::new();
+    for entry in &index.entries {
+        if tracked.last().map(|e| &e.name) != Some(&entry.name) {
+            tracked.push(entry);
+        }
+    }
+    for entry in &tracked {
+        if !pathspec_any(pathspecs, &entry.name) {
+            continue;
+        }
+        mark(&entry.name);
+        let state = match entry.stage {
+            0 => worktree_compare(repo, entry),
+            _ if worktree_path(repo, &entry.name).exists() => WorktreeState::Modified,
+            _ => WorktreeState::Deleted,
+        };
+        match state {
+            WorktreeState::Unchanged => {}
+            // Intent to add leaves modified entries alone, but not removals
+            WorktreeState::Modified if opts.intent_to_add => {}
+            WorktreeState::Modified => changes.push(AddChange::Add(entry.name.clone())),
+            WorktreeState::Deleted => changes.push(AddChange::Remove(entry.name.clone())),
+        }
+    }
 
-    log_graphviz(&repo, &object_find(&repo, &commit, None, true), &mut seen);
+    if !opts.update {
+        let names: HashSet<&[u8]> = tracked.iter().map(|e| e.name.as_slice()).collect();
+        let mut ignore = (!opts.force).then(|| GitIgnore::new(repo));
+        for path in worktree_files(repo, ignore.as_mut()) {
+            // Nested repositories are left alone
+            if path.ends_with(b"/") || names.contains(path.as_slice()) {
+                continue;
+            }
+            if pathspec_any(pathspecs, &path) {
+                mark(&path);
+                changes.push(AddChange::Add(path));
+            }
+        }
+    }
 
-    print!("}}");
+    let mut ignored = Vec::new();
+    let mut unmatched = Vec::new();
+    for (spec, matched) in pathspecs.iter().zip(matched) {
+        if matched {
+            continue;
+        }
+        match repo.worktree.join(spec).exists() {
+            // Untracked files are simply not considered with update
+            true if opts.update => {}
+            true => ignored.push(spec.clone()),
+            false => unmatched.push(spec.clone()),
+        }
+    }
+    (changes, ignored, unmatched)
 }
 
-fn log_graphviz(repo: &GitRepository, sha: &str, seen: &mut HashSet<String>) {
-    if seen.contains(sha) {
+pub fn cmd_add(repo: &GitRepository, pathspecs: &[String], opts: &AddOptions) {
+    if pathspecs.is_empty() && !(opts.all || opts.update) {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!("hint: Maybe you wanted to say 'git add .'?");
         return;
     }
-    seen.insert(sha.to_string());
+    let pathspecs: Vec<String> = pathspecs
+        .iter()
+        .map(|p| worktree_relpath(repo, p))
+        .collect();
 
-    let commit = object_read(repo, sha);
-    if commit.is_none() {
-        return;
+    let mut index = index_read(repo);
+    let (changes, ignored, unmatched) = add_changes(repo, &index, &pathspecs, opts);
+    if let Some(spec) = unmatched.first() {
+        eprintln!("fatal: pathspec '{spec}' did not match any files");
+        process::exit(128);
     }
-    let commit = commit.unwrap();
 
-    let commit = commit
-        .as_any()
-        .downcast_ref::<GitCommit>()
-        .expect("Not a commit object");
-    let short_hash = &sha[0..8];
-    let message = commit.kvlm.get(&None).unwrap();
-    let message = std::str::from_utf8(&message[0]).unwrap().trim();
-    let message = message.replace("\\", "\\\\").replace("\"", "\\\"");
-    let message = if message.contains("\n") {
-        let index = message.find("\n").unwrap();
-        &message[..index] // Keep only the first line
+    // Changes are collected and applied at once, as updating the sorted
+    // entries one path at a time is quadratic
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut out = io::stdout().lock();
+    for change in &changes {
+        match change {
+            // Like git, paths are printed as they are, without quoting
+            AddChange::Add(path) if opts.dry_run => {
+                out.write_all(&[b"add '", &path[..], b"'\n"].concat())
+                    .expect("Failed to write to stdout");
+            }
+            AddChange::Remove(path) if opts.dry_run => {
+                out.write_all(&[b"remove '", &path[..], b"'\n"].concat())
+                    .expect("Failed to write to stdout");
+            }
+            AddChange::Add(path) if opts.intent_to_add => {
+                // Like git, the entry records the empty blob and no stat data
+                let meta =
+                    fs::symlink_metadata(worktree_path(repo, path)).expect("Failed to stat file");
+                let empty = Box::new(GitBlob {
+                    fmt: b"blob".to_vec(),
+                    blobdata: Vec::new(),
+                });
+                added.push(GitIndexEntry {
+                    mode: worktree_mode(&meta),
+                    sha: object_write(empty, Some(repo.clone())),
+                    intent_to_add: true,
+                    name: path.clone(),
+                    ..Default::default()
+                });
+            }
+            AddChange::Add(path) => {
+                added.push(worktree_entry(repo, path, index.get(path), true));
+            }
+            AddChange::Remove(path) => removed.push(path.as_slice()),
+        }
+    }
+    if !opts.dry_run && !changes.is_empty() {
+        index.remove_all(&removed);
+        index.add_all(added);
+        index_write(repo, &index);
+    }
+
+    if !ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in &ignored {
+            eprintln!("{path}");
+        }
+        eprintln!("hint: Use -f if you really want to add them.");
+        process::exit(1);
+    }
+}
+
+/// Options for `rm`
+#[derive(Default)]
+pub struct RmOptions {
+    /// Only remove from the index, keeping the worktree files
+    pub cached: bool,
+    pub recursive: bool,
+    /// Skip the up-to-date check
+    pub force: bool,
+    pub dry_run: bool,
+    pub quiet: bool,
+}
+
+/// Why `rm` refuses to remove a file without `-f`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RmProblem {
+    /// Staged content differs from both the worktree file and `HEAD`
+    Both,
+    /// Staged content differs from `HEAD`
+    Staged,
+    /// Worktree file differs from the staged content
+    Local,
+}
+
+/// Check that removing `paths` loses no content, the way git does: the
+/// staged content must match `HEAD` and the worktree file (if any) must
+/// match the index. With `cached`, only content in neither place is at risk.
+pub fn rm_check(
+    repo: &GitRepository,
+    index: &GitIndex,
+    paths: &[Vec<u8>],
+    cached: bool,
+) -> Vec<(RmProblem, Vec<u8>)> {
+    let head = object_find(repo, "HEAD", Some(ObjectType::Tree), true)
+        .map(|tree| tree_flatten(repo, &tree))
+        .unwrap_or_default();
+
+    let mut ret = Vec::new();
+    for path in paths {
+        let entry = match index.get(path) {
+            Some(entry) => entry,
+            // Conflicted paths have nothing worth protecting
+            None => continue,
+        };
+        let staged = match head.get(path) {
+            Some(leaf) => leaf.mode_bits() != entry.mode || leaf.sha != entry.sha,
+            None => true,
+        };
+        let local = worktree_compare(repo, entry) == WorktreeState::Modified;
+
+        if local && staged {
+            if !cached || !entry.intent_to_add {
+                ret.push((RmProblem::Both, path.clone()));
+            }
+        } else if !cached && staged {
+            ret.push((RmProblem::Staged, path.clone()));
+        } else if !cached && local {
+            ret.push((RmProblem::Local, path.clone()));
+        }
+    }
+    ret
+}
+
+pub fn cmd_rm(repo: &GitRepository, pathspecs: &[String], opts: &RmOptions) {
+    if pathspecs.is_empty() {
+        eprintln!("fatal: No pathspec was given. Which files should I remove?");
+        process::exit(128);
+    }
+    let mut index = index_read(repo);
+    let mut paths: Vec<Vec<u8>> = Vec::new();
+    for arg in pathspecs {
+        let spec = &worktree_relpath(repo, arg);
+        let mut matched = false;
+        for entry in &index.entries {
+            if !pathspec_match(spec, &entry.name) {
+                continue;
+            }
+            matched = true;
+            if !opts.recursive
+                && entry.name != spec.as_bytes()
+                && !wildmatch_bytes(spec.as_bytes(), &entry.name, false)
+            {
+                eprintln!("fatal: not removing '{}' recursively without -r", arg);
+                process::exit(128);
+            }
+            if paths.last() != Some(&entry.name) {
+                paths.push(entry.name.clone());
+            }
+        }
+        if !matched {
+            eprintln!("fatal: pathspec '{arg}' did not match any files");
+            process::exit(128);
+        }
+    }
+    paths.sort();
+    paths.dedup();
+
+    if !opts.force {
+        let problems = rm_check(repo, &index, &paths, opts.cached);
+        for kind in [RmProblem::Both, RmProblem::Staged, RmProblem::Local] {
+            let files: Vec<&Vec<u8>> = problems
+                .iter()
+                .filter(|(k, _)| *k == kind)
+                .map(|(_, path)| path)
+                .collect();
+            if files.is_empty() {
+                continue;
+            }
+            let what = match kind {
+                RmProblem::Both => "staged content different from both the\nfile and the HEAD",
+                RmProblem::Staged => "changes staged in the index",
+                RmProblem::Local => "local modifications",
+            };
+            match files.len() {
+                1 => eprintln!("error: the following file has {what}:"),
+                _ => eprintln!("error: the following files have {what}:"),
+            }
+            for path in files {
+                let _ = io::stderr().write_all(&[b"    ", &path[..], b"\n"].concat());
+            }
+            match kind {
+                RmProblem::Both => eprintln!("(use -f to force removal)"),
+                _ => eprintln!("(use --cached to keep the file, or -f to force removal)"),
+            }
+        }
+        if !problems.is_empty() {
+            process::exit(1);
+        }
+    }
+
+    let mut out = io::stdout().lock();
+    for path in &paths {
+        if !opts.quiet {
+            out.write_all(&[b"rm '", &path[..], b"'\n"].concat())
+                .expect("Failed to write to stdout");
+        }
+        if opts.dry_run {
+            continue;
+        }
+        if !opts.cached {
+            worktree_remove(repo, path);
+        }
+    }
+    if !opts.dry_run {
+        let paths: Vec<&[u8]> = paths.iter().map(|p| p.as_slice()).collect();
+        index.remove_all(&paths);
+        index_write(repo, &index);
+    }
+}
+
+/// Options for `commit`
+#[derive(Default)]
+pub struct CommitOptions {
+    /// Message paragraphs given with `-m`
+    pub message: Vec<String>,
+    /// File to read the message from, `-` for stdin
+    pub file: Option<PathBuf>,
+    /// Replace the tip of the current branch instead of adding to it
+    pub amend: bool,
+    pub allow_empty: bool,
+}
+
+/// Create a commit object for the current index and return its id.
+///
+/// The author and committer come from `GIT_AUTHOR_*`/`GIT_COMMITTER_*` or
+/// the `user` config section. No refs are updated.
+pub fn commit_create(
+    repo: &GitRepository,
+    tree: &str,
+    parents: &[String],
+    author: &str,
+    message: &str,
+) -> String {
+    let mut kvlm = Dict::new();
+    kvlm.insert(Some(b"tree".to_vec()), vec![tree.as_bytes().to_vec()]);
+    if !parents.is_empty() {
+        let parents = parents.iter().map(|p| p.as_bytes().to_vec()).collect();
+        kvlm.insert(Some(b"parent".to_vec()), parents);
+    }
+    kvlm.insert(Some(b"author".to_vec()), vec![author.as_bytes().to_vec()]);
+    kvlm.insert(
+        Some(b"committer".to_vec()),
+        vec![ident(repo, "COMMITTER").into_bytes()],
+    );
+    kvlm.insert(None, vec![message.as_bytes().to_vec()]);
+
+    let commit = Box::new(GitCommit {
+        fmt: b"commit".to_vec(),
+        kvlm,
+    });
+    object_write(commit, Some(repo.clone()))
+}
+
+pub fn cmd_commit(repo: &GitRepository, opts: &CommitOptions) {
+    let index = index_read(repo);
+    if index.entries.iter().any(|e| e.stage > 0) {
+        eprintln!("error: Committing is not possible because you have unmerged files.");
+        eprintln!("fatal: Exiting because of an unresolved conflict.");
+        process::exit(128);
+    }
+
+    let head = rev_parse(repo, "HEAD");
+    let previous = match (&head, opts.amend) {
+        (None, true) => {
+            eprintln!("fatal: You have nothing to amend.");
+            process::exit(128);
+        }
+        (Some(sha), true) => {
+            let obj = object_read(repo, sha).expect("Failed to read HEAD commit");
+            let commit = obj.as_any().downcast_ref::<GitCommit>().cloned();
+            Some(commit.expect("HEAD is not a commit"))
+        }
+        _ => None,
+    };
+
+    let message = match &opts.file {
+        Some(path) if path.as_os_str() == "-" => {
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .expect("Failed to read message from stdin");
+            Some(buf)
+        }
+        Some(path) => match fs::read_to_string(path) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                eprintln!(
+                    "fatal: could not read log file '{}': {}",
+                    path.display(),
+                    io_error_message(&e)
+                );
+                process::exit(128);
+            }
+        },
+        None if !opts.message.is_empty() => Some(opts.message.join("\n\n")),
+        // Amending without a new message keeps the old one
+        None => previous.as_ref().map(|c| c.message()),
+    };
+    let message = message_cleanup(&message.unwrap_or_default());
+    if message.is_empty() {
+        eprintln!("Aborting commit due to empty commit message.");
+        process::exit(1);
+    }
+
+    let tree = index_write_tree(repo, &index);
+    let parents = match &previous {
+        Some(commit) => commit.parents(),
+        None => head.iter().cloned().collect(),
+    };
+    if !opts.allow_empty && !opts.amend {
+        let parent_tree = parents.first().map(|p| {
+            let obj = object_read(repo, p).expect("Failed to read parent commit");
+            let commit = obj.as_any().downcast_ref::<GitCommit>();
+            commit.expect("Parent is not a commit").tree()
+        });
+        let empty = match &parent_tree {
+            Some(parent_tree) => *parent_tree == tree,
+            None => index.entries.iter().all(|e| e.intent_to_add),
+        };
+        if empty {
+            match parent_tree {
+                Some(_) => println!("nothing to commit, working tree clean"),
+                None => {
+                    println!("nothing to commit (create/copy files and use \"git add\" to track)")
+                }
+            }
+            process::exit(1);
+        }
+    }
+
+    // Amending keeps the original authorship
+    let author = match previous.as_ref().and_then(|c| c.author()) {
+        Some(author) => author,
+        None => ident(repo, "AUTHOR"),
+    };
+    let sha = commit_create(repo, &tree, &parents, &author, &message);
+
+    let subject = message.lines().next().unwrap_or_default();
+    let kind = match (opts.amend, parents.is_empty()) {
+        (true, _) => " (amend)",
+        (false, true) => " (initial)",
+        (false, false) => "",
+    };
+    ref_update(
+        repo,
+        "HEAD",
+        &sha,
+        Some(head.as_deref().unwrap_or(&repo.object_format.null_sha())),
+        Some(&format!("commit{kind}: {subject}")),
+    );
+
+    let branch = match ref_symbolic(repo, "HEAD") {
+        Some(target) => target
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&target)
+            .to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    let root = if parents.is_empty() {
+        " (root-commit)"
     } else {
-        &message
+        ""
+    };
+    println!(
+        "[{branch}{root} {}] {subject}",
+        object_abbrev(repo, &sha, 7)
+    );
+}
+
+/// Options for `status`
+#[derive(Default)]
+pub struct StatusOptions {
+    pub short: bool,
+    /// Show branch and tracking information in the short formats
+    pub branch: bool,
+    /// Porcelain format version, 1 or 2
+    pub porcelain: Option<u8>,
+    pub null_terminated: bool,
+    pub untracked: UntrackedMode,
+}
+
+pub fn cmd_status(repo: &GitRepository, opts: &StatusOptions) {
+    let status = status_read(repo, opts.untracked);
+    let term = if opts.null_terminated { '\0' } else { '\n' };
+
+    match opts.porcelain {
+        Some(2) => status_print_v2(
+            &status,
+            &worktree_prefix(repo),
+            opts.branch,
+            term,
+            repo.object_format,
+        ),
+        Some(_) => status_print_short(&status, "", opts.branch, term),
+        None if opts.short || opts.null_terminated => {
+            status_print_short(&status, &worktree_prefix(repo), opts.branch, term)
+        }
+        None => status_print_long(repo, &status, opts.untracked),
+    }
+}
+
+/// Short name of a ref for display, e.g. `origin/master`
+fn ref_shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/remotes/", "refs/tags/", "refs/"]
+        .iter()
+        .find_map(|p| name.strip_prefix(p))
+        .unwrap_or(name)
+}
+
+fn status_print_short(status: &GitStatus, prefix: &str, branch: bool, term: char) {
+    if branch {
+        let mut line = match (&status.branch, &status.head) {
+            (None, _) => "## HEAD (no branch)".to_string(),
+            (Some(b), None) => format!("## No commits yet on {b}"),
+            (Some(b), Some(_)) => format!("## {b}"),
+        };
+        if let (Some(upstream), Some(_)) = (&status.upstream, &status.head) {
+            line.push_str(&format!("...{}", ref_shorten(upstream)));
+            match status.ahead_behind {
+                None => line.push_str(" [gone]"),
+                Some((0, 0)) => {}
+                Some((a, 0)) => line.push_str(&format!(" [ahead {a}]")),
+                Some((0, b)) => line.push_str(&format!(" [behind {b}]")),
+                Some((a, b)) => line.push_str(&format!(" [ahead {a}, behind {b}]")),
+            }
+        }
+        print!("{line}{term}");
+    }
+
+    let show = |path: &[u8]| path_show(&worktree_display(path, prefix), term);
+    let mut out = io::stdout().lock();
+    for entry in &status.entries {
+        let xy = format!("{}{} ", entry.staged, entry.unstaged);
+        let path = show(&entry.path);
+        let line = match &entry.orig {
+            // With -z the new name comes first, as in porcelain v2
+            Some(orig) if term == '\0' => {
+                [xy.as_bytes(), &path, b"\0", &show(orig), b"\0"].concat()
+            }
+            Some(orig) => [xy.as_bytes(), &show(orig), b" -> ", &path, b"\n"].concat(),
+            None => [xy.as_bytes(), &path, &[term as u8]].concat(),
+        };
+        out.write_all(&line).expect("Failed to write to stdout");
+    }
+    for path in &status.untracked {
+        out.write_all(&[b"?? ", &show(path)[..], &[term as u8]].concat())
+            .expect("Failed to write to stdout");
+    }
+}
+
+fn status_print_v2(
+    status: &GitStatus,
+    prefix: &str,
+    branch: bool,
+    term: char,
+    format: ObjectFormat,
+) {
+    let none = || (0, format.null_sha());
+    if branch {
+        print!(
+            "# branch.oid {}{term}",
+            status.head.as_deref().unwrap_or("(initial)")
+        );
+        print!(
+            "# branch.head {}{term}",
+            status.branch.as_deref().unwrap_or("(detached)")
+        );
+        if let Some(upstream) = &status.upstream {
+            print!("# branch.upstream {}{term}", ref_shorten(upstream));
+            if let Some((ahead, behind)) = status.ahead_behind {
+                print!("# branch.ab +{ahead} -{behind}{term}");
+            }
+        }
+    }
+
+    let dot = |c: char| if c == ' ' { '.' } else { c };
+    let show = |path: &[u8]| path_show(&worktree_display(path, prefix), term);
+    let mut out = io::stdout().lock();
+    // Unlike the short format, conflicts come after ordinary changes
+    let (unmerged, changed): (Vec<&StatusEntry>, Vec<&StatusEntry>) =
+        status.entries.iter().partition(|e| e.is_unmerged());
+    for entry in changed.into_iter().chain(unmerged) {
+        let xy = format!("{}{}", dot(entry.staged), dot(entry.unstaged));
+        let path = show(&entry.path);
+        if let Some(stages) = &entry.stages {
+            let [s1, s2, s3] = stages.clone().map(|s| s.unwrap_or_else(none));
+            let fields = format!(
+                "u {xy} N... {:06o} {:06o} {:06o} {:06o} {} {} {} ",
+                s1.0, s2.0, s3.0, entry.worktree_mode, s1.1, s2.1, s3.1
+            );
+            out.write_all(&[fields.as_bytes(), &path, &[term as u8]].concat())
+                .expect("Failed to write to stdout");
+            continue;
+        }
+
+        let head = entry.head.clone().unwrap_or_else(none);
+        let index = match (entry.staged, entry.unstaged) {
+            // Deleted and intent-to-add entries have nothing staged
+            ('D', _) | (_, 'A') => none(),
+            _ => entry.index.clone().unwrap_or_else(none),
+        };
+        let fields = format!(
+            "{xy} N... {:06o} {:06o} {:06o} {} {}",
+            head.0, index.0, entry.worktree_mode, head.1, index.1
+        );
+        let line = match &entry.orig {
+            Some(orig) => {
+                let sep = if term == '\0' { b'\0' } else { b'\t' };
+                let fields = format!("2 {fields} R100 ");
+                [fields.as_bytes(), &path, &[sep], &show(orig), &[term as u8]].concat()
+            }
+            None => [format!("1 {fields} ").as_bytes(), &path, &[term as u8]].concat(),
+        };
+        out.write_all(&line).expect("Failed to write to stdout");
+    }
+    for path in &status.untracked {
+        out.write_all(&[b"? ", &show(path)[..], &[term as u8]].concat())
+            .expect("Failed to write to stdout");
+    }
+}
+
+fn status_print_long(repo: &GitRepository, status: &GitStatus, untracked: UntrackedMode) {
+    let prefix = worktree_prefix(repo);
+    let show = |path: &[u8]| path_quote(&worktree_display(path, &prefix));
+    let display = |e: &StatusEntry| match &e.orig {
+        Some(orig) => format!("{} -> {}", show(orig), show(&e.path)),
+        None => show(&e.path),
+    };
+
+    match (&status.branch, &status.head) {
+        (Some(branch), _) => println!("On branch {branch}"),
+        (None, Some(head)) => println!("HEAD detached at {}", object_abbrev(repo, head, 7)),
+        (None, None) => println!("Not currently on any branch."),
+    }
+
+    // Every block below ends with a blank line
+    if let (Some(upstream), Some(_)) = (&status.upstream, &status.head) {
+        let up = ref_shorten(upstream);
+        match status.ahead_behind {
+            None => {
+                println!("Your branch is based on '{up}', but the upstream is gone.");
+                println!("  (use \"git branch --unset-upstream\" to fixup)");
+            }
+            Some((0, 0)) => println!("Your branch is up to date with '{up}'."),
+            Some((a, 0)) => {
+                let s = if a == 1 { "" } else { "s" };
+                println!("Your branch is ahead of '{up}' by {a} commit{s}.");
+                println!("  (use \"git push\" to publish your local commits)");
+            }
+            Some((0, b)) => {
+                let s = if b == 1 { "" } else { "s" };
+                println!(
+                    "Your branch is behind '{up}' by {b} commit{s}, and can be fast-forwarded."
+                );
+                println!("  (use \"git pull\" to update your local branch)");
+            }
+            Some((a, b)) => {
+                println!("Your branch and '{up}' have diverged,");
+                println!("and have {a} and {b} different commits each, respectively.");
+                println!("  (use \"git pull\" to merge the remote branch into yours)");
+            }
+        }
+        println!();
+    }
+
+    let unmerged: Vec<&StatusEntry> = status.entries.iter().filter(|e| e.is_unmerged()).collect();
+    let merging = repo_path(repo, "MERGE_HEAD").exists();
+    if merging {
+        if unmerged.is_empty() {
+            println!("All conflicts fixed but you are still merging.");
+            println!("  (use \"git commit\" to conclude merge)");
+        } else {
+            println!("You have unmerged paths.");
+            println!("  (fix conflicts and run \"git commit\")");
+            println!("  (use \"git merge --abort\" to abort the merge)");
+        }
+        println!();
+    }
+    if status.head.is_none() {
+        println!("\nNo commits yet\n");
+    }
+
+    let label = |c: char| match c {
+        'A' => "new file:",
+        'M' => "modified:",
+        'D' => "deleted:",
+        'R' => "renamed:",
+        'T' => "typechange:",
+        _ => "unknown:",
     };
-    println!("  c_{} [label=\"{}: {}\"]", sha, short_hash, message);
-    assert_eq!(commit.fmt(), b"commit");
+    let staged: Vec<&StatusEntry> = status
+        .entries
+        .iter()
+        .filter(|e| !e.is_unmerged() && e.staged != ' ')
+        .collect();
+    let unstaged: Vec<&StatusEntry> = status
+        .entries
+        .iter()
+        .filter(|e| !e.is_unmerged() && e.unstaged != ' ')
+        .collect();
+
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        match status.head {
+            // Unstaging is not suggested in the middle of a merge
+            _ if merging => {}
+            Some(_) => println!("  (use \"git restore --staged <file>...\" to unstage)"),
+            None => println!("  (use \"git rm --cached <file>...\" to unstage)"),
+        }
+        for e in &staged {
+            println!("\t{:<12}{}", label(e.staged), display(e));
+        }
+        println!();
+    }
+
+    if !unmerged.is_empty() {
+        println!("Unmerged paths:");
+        match status.head {
+            _ if merging => {}
+            Some(_) => println!("  (use \"git restore --staged <file>...\" to unstage)"),
+            None => println!("  (use \"git rm --cached <file>...\" to unstage)"),
+        }
+        if unmerged
+            .iter()
+            .any(|e| e.staged == 'D' || e.unstaged == 'D')
+        {
+            println!("  (use \"git add/rm <file>...\" as appropriate to mark resolution)");
+        } else {
+            println!("  (use \"git add <file>...\" to mark resolution)");
+        }
+        for e in &unmerged {
+            let label = match (e.staged, e.unstaged) {
+                ('D', 'D') => "both deleted:",
+                ('A', 'U') => "added by us:",
+                ('U', 'D') => "deleted by them:",
+                ('U', 'A') => "added by them:",
+                ('D', 'U') => "deleted by us:",
+                ('A', 'A') => "both added:",
+                _ => "both modified:",
+            };
+            println!("\t{label:<17}{}", display(e));
+        }
+        println!();
+    }
+
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        if unstaged.iter().any(|e| e.unstaged == 'D') {
+            println!("  (use \"git add/rm <file>...\" to update what will be committed)");
+        } else {
+            println!("  (use \"git add <file>...\" to update what will be committed)");
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for e in &unstaged {
+            println!("\t{:<12}{}", label(e.unstaged), show(&e.path));
+        }
+        println!();
+    }
+
+    if !status.untracked.is_empty() {
+        println!("Untracked files:");
+        println!("  (use \"git add <file>...\" to include in what will be committed)");
+        for path in &status.untracked {
+            println!("\t{}", show(path));
+        }
+        println!();
+    } else if untracked == UntrackedMode::No && !staged.is_empty() {
+        println!("Untracked files not listed (use -u option to show untracked files)");
+    }
 
-    if !commit.kvlm.contains_key(&Some(b"parent".to_vec())) {
+    if !staged.is_empty() {
         return;
     }
+    if !unstaged.is_empty() || !unmerged.is_empty() {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else if !status.untracked.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
+    } else if status.head.is_none() {
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+    } else if untracked == UntrackedMode::No {
+        println!("nothing to commit (use -u to show untracked files)");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+}
+
+/// Options for `check-ignore`
+#[derive(Default)]
+pub struct CheckIgnoreOptions {
+    /// Show the matching pattern and where it comes from
+    pub verbose: bool,
+    /// Also report paths that are tracked in the index
+    pub no_index: bool,
+    /// Read paths from standard input, one per line
+    pub stdin: bool,
+    /// With `stdin`, paths and output are NUL separated
+    pub null_terminated: bool,
+}
+
+/// Print which of `paths` are ignored, exiting with 1 when none are
+pub fn cmd_check_ignore(repo: &GitRepository, paths: &[String], opts: &CheckIgnoreOptions) {
+    if opts.stdin && !paths.is_empty() {
+        eprintln!("fatal: cannot specify pathnames with --stdin");
+        process::exit(128);
+    }
+    if !opts.stdin && paths.is_empty() {
+        eprintln!("fatal: no path specified");
+        process::exit(128);
+    }
+    if opts.null_terminated && !opts.stdin {
+        eprintln!("fatal: -z only makes sense with --stdin");
+        process::exit(128);
+    }
+
+    let index = (!opts.no_index).then(|| index_read(repo));
+    let mut ignore = GitIgnore::new(repo);
+    let mut found = false;
+    let mut check = |path: &str| {
+        let rel = worktree_relpath(repo, path);
+        let is_dir = path.ends_with('/') || repo.worktree.join(&rel).is_dir();
+        let tracked = index
+            .as_ref()
+            .is_some_and(|index| index.entries.iter().any(|e| e.name == rel.as_bytes()));
+        let m = match rel.is_empty() || tracked {
+            true => None,
+            false => ignore.find(rel.as_bytes(), is_dir),
+        };
+        // Without -v, re-included paths are not worth mentioning
+        let m = match m {
+            Some(m) if m.rule.negated && !opts.verbose => None,
+            m => m,
+        };
+        let m = match m {
+            Some(m) => m,
+            None => return,
+        };
+        found = true;
+
+        let mut out = io::stdout().lock();
+        let written = match (opts.verbose, opts.null_terminated) {
+            (false, false) => writeln!(out, "{path}"),
+            (false, true) => write!(out, "{path}\0"),
+            (true, false) => writeln!(out, "{}:{}:{}\t{path}", m.source, m.rule.line, m.rule.text),
+            (true, true) => write!(
+                out,
+                "{}\0{}\0{}\0{path}\0",
+                m.source, m.rule.line, m.rule.text
+            ),
+        };
+        // Flush each answer, as callers may be waiting for it on a pipe
+        written.and_then(|_| out.flush()).expect("Failed to write");
+    };
+
+    if opts.stdin {
+        let mut input = Vec::new();
+        io::stdin()
+            .read_to_end(&mut input)
+            .expect("Failed to read stdin");
+        let sep = if opts.null_terminated { b'\0' } else { b'\n' };
+        for line in input.split(|&c| c == sep).filter(|l| !l.is_empty()) {
+            check(&String::from_utf8_lossy(line));
+        }
+    } else {
+        for path in paths {
+            check(path);
+        }
+    }
+
+    if !found {
+        process::exit(1);
+    }
+}
+
+/// Paths that stop `checkout` from switching trees
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CheckoutConflicts {
+    /// Tracked files whose local changes would be overwritten
+    pub modified: Vec<Vec<u8>>,
+    /// Directories that would lose untracked files in them
+    pub lost: Vec<Vec<u8>>,
+    /// Untracked files that would be overwritten
+    pub untracked: Vec<Vec<u8>>,
+}
+
+/// Move the index and worktree from tree `old` to tree `new`, either id
+/// possibly missing.
+///
+/// Paths that are the same in both trees keep their local changes. Other
+/// paths must be clean, otherwise nothing is touched and the conflicting
+/// paths are returned. With `force`, everything is reset to `new`.
+pub fn checkout_tree(
+    repo: &GitRepository,
+    old: Option<&str>,
+    new: Option<&str>,
+    force: bool,
+) -> Result<(), CheckoutConflicts> {
+    let flatten = |tree: Option<&str>| tree.map(|t| tree_flatten(repo, t)).unwrap_or_default();
+    let (old, new) = (flatten(old), flatten(new));
+    let mut index = index_read(repo);
+    let blob = |leaf: &GitTreeLeaf| (leaf.mode_bits(), leaf.sha.clone());
+
+    let names: Vec<Vec<u8>> = index.entries.iter().map(|e| e.name.clone()).collect();
+    let mut paths: Vec<&Vec<u8>> = old.keys().chain(new.keys()).chain(&names).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut conflicts = CheckoutConflicts::default();
+    let mut ignore = GitIgnore::new(repo);
+    let mut untracked_files: Option<Vec<Vec<u8>>> = None;
+    let mut remove = Vec::new();
+    let mut write = Vec::new();
+    for path in paths {
+        let o = old.get(path).map(blob);
+        let n = new.get(path).map(blob);
+        let entry = index.get(path);
+        let i = entry.map(|e| (e.mode, e.sha.clone()));
+
+        if force {
+            match &n {
+                Some(_) => write.push(path),
+                None if o.is_some() || i.is_some() => remove.push(path),
+                None => {}
+            }
+            continue;
+        }
+        // Unchanged between the trees, or the change is already staged
+        if o == n || i == n {
+            continue;
+        }
+
+        let clean = match entry {
+            // A file deleted from the worktree has nothing left to lose
+            Some(entry) => i == o && worktree_compare(repo, entry) != WorktreeState::Modified,
+            None => {
+                let meta = fs::symlink_metadata(worktree_path(repo, path));
+                let present = meta.is_ok();
+                // A directory in the way only matters if it holds files
+                // that are not tracked, as the tracked ones are removed
+                let in_the_way = match &meta {
+                    Ok(meta) if meta.is_dir() => {
+                        let files = untracked_files.get_or_insert_with(|| {
+                            worktree_files(repo, Some(&mut ignore))
+                                .into_iter()
+                                .filter(|f| index.get(f).is_none())
+                                .collect::<Vec<_>>()
+                        });
+                        let dir = [&path[..], b"/"].concat();
+                        if files.iter().any(|f| f.starts_with(&dir)) {
+                            conflicts.lost.push(path.clone());
+                            continue;
+                        }
+                        false
+                    }
+                    Ok(_) => !ignore.is_ignored(path, false),
+                    // An untracked file where a parent directory must go
+                    Err(_) if n.is_some() => {
+                        let mut parent = path.as_slice();
+                        let mut blocked = false;
+                        while let Some(i) = parent.iter().rposition(|&c| c == b'/') {
+                            parent = &parent[..i];
+                            let meta = fs::symlink_metadata(worktree_path(repo, parent));
+                            if meta.is_ok_and(|m| !m.is_dir())
+                                && index.get(parent).is_none()
+                                && !ignore.is_ignored(parent, false)
+                            {
+                                if !conflicts.untracked.iter().any(|p| p == parent) {
+                                    conflicts.untracked.push(parent.to_vec());
+                                }
+                                blocked = true;
+                            }
+                        }
+                        if blocked {
+                            continue;
+                        }
+                        false
+                    }
+                    Err(_) => false,
+                };
+                if in_the_way && o.is_none() {
+                    conflicts.untracked.push(path.clone());
+                    continue;
+                }
+                o.is_none() || !present
+            }
+        };
+        if !clean {
+            conflicts.modified.push(path.clone());
+        } else if n.is_some() {
+            write.push(path);
+        } else {
+            remove.push(path);
+        }
+    }
+    if conflicts != CheckoutConflicts::default() {
+        return Err(conflicts);
+    }
+
+    for path in &remove {
+        worktree_remove(repo, path);
+    }
+    let remove: Vec<&[u8]> = remove.iter().map(|p| p.as_slice()).collect();
+    index.remove_all(&remove);
+    let mut entries = Vec::new();
+    for path in write {
+        let leaf = &new[path];
+        let mut entry = GitIndexEntry {
+            mode: leaf.mode_bits(),
+            sha: leaf.sha.clone(),
+            name: path.clone(),
+            ..Default::default()
+        };
+        worktree_checkout(repo, &mut entry);
+        entries.push(entry);
+    }
+    index.add_all(entries);
+    index_write(repo, &index);
+    Ok(())
+}
+
+/// Local changes carried over a checkout of tree `new`, as git's
+/// `diff-index --name-status` letters
+fn checkout_local_changes(repo: &GitRepository, new: Option<&str>) -> Vec<(char, Vec<u8>)> {
+    let new = new.map(|t| tree_flatten(repo, t)).unwrap_or_default();
+    let index = index_read(repo);
+    let mut changes = Vec::new();
+    for entry in &index.entries {
+        let meta = fs::symlink_metadata(worktree_path(repo, &entry.name));
+        let letter = match (new.get(&entry.name), meta) {
+            (None, _) => 'A',
+            (Some(_), Err(_)) => 'D',
+            (Some(leaf), Ok(meta))
+                if leaf.mode_bits() & 0o170000 != worktree_mode(&meta) & 0o170000 =>
+            {
+                'T'
+            }
+            (Some(leaf), Ok(_))
+                if leaf.sha != entry.sha
+                    || leaf.mode_bits() != entry.mode
+                    || worktree_compare(repo, entry) != WorktreeState::Unchanged =>
+            {
+                'M'
+            }
+            _ => continue,
+        };
+        changes.push((letter, entry.name.clone()));
+    }
+    for path in new.keys() {
+        if index.get(path).is_none() {
+            changes.push(('D', path.clone()));
+        }
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    changes
+}
+
+const DETACHED_HEAD_ADVICE: &str = "\
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+
+";
+
+/// Options for `checkout`
+#[derive(Default)]
+pub struct CheckoutOptions {
+    /// Create this branch at the start point and switch to it
+    pub new_branch: Option<String>,
+    /// Detach `HEAD` even when switching to a branch
+    pub detach: bool,
+    /// Throw away local changes
+    pub force: bool,
+}
+
+/// Switch branches, or restore `paths` when given. `args` are the
+/// arguments before `--`, `paths` the ones after it.
+pub fn cmd_checkout(
+    repo: &GitRepository,
+    args: &[String],
+    paths: &[String],
+    opts: &CheckoutOptions,
+) {
+    let commit = |name: &str| object_find(repo, name, Some(ObjectType::Commit), true);
+    match (args, paths) {
+        ([], []) if opts.new_branch.is_none() && !opts.detach => {}
+        ([], paths) if !paths.is_empty() => checkout_paths(repo, None, paths, false),
+        ([source], paths) if !paths.is_empty() => checkout_paths(repo, Some(source), paths, false),
+        (_, paths) if !paths.is_empty() => {
+            eprintln!("fatal: only one reference expected, {} given.", args.len());
+            process::exit(128);
+        }
+        ([], []) => checkout_switch(repo, "HEAD", opts),
+        ([target], []) if commit(target).is_some() => checkout_switch(repo, target, opts),
+        ([source, rest @ ..], []) if commit(source).is_some() => {
+            checkout_paths(repo, Some(source), rest, true)
+        }
+        (args, []) if opts.new_branch.is_some() => {
+            eprintln!(
+                "fatal: '{}' is not a commit and a branch '{}' cannot be created from it",
+                args[0],
+                opts.new_branch.as_deref().unwrap()
+            );
+            process::exit(128);
+        }
+        (args, _) => checkout_paths(repo, None, args, true),
+    }
+}
+
+fn checkout_switch(repo: &GitRepository, target: &str, opts: &CheckoutOptions) {
+    if index_read(repo).entries.iter().any(|e| e.stage > 0) {
+        eprintln!("error: you need to resolve your current index first");
+        process::exit(1);
+    }
+    let Some(sha) = object_find(repo, target, Some(ObjectType::Commit), true) else {
+        eprintln!("fatal: invalid reference: {target}");
+        process::exit(128);
+    };
+    let branch = match &opts.new_branch {
+        Some(name) => {
+            let refname = format!("refs/heads/{name}");
+            if !ref_check_format(&refname) {
+                eprintln!("fatal: '{name}' is not a valid branch name");
+                process::exit(128);
+            }
+            if ref_resolve(repo, &refname).is_some() {
+                eprintln!("fatal: a branch named '{name}' already exists");
+                process::exit(128);
+            }
+            Some(refname)
+        }
+        None if opts.detach => None,
+        None => Some(format!("refs/heads/{target}")).filter(|r| ref_resolve(repo, r).is_some()),
+    };
+
+    let head = rev_parse(repo, "HEAD");
+    let old_tree = object_find(repo, "HEAD", Some(ObjectType::Tree), true);
+    let new_tree = object_find(repo, &sha, Some(ObjectType::Tree), true);
+    if let Err(conflicts) =
+        checkout_tree(repo, old_tree.as_deref(), new_tree.as_deref(), opts.force)
+    {
+        // Like git, paths are listed as they are, without quoting
+        let list = |paths: &[Vec<u8>]| {
+            for path in paths {
+                let _ = io::stderr().write_all(&[b"\t", &path[..], b"\n"].concat());
+            }
+        };
+        if !conflicts.modified.is_empty() {
+            eprintln!("error: Your local changes to the following files would be overwritten by checkout:");
+            list(&conflicts.modified);
+            eprintln!("Please commit your changes or stash them before you switch branches.");
+        }
+        if !conflicts.lost.is_empty() {
+            eprintln!(
+                "error: Updating the following directories would lose untracked files in them:"
+            );
+            list(&conflicts.lost);
+            eprintln!();
+        }
+        if !conflicts.untracked.is_empty() {
+            eprintln!("error: The following untracked working tree files would be overwritten by checkout:");
+            list(&conflicts.untracked);
+            eprintln!("Please move or remove them before you switch branches.");
+        }
+        eprintln!("Aborting");
+        process::exit(1);
+    }
+    if !opts.force {
+        for (letter, path) in checkout_local_changes(repo, new_tree.as_deref()) {
+            println!("{letter}\t{}", path_quote(&path));
+        }
+    }
+
+    let old_symbolic = ref_symbolic(repo, "HEAD");
+    let from = match (&old_symbolic, &head) {
+        (Some(r), _) => ref_shorten(r).to_string(),
+        (None, Some(head)) => head.clone(),
+        (None, None) => "HEAD".to_string(),
+    };
+    let to = match &opts.new_branch {
+        Some(name) => name.clone(),
+        None => target.to_string(),
+    };
+    let message = format!("checkout: moving from {from} to {to}");
+    let head_sha = head
+        .clone()
+        .unwrap_or_else(|| repo.object_format.null_sha());
+
+    let describe = |sha: &str| {
+        let obj = object_read(repo, sha).expect("Failed to read commit");
+        let commit = obj
+            .as_any()
+            .downcast_ref::<GitCommit>()
+            .expect("Not a commit");
+        let subject = commit
+            .message()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        format!("{} {subject}", object_abbrev(repo, sha, 7))
+    };
+    if let (None, Some(head)) = (&old_symbolic, &head) {
+        if *head != sha {
+            eprintln!("Previous HEAD position was {}", describe(head));
+        }
+    }
+
+    match &branch {
+        Some(refname) => {
+            if opts.new_branch.is_some() {
+                ref_update(
+                    repo,
+                    refname,
+                    &sha,
+                    Some(&repo.object_format.null_sha()),
+                    Some(&format!("branch: Created from {target}")),
+                );
+            }
+            ref_symbolic_update(repo, "HEAD", refname);
+            reflog_append(repo, "HEAD", &head_sha, &sha, &message);
+
+            let name = ref_shorten(refname);
+            if opts.new_branch.is_some() {
+                eprintln!("Switched to a new branch '{name}'");
+            } else if old_symbolic.as_deref() == Some(refname.as_str()) {
+                eprintln!("Already on '{name}'");
+            } else {
+                eprintln!("Switched to branch '{name}'");
+            }
+        }
+        None => {
+            ref_update_no_deref(repo, "HEAD", &sha, Some(&message));
+            if old_symbolic.is_some() && !opts.detach {
+                eprintln!("Note: switching to '{target}'.\n");
+                eprint!("{}", DETACHED_HEAD_ADVICE);
+            }
+            eprintln!("HEAD is now at {}", describe(&sha));
+        }
+    }
+}
+
+/// Restore `paths` in the worktree from the index, or from `source` into
+/// both the index and the worktree
+fn checkout_paths(repo: &GitRepository, source: Option<&str>, paths: &[String], report: bool) {
+    let specs: Vec<String> = paths.iter().map(|p| worktree_relpath(repo, p)).collect();
+    let mut index = index_read(repo);
+
+    let (entries, from) = match source {
+        Some(source) => {
+            let Some(sha) = object_find(repo, source, None, true) else {
+                eprintln!("fatal: invalid reference: {source}");
+                process::exit(128);
+            };
+            let Some(tree) = object_peel(repo, &sha, "tree", true) else {
+                eprintln!("fatal: reference is not a tree: {source}");
+                process::exit(128);
+            };
+            let entries: Vec<GitIndexEntry> = tree_flatten(repo, &tree)
+                .into_values()
+                .map(|leaf| GitIndexEntry {
+                    mode: leaf.mode_bits(),
+                    sha: leaf.sha,
+                    name: leaf.path,
+                    ..Default::default()
+                })
+                .collect();
+            (entries, object_abbrev(repo, &tree, 7))
+        }
+        None => (index.entries.clone(), "the index".to_string()),
+    };
+
+    let mut matched = vec![false; specs.len()];
+    let mut selected = Vec::new();
+    for entry in entries {
+        let mut hit = false;
+        for (i, spec) in specs.iter().enumerate() {
+            if pathspec_match(spec, &entry.name) {
+                matched[i] = true;
+                hit = true;
+            }
+        }
+        if hit {
+            selected.push(entry);
+        }
+    }
+    let mut failed = false;
+    for (i, arg) in paths.iter().enumerate() {
+        if !matched[i] {
+            eprintln!("error: pathspec '{arg}' did not match any file(s) known to git");
+            failed = true;
+        }
+    }
+    if source.is_none() {
+        for entry in selected.iter().filter(|e| e.stage > 0) {
+            eprintln!(
+                "error: path '{}' is unmerged",
+                String::from_utf8_lossy(&entry.name)
+            );
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+
+    let count = selected.len();
+    for entry in &mut selected {
+        worktree_checkout(repo, entry);
+    }
+    index.add_all(selected);
+    index_write(repo, &index);
+
+    // Like git, the summary is only shown when no `--` was given
+    if report {
+        let s = if count == 1 { "" } else { "s" };
+        eprintln!("Updated {count} path{s} from {from}");
+    }
+}
+
+/// Handle the arguments of `rev-parse` in order. `--verify`, `-q`/`--quiet`
+/// and `--short[=N]` (which implies `--verify`) apply to the revisions
+/// after them; with `--verify` the single revision is shown at the end.
+/// Other options are answered where they appear, unknown ones echoed.
+pub fn cmd_rev_parse(repo: &GitRepository, args: &[String]) {
+    let prefix = worktree_prefix(repo);
+    let mut verify = false;
+    let mut quiet = false;
+    let mut short: Option<usize> = None;
+    let mut verified: Vec<String> = Vec::new();
+    let no_single_rev = |quiet: bool| -> ! {
+        if !quiet {
+            eprintln!("fatal: Needed a single revision");
+        }
+        process::exit(if quiet { 1 } else { 128 });
+    };
+    let show = |sha: &str, short: Option<usize>| match short {
+        Some(len) => println!("{}", object_abbrev(repo, sha, len)),
+        None => println!("{sha}"),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                println!("--");
+                args.by_ref().for_each(|path| println!("{path}"));
+            }
+            "--verify" => verify = true,
+            "-q" | "--quiet" => quiet = true,
+            "--git-dir" => cmd_rev_parse_git_dir(repo),
+            "--show-toplevel" => println!("{}", repo.worktree.display()),
+            "--is-inside-work-tree" => cmd_rev_parse_inside_work_tree(repo),
+            "--short" => {
+                verify = true;
+                short = Some(7);
+            }
+            _ if arg.starts_with("--short=") => {
+                verify = true;
+                short = Some(arg["--short=".len()..].parse().unwrap_or(0));
+            }
+            _ if arg.starts_with('-') => println!("{arg}"),
+            _ => {
+                let Some(spec) = rev_relative(arg, &prefix) else {
+                    let path = &arg[arg.find(':').unwrap() + 1..];
+                    eprintln!(
+                        "fatal: '{path}' is outside repository at '{}'",
+                        repo.worktree.display()
+                    );
+                    process::exit(128);
+                };
+                match rev_parse(repo, &spec) {
+                    Some(sha) if verify => verified.push(sha),
+                    Some(sha) => show(&sha, short),
+                    None if verify => no_single_rev(quiet),
+                    None => {
+                        eprintln!("fatal: ambiguous argument '{arg}': unknown revision or path not in the working tree.");
+                        process::exit(128);
+                    }
+                }
+            }
+        }
+    }
+
+    if verify {
+        match verified.as_slice() {
+            [sha] => show(sha, short),
+            _ => no_single_rev(quiet),
+        }
+    }
+}
+
+pub fn cmd_rev_parse_git_dir(repo: &GitRepository) {
+    let cwd = std::env::current_dir()
+        .and_then(|p| p.canonicalize())
+        .expect("Failed to get current directory");
+    if cwd == repo.worktree {
+        println!(".git");
+    } else {
+        println!("{}", repo.gitdir.display());
+    }
+}
+
+pub fn cmd_rev_parse_inside_work_tree(repo: &GitRepository) {
+    let cwd = std::env::current_dir()
+        .and_then(|p| p.canonicalize())
+        .expect("Failed to get current directory");
+    println!("{}", !cwd.starts_with(&repo.gitdir));
+}
+
+/// Options for `show-ref`
+#[derive(Default)]
+pub struct ShowRefOptions {
+    pub head: bool,
+    pub heads: bool,
+    pub tags: bool,
+    pub dereference: bool,
+    pub hash: Option<usize>,
+    pub verify: bool,
+    pub quiet: bool,
+}
+
+pub fn cmd_show_ref(repo: &GitRepository, patterns: &[String], opts: &ShowRefOptions) {
+    match show_ref(repo, patterns, opts) {
+        Err(_) if opts.quiet => process::exit(1),
+        Err(name) => {
+            eprintln!("fatal: '{name}' - not a valid ref");
+            process::exit(128);
+        }
+        Ok(lines) if lines.is_empty() => process::exit(1),
+        Ok(_) if opts.quiet => {}
+        Ok(lines) => lines.iter().for_each(|line| println!("{line}")),
+    }
+}
+
+/// The lines `show-ref` prints, none when no ref matched. With `--verify`,
+/// `Err` is the first name that is not a ref.
+fn show_ref(
+    repo: &GitRepository,
+    patterns: &[String],
+    opts: &ShowRefOptions,
+) -> Result<Vec<String>, String> {
+    let mut refs: Vec<(String, String)> = Vec::new();
+
+    if opts.verify {
+        for name in patterns {
+            let found = match name.starts_with("refs/") || name == "HEAD" {
+                true => ref_resolve(repo, name),
+                false => None,
+            };
+            match found {
+                Some(sha) => refs.push((name.clone(), sha)),
+                None => return Err(name.clone()),
+            }
+        }
+    } else {
+        if opts.head {
+            refs.extend(ref_resolve(repo, "HEAD").map(|sha| ("HEAD".to_string(), sha)));
+        }
+        refs.extend(ref_list(repo, "refs").into_iter().filter(|(name, _)| {
+            if (opts.heads || opts.tags)
+                && !(opts.heads && name.starts_with("refs/heads/")
+                    || opts.tags && name.starts_with("refs/tags/"))
+            {
+                return false;
+            }
+            patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|p| name == p || name.ends_with(&format!("/{p}")))
+        }));
+    }
+
+    let mut lines = Vec::new();
+    for (name, sha) in refs {
+        match opts.hash {
+            Some(len) => lines.push(object_abbrev(repo, &sha, len)),
+            None => lines.push(format!("{sha} {name}")),
+        }
+        if opts.dereference {
+            // git prints peeled entries in full even with --hash
+            if let Some(peeled) = ref_peeled(repo, &name, &sha) {
+                lines.push(format!("{peeled} {name}^{{}}"));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// What an annotated tag ref ultimately points to, preferring the peeled
+/// value recorded in `packed-refs`. `None` when the ref is not a tag.
+fn ref_peeled(repo: &GitRepository, name: &str, sha: &str) -> Option<String> {
+    if let Some(r) = packed_refs_read(repo).get(name) {
+        if r.sha == sha && r.peeled.is_some() {
+            return r.peeled.clone();
+        }
+    }
+
+    object_peel(repo, sha, "tag", false)?;
+    object_peel_tags(repo, sha)
+}
+
+pub fn cmd_tag_list(repo: &GitRepository) {
+    for (name, _) in ref_list(repo, "refs/tags") {
+        println!("{}", &name["refs/tags/".len()..]);
+    }
+}
+
+pub fn cmd_tag_create(
+    repo: &GitRepository,
+    name: &str,
+    target: &str,
+    annotate: bool,
+    message: Option<&str>,
+) {
+    if let Err(e) = tag_create(repo, name, target, annotate, message) {
+        eprintln!("fatal: {e}");
+        process::exit(128);
+    }
+}
+
+/// Point `refs/tags/<name>` at `target`, through a new tag object when
+/// annotating. An existing tag is never overwritten.
+fn tag_create(
+    repo: &GitRepository,
+    name: &str,
+    target: &str,
+    annotate: bool,
+    message: Option<&str>,
+) -> Result<(), String> {
+    let refname = format!("refs/tags/{name}");
+    if !ref_check_format(&refname) {
+        return Err(format!("'{name}' is not a valid tag name."));
+    }
+    if ref_resolve(repo, &refname).is_some() {
+        return Err(format!("tag '{name}' already exists"));
+    }
+
+    let sha = object_find(repo, target, None, true)
+        .ok_or_else(|| format!("Failed to resolve '{target}' as a valid ref."))?;
+    let null = repo.object_format.null_sha();
+    if !annotate {
+        ref_update(repo, &refname, &sha, Some(&null), None);
+        return Ok(());
+    }
+
+    let message = message.ok_or("no tag message given, use -m")?;
+    let fmt = object_read(repo, &sha)
+        .ok_or_else(|| format!("Failed to read tagged object {sha}"))?
+        .fmt();
+
+    let mut kvlm = Dict::new();
+    kvlm.insert(Some(b"object".to_vec()), vec![sha.into_bytes()]);
+    kvlm.insert(Some(b"type".to_vec()), vec![fmt]);
+    kvlm.insert(Some(b"tag".to_vec()), vec![name.as_bytes().to_vec()]);
+    kvlm.insert(
+        Some(b"tagger".to_vec()),
+        vec![ident(repo, "COMMITTER").into_bytes()],
+    );
+    kvlm.insert(None, vec![message_cleanup(message).into_bytes()]);
+
+    let tag = Box::new(GitTag {
+        fmt: b"tag".to_vec(),
+        kvlm,
+    });
+    let tag_sha = object_write(tag, Some(repo.clone()));
+    ref_update(repo, &refname, &tag_sha, Some(&null), None);
+    Ok(())
+}
+
+pub fn cmd_tag_delete(repo: &GitRepository, name: &str) {
+    match tag_delete(repo, name) {
+        Ok(sha) => println!(
+            "Deleted tag '{name}' (was {})",
+            object_abbrev(repo, &sha, 7)
+        ),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Delete `refs/tags/<name>`, returning the object it pointed at
+fn tag_delete(repo: &GitRepository, name: &str) -> Result<String, String> {
+    let refname = format!("refs/tags/{name}");
+    let sha = ref_resolve(repo, &refname).ok_or_else(|| format!("tag '{name}' not found."))?;
+    ref_delete(repo, &refname, Some(&sha));
+    Ok(sha)
+}
+
+/// Clean up a message the way git does for `-m` and `-F`: strip trailing
+/// whitespace, collapse runs of blank lines and drop leading and trailing
+/// ones. A non-empty result ends with a newline.
+fn message_cleanup(message: &str) -> String {
+    let mut ret = String::new();
+    let mut blank = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !ret.is_empty() {
+            ret.push('\n');
+        }
+        blank = false;
+        ret.push_str(line);
+        ret.push('\n');
+    }
+    ret
+}
+
+/// Resolve a name to every object id it could refer to.
+///
+/// Refs are tried in git's precedence order and the first hit wins. Only
+/// when no ref matches is the name treated as a (possibly abbreviated) hash,
+/// which may yield several candidates.
+pub fn object_resolve(repo: &GitRepository, name: &str) -> Vec<String> {
+    if name.is_empty() {
+        return Vec::new();
+    }
+
+    let is_hex = name.bytes().all(|c| c.is_ascii_hexdigit());
+    if is_hex && name.len() == repo.object_format.hex_len() {
+        return vec![name.to_lowercase()];
+    }
+
+    if let Some(refname) = ref_dwim(repo, name) {
+        return ref_resolve(repo, &refname).into_iter().collect();
+    }
+
+    if is_hex && name.len() >= 4 {
+        return object_prefix_search(repo, &name.to_lowercase());
+    }
+
+    Vec::new()
+}
+
+/// Find all objects whose id starts with the given hex prefix
+fn object_prefix_search(repo: &GitRepository, prefix: &str) -> Vec<String> {
+    let mut ret = pack_prefix_search(repo, prefix);
+    if let Some(dir) = repo_dir(repo, &format!("objects/{}", &prefix[0..2]), false) {
+        for entry in fs::read_dir(dir).expect("Failed to read objects directory") {
+            let rest = entry.unwrap().file_name().to_string_lossy().to_string();
+            if rest.starts_with(&prefix[2..]) {
+                ret.push(format!("{}{rest}", &prefix[0..2]));
+            }
+        }
+    }
+    ret.sort();
+    ret.dedup();
+
+    ret
+}
+
+/// Shortest unique abbreviation of an object id, at least `min` long
+pub fn object_abbrev(repo: &GitRepository, sha: &str, min: usize) -> String {
+    let mut len = min.clamp(4, sha.len());
+    while len < sha.len() && object_prefix_search(repo, &sha[..len]).len() > 1 {
+        len += 1;
+    }
+    sha[..len].to_string()
+}
+
+/// Find the object a name refers to.
+///
+/// The name may use the full revision syntax understood by `rev_parse`.
+/// When `fmt` is given the object must be of that type. With `follow`, tags
+/// are peeled to the object they point at and commits to their tree until an
+/// object of the requested type is found.
+pub fn object_find(
+    repo: &GitRepository,
+    name: &str,
+    fmt: Option<ObjectType>,
+    follow: bool,
+) -> Option<String> {
+    let sha = rev_parse(repo, name)?;
+    match fmt {
+        Some(fmt) => object_peel(repo, &sha, &fmt.to_string(), follow),
+        None => Some(sha),
+    }
+}
+
+/// Peel annotated tags until reaching an object that is not a tag
+pub fn object_peel_tags(repo: &GitRepository, sha: &str) -> Option<String> {
+    let mut sha = sha.to_string();
+    loop {
+        let obj = object_read(repo, &sha)?;
+        match obj.as_any().downcast_ref::<GitTag>() {
+            Some(tag) => sha = tag.object()?,
+            None => return Some(sha),
+        }
+    }
+}
+
+/// Peel an object until one of type `fmt` is found, see `object_find`
+pub fn object_peel(repo: &GitRepository, sha: &str, fmt: &str, follow: bool) -> Option<String> {
+    let fmt = fmt.as_bytes();
+    let mut sha = sha.to_string();
+    loop {
+        let obj = object_read(repo, &sha)?;
+        if obj.fmt() == fmt {
+            return Some(sha);
+        }
+        if !follow {
+            return None;
+        }
+
+        if let Some(tag) = obj.as_any().downcast_ref::<GitTag>() {
+            sha = tag.object()?;
+        } else if let Some(commit) = obj.as_any().downcast_ref::<GitCommit>() {
+            if fmt != b"tree" {
+                return None;
+            }
+            sha = commit.tree();
+        } else {
+            return None;
+        }
+    }
+}
+
+pub fn cmd_hash_object(type_: &ObjectType, write: bool, path: &Path) {
+    // Without -w the repository, if any, only decides the hash function
+    let repo = repo_find(".", write);
+
+    let fd = File::open(path).expect("Failed to open file {path}");
+    let sha = object_hash(fd, type_, repo, write);
+    println!("{sha}")
+}
+
+fn object_hash(fd: File, fmt: &ObjectType, repo: Option<GitRepository>, write: bool) -> String {
+    let mut data: Vec<u8> = Vec::new();
+    let mut reader = BufReader::new(fd);
+    reader.read_to_end(&mut data).expect("Failed to read file");
+
+    let obj: Box<dyn GitObject> = match fmt {
+        ObjectType::Blob => Box::new(GitBlob {
+            fmt: fmt.as_bytes(),
+            blobdata: data,
+        }),
+        _ => panic!("Unknown type {}!", { fmt.to_string() }),
+    };
+
+    if write {
+        return object_write(obj, repo);
+    }
+    let format = repo.map(|r| r.object_format).unwrap_or_default();
+    object_sha(format, &obj.fmt(), &obj.serialize(None))
+}
+
+/// How `log` shows each commit (`--pretty`, `--format`)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Medium,
+    Short,
+    Full,
+    Fuller,
+    Oneline,
+    /// A template with `%` placeholders. `terminated` (`tformat:`) ends
+    /// every entry with a newline instead of putting one between entries.
+    Format {
+        template: String,
+        terminated: bool,
+    },
+    /// A Graphviz digraph of the history
+    Dot,
+}
+
+/// Parses the value of `--pretty` or `--format`
+impl str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        if let Some(template) = value.strip_prefix("format:") {
+            return Ok(LogFormat::Format {
+                template: template.to_string(),
+                terminated: false,
+            });
+        }
+        if let Some(template) = value.strip_prefix("tformat:") {
+            return Ok(LogFormat::Format {
+                template: template.to_string(),
+                terminated: true,
+            });
+        }
+        match value {
+            "medium" => Ok(LogFormat::Medium),
+            "short" => Ok(LogFormat::Short),
+            "full" => Ok(LogFormat::Full),
+            "fuller" => Ok(LogFormat::Fuller),
+            "oneline" => Ok(LogFormat::Oneline),
+            "dot" => Ok(LogFormat::Dot),
+            _ if value.is_empty() || value.contains('%') => Ok(LogFormat::Format {
+                template: value.to_string(),
+                terminated: true,
+            }),
+            _ => Err(format!("invalid --pretty format: {value}")),
+        }
+    }
+}
+
+/// Which refs `log` shows next to the commits they point at (`--decorate`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogDecorate {
+    /// Short names when writing to a terminal, none otherwise
+    #[default]
+    Auto,
+    No,
+    /// Names without `refs/heads/`, `refs/tags/` or `refs/remotes/`
+    Short,
+    Full,
+}
+
+/// Parses the value of `--decorate`
+impl str::FromStr for LogDecorate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value {
+            "auto" => Ok(LogDecorate::Auto),
+            "no" => Ok(LogDecorate::No),
+            "short" => Ok(LogDecorate::Short),
+            "full" => Ok(LogDecorate::Full),
+            _ => Err(format!("invalid --decorate option: {value}")),
+        }
+    }
+}
+
+/// Which commits to walk and in what order, for `log` and `rev-list`
+#[derive(Default)]
+pub struct RevOptions {
+    /// Start from every ref and `HEAD` as well as the revisions given
+    pub all: bool,
+    /// Only follow the first parent of merges
+    pub first_parent: bool,
+    pub sort: RevSort,
+    /// Only show commits that descend from an excluded commit
+    pub ancestry_path: bool,
+    /// Only show commits younger or older than these, as seconds
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// Only show commits whose author matches one of these patterns
+    pub authors: Vec<String>,
+    /// Only show commits whose message matches one of these patterns
+    pub greps: Vec<String>,
+    /// Only show merges (`Some(true)`) or no merges (`Some(false)`)
+    pub merges: Option<bool>,
+    /// Stop after this many commits
+    pub max_count: Option<usize>,
+}
+
+/// Set up a walk over the commits selected by revision arguments, with
+/// `HEAD` as the default, and limited to those touching `paths`
+pub fn rev_walk_setup<'a>(
+    repo: &'a GitRepository,
+    revs: &[String],
+    paths: &[String],
+    opts: &RevOptions,
+) -> RevWalk<'a> {
+    let mut walk = RevWalk::new(repo);
+    walk.first_parent = opts.first_parent;
+    walk.sort = opts.sort;
+    walk.ancestry_path = opts.ancestry_path;
+    walk.since = opts.since;
+    walk.until = opts.until;
+    match opts.merges {
+        Some(true) => walk.min_parents = 2,
+        Some(false) => walk.max_parents = Some(1),
+        None => {}
+    }
+    let regex = |pattern: &String| match Regex::new(pattern) {
+        Ok(re) => re,
+        Err(_) => {
+            eprintln!("fatal: command line, '{pattern}': Invalid regular expression");
+            process::exit(128);
+        }
+    };
+    walk.authors = opts.authors.iter().map(regex).collect();
+    walk.greps = opts.greps.iter().map(regex).collect();
+    walk.paths = paths
+        .iter()
+        .map(|path| worktree_relpath(repo, path))
+        .collect();
+
+    if opts.all {
+        let tips = ref_list(repo, "refs").into_iter().map(|(_, sha)| sha);
+        for sha in tips.chain(ref_resolve(repo, "HEAD")) {
+            if let Some(commit) = object_find(repo, &sha, Some(ObjectType::Commit), true) {
+                walk.push(&commit);
+            }
+        }
+    }
+    if revs.is_empty() && !opts.all {
+        match object_find(repo, "HEAD", Some(ObjectType::Commit), true) {
+            Some(head) => walk.push(&head),
+            None => {
+                let branch = ref_symbolic(repo, "HEAD").unwrap_or_default();
+                eprintln!(
+                    "fatal: your current branch '{}' does not have any commits yet",
+                    ref_shorten(&branch)
+                );
+                process::exit(128);
+            }
+        }
+    }
+    if let Err(rev) = walk.push_revs(revs) {
+        if !paths.is_empty() {
+            eprintln!("fatal: bad revision '{rev}'");
+            process::exit(128);
+        }
+        eprintln!(
+            "fatal: ambiguous argument '{rev}': unknown revision or path not in the working tree."
+        );
+        eprintln!("Use '--' to separate paths from revisions, like this:");
+        eprintln!("'git <command> [<revision>...] -- [<file>...]'");
+        process::exit(128);
+    }
+    if opts.ancestry_path && !walk.has_bottom() {
+        eprintln!("fatal: --ancestry-path given but there are no bottom commits");
+        process::exit(128);
+    }
+    walk
+}
+
+/// Options for `rev-list`
+#[derive(Default)]
+pub struct RevListOptions {
+    /// Print the number of commits instead of listing them
+    pub count: bool,
+    /// Mark each commit with the side of a symmetric difference it is on
+    pub left_right: bool,
+    /// Also list the trees, blobs and tags reachable from the commits
+    pub objects: bool,
+    /// Print the parents of each commit after it
+    pub parents: bool,
+    pub rev: RevOptions,
+}
+
+/// List the commits selected by `revs` and `paths`, youngest first
+pub fn cmd_rev_list(
+    repo: &GitRepository,
+    revs: &[String],
+    paths: &[String],
+    opts: &RevListOptions,
+) {
+    if revs.is_empty() && !opts.rev.all {
+        eprintln!("usage: git rev-list [<options>] <commit>... [--] [<path>...]");
+        process::exit(129);
+    }
+    let written = rev_list(repo, &mut io::stdout().lock(), revs, paths, opts);
+    // A closed pipe, e.g. when piping into `head`, is not an error
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            panic!("Failed to write: {e}");
+        }
+    }
+}
+
+fn rev_list(
+    repo: &GitRepository,
+    out: &mut impl Write,
+    revs: &[String],
+    paths: &[String],
+    opts: &RevListOptions,
+) -> io::Result<()> {
+    let mut walk = rev_walk_setup(repo, revs, paths, &opts.rev);
+    let commits: Vec<(String, GitCommit)> = walk
+        .by_ref()
+        .take(opts.rev.max_count.unwrap_or(usize::MAX))
+        .collect();
+
+    if opts.count {
+        let left = commits.iter().filter(|(sha, _)| walk.is_left(sha)).count();
+        return match opts.left_right {
+            true => writeln!(out, "{left}\t{}", commits.len() - left),
+            false => writeln!(out, "{}", commits.len()),
+        };
+    }
+
+    for (sha, _) in &commits {
+        let mut line = String::new();
+        if opts.left_right {
+            line.push(if walk.is_left(sha) { '<' } else { '>' });
+        }
+        line.push_str(sha);
+        if opts.parents {
+            for parent in walk.parents(sha) {
+                line.push(' ');
+                line.push_str(&parent);
+            }
+        }
+        writeln!(out, "{line}")?;
+    }
+    if opts.objects {
+        rev_list_objects(repo, out, &walk, revs, opts.rev.all, &commits)?;
+    }
+    Ok(())
+}
+
+/// List the annotated tags given on the command line and the trees and
+/// blobs of the listed commits with their paths, leaving out those that
+/// the excluded commits next to them have too
+fn rev_list_objects(
+    repo: &GitRepository,
+    out: &mut impl Write,
+    walk: &RevWalk,
+    revs: &[String],
+    all: bool,
+    commits: &[(String, GitCommit)],
+) -> io::Result<()> {
+    let mut seen = HashSet::new();
+    for (sha, _) in commits {
+        for parent in walk.parents(sha) {
+            if walk.is_uninteresting(&parent) {
+                let tree = commit_read(repo, &parent).tree();
+                tree_objects(repo, &tree, b"", &mut |sha, _| seen.insert(sha.to_string()));
+            }
+        }
+    }
+
+    let mut tips: Vec<String> = Vec::new();
+    if all {
+        tips.extend(ref_list(repo, "refs").into_iter().map(|(_, sha)| sha));
+        tips.extend(ref_resolve(repo, "HEAD"));
+    }
+    tips.extend(
+        revs.iter()
+            .filter(|rev| !rev.starts_with('^') && !rev.contains(".."))
+            .filter_map(|rev| rev_parse(repo, rev)),
+    );
+    for sha in tips {
+        if let Some(obj) = object_read(repo, &sha) {
+            if let Some(tag) = obj.as_any().downcast_ref::<GitTag>() {
+                if seen.insert(sha.clone()) {
+                    writeln!(out, "{sha} {}", tag.tag().unwrap_or_default())?;
+                }
+            }
+        }
+    }
+
+    // With paths, only what leads to them or is in them is listed
+    let interesting = |path: &[u8]| {
+        walk.paths.is_empty()
+            || path.is_empty()
+            || walk.paths.iter().any(|p| {
+                let under = |a: &[u8], b: &[u8]| {
+                    a == b || a.strip_prefix(b).is_some_and(|rest| rest.starts_with(b"/"))
+                };
+                under(path, p.as_bytes()) || under(p.as_bytes(), path)
+            })
+    };
+    let mut lines = Vec::new();
+    for (_, commit) in commits {
+        tree_objects(repo, &commit.tree(), b"", &mut |sha, path| {
+            let new = interesting(path) && seen.insert(sha.to_string());
+            if new {
+                // Like git, paths are printed as they are
+                let mut line = format!("{sha} ").into_bytes();
+                line.extend(path);
+                line.push(b'\n');
+                lines.push(line);
+            }
+            new
+        });
+    }
+    lines.iter().try_for_each(|line| out.write_all(line))
+}
+
+/// Visit a tree and, depth first, the trees and blobs in it, with their
+/// paths. `visit` returns whether to go into a tree.
+fn tree_objects(
+    repo: &GitRepository,
+    sha: &str,
+    path: &[u8],
+    visit: &mut impl FnMut(&str, &[u8]) -> bool,
+) {
+    if !visit(sha, path) {
+        return;
+    }
+    let obj = object_read(repo, sha).unwrap_or_else(|| panic!("Failed to read tree {sha}"));
+    let tree = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .unwrap_or_else(|| panic!("Object {sha} is not a tree"));
+    for leaf in &tree.items {
+        let child = tree_path_join(path, &leaf.path);
+        match leaf.object_type() {
+            "tree" => tree_objects(repo, &leaf.sha, &child, visit),
+            "blob" => {
+                visit(&leaf.sha, &child);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Options for `log`
+#[derive(Default)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Show abbreviated commit ids in the headers
+    pub abbrev_commit: bool,
+    /// Show the selected commits oldest first
+    pub reverse: bool,
+    /// Draw the history graph next to the commits
+    pub graph: bool,
+    pub decorate: LogDecorate,
+    pub rev: RevOptions,
+}
+
+/// Show the history selected by `revs` (default `HEAD`) and `paths`,
+/// youngest commit first
+pub fn cmd_log(repo: &GitRepository, revs: &[String], paths: &[String], opts: &LogOptions) {
+    if opts.graph && opts.reverse {
+        eprintln!("fatal: options '--reverse' and '--graph' cannot be used together");
+        process::exit(128);
+    }
+    let mut walk = rev_walk_setup(repo, revs, paths, &opts.rev);
+    // The graph needs children before their parents, so it implies
+    // topological order
+    if opts.graph && walk.sort == RevSort::Default {
+        walk.sort = RevSort::Topo;
+    }
+    let mut commits: Vec<(String, GitCommit)> = walk
+        .by_ref()
+        .take(opts.rev.max_count.unwrap_or(usize::MAX))
+        .collect();
+    if opts.reverse {
+        commits.reverse();
+    }
+
+    let mut out = io::stdout().lock();
+    let written = match &opts.format {
+        LogFormat::Dot => log_graphviz(&mut out, &commits),
+        format => {
+            let decorate = match opts.decorate {
+                LogDecorate::Auto if io::stdout().is_terminal() => LogDecorate::Short,
+                LogDecorate::Auto => LogDecorate::No,
+                decorate => decorate,
+            };
+            let decorations = LogDecorations::load(repo, decorate == LogDecorate::Full);
+            let mut graph = opts.graph.then(GitGraph::new);
+            // Oneline and tformat entries end with a newline, the others
+            // are separated by one
+            let use_terminator = matches!(
+                format,
+                LogFormat::Oneline
+                    | LogFormat::Format {
+                        terminated: true,
+                        ..
+                    }
+            );
+            let mut missing_newline = false;
+
+            commits
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, (sha, commit))| {
+                    if let Some(graph) = &mut graph {
+                        let mut parents = walk.parents(sha);
+                        if opts.rev.first_parent {
+                            parents.truncate(1);
+                        }
+                        parents.retain(|p| walk.is_shown(p));
+                        graph.update(sha, parents);
+                    }
+                    let mut entry = String::new();
+                    if i > 0 && !use_terminator {
+                        if let (Some(graph), false) = (&mut graph, missing_newline) {
+                            entry.push_str(&graph.padding_line());
+                        }
+                        entry.push('\n');
+                    }
+                    if let Some(graph) = &mut graph {
+                        entry.push_str(&graph.show_commit());
+                    }
+
+                    let decoration = decorations.get(sha);
+                    let (header, message) =
+                        log_entry(repo, sha, commit, format, opts.abbrev_commit, &decoration);
+                    if let Some(mut header) = header {
+                        if decorate != LogDecorate::No && !decoration.is_empty() {
+                            header.push_str(&format!(" ({decoration})"));
+                        }
+                        entry.push_str(&header);
+                        if *format == LogFormat::Oneline {
+                            entry.push(' ');
+                        } else {
+                            entry.push('\n');
+                            if let Some(graph) = &mut graph {
+                                entry.push_str(&graph.next_line().0);
+                            }
+                        }
+                    }
+
+                    missing_newline = !message.ends_with('\n');
+                    match &mut graph {
+                        Some(graph) => entry.push_str(&graph.show_message(&message)),
+                        None => entry.push_str(&message),
+                    }
+                    let empty =
+                        matches!(format, LogFormat::Format { template, .. } if template.is_empty());
+                    if use_terminator && !empty {
+                        if let (Some(graph), false) = (&mut graph, missing_newline) {
+                            entry.push_str(&graph.padding_line());
+                        }
+                        entry.push('\n');
+                    }
+                    write!(out, "{entry}")
+                })
+        }
+    };
+    // A closed pipe, e.g. when piping into `head`, is not an error
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            panic!("Failed to write: {e}");
+        }
+    }
+}
+
+/// The refs pointing at each commit, as `log` decorates them
+struct LogDecorations {
+    /// Full ref names by commit, `HEAD` first and then the refs in reverse
+    /// order, like git lists them
+    refs: HashMap<String, Vec<String>>,
+    /// The branch `HEAD` points at, if any
+    head_branch: Option<String>,
+    full: bool,
+}
+
+impl LogDecorations {
+    fn load(repo: &GitRepository, full: bool) -> Self {
+        let mut refs: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, sha) in ref_list(repo, "refs") {
+            // Annotated tags decorate the commit they point at
+            let mut shas = vec![sha.clone()];
+            shas.extend(object_peel_tags(repo, &sha).filter(|peeled| *peeled != sha));
+            for sha in shas {
+                refs.entry(sha).or_default().insert(0, name.clone());
+            }
+        }
+        if let Some(head) = ref_resolve(repo, "HEAD") {
+            refs.entry(head).or_default().insert(0, "HEAD".to_string());
+        }
+        LogDecorations {
+            refs,
+            head_branch: ref_symbolic(repo, "HEAD"),
+            full,
+        }
+    }
+
+    /// The decoration of a commit, e.g. `HEAD -> master, tag: v1`, or an
+    /// empty string
+    fn get(&self, sha: &str) -> String {
+        let names = match self.refs.get(sha) {
+            Some(names) => names,
+            None => return String::new(),
+        };
+        let show = |name: &str| match self.full {
+            true => name.to_string(),
+            false => ["refs/heads/", "refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|p| name.strip_prefix(p))
+                .unwrap_or(name)
+                .to_string(),
+        };
+
+        // `HEAD -> branch` stands for both when HEAD is on a decorated branch
+        let current = self.head_branch.as_ref().filter(|branch| {
+            branch.starts_with("refs/heads/")
+                && names.contains(branch)
+                && names.iter().any(|n| n == "HEAD")
+        });
+        let mut ret = Vec::new();
+        for name in names {
+            if Some(name) == current {
+                continue;
+            }
+            let mut deco = match name.starts_with("refs/tags/") {
+                true => format!("tag: {}", show(name)),
+                false => show(name),
+            };
+            if let (Some(current), "HEAD") = (current, name.as_str()) {
+                deco.push_str(&format!(" -> {}", show(current)));
+            }
+            ret.push(deco);
+        }
+        ret.join(", ")
+    }
+}
+
+/// Render one commit in one of the built-in formats or a template: the
+/// header line with its id, if the format has one, and the rest
+fn log_entry(
+    repo: &GitRepository,
+    sha: &str,
+    commit: &GitCommit,
+    format: &LogFormat,
+    abbrev: bool,
+    decoration: &str,
+) -> (Option<String>, String) {
+    let id = match abbrev {
+        true => object_abbrev(repo, sha, 7),
+        false => sha.to_string(),
+    };
+    let (subject, _) = message_split(&commit.message());
+    let person = |line: Option<String>| line.as_deref().and_then(ident_parse).unwrap_or_default();
+    let (an, ae, at, atz) = person(commit.author());
+    let (cn, ce, ct, ctz) = person(commit.committer());
+
+    match format {
+        LogFormat::Oneline => return (Some(id), subject),
+        LogFormat::Format { template, .. } => {
+            return (None, log_pretty(repo, sha, commit, template, decoration))
+        }
+        _ => {}
+    }
+    let mut ret = String::new();
+    let parents = commit.parents();
+    if parents.len() > 1 {
+        let abbrevs: Vec<String> = parents.iter().map(|p| object_abbrev(repo, p, 7)).collect();
+        ret.push_str(&format!("Merge: {}\n", abbrevs.join(" ")));
+    }
+    match format {
+        LogFormat::Short => ret.push_str(&format!("Author: {an} <{ae}>\n")),
+        LogFormat::Full => ret.push_str(&format!("Author: {an} <{ae}>\nCommit: {cn} <{ce}>\n")),
+        LogFormat::Fuller => ret.push_str(&format!(
+            "Author:     {an} <{ae}>\nAuthorDate: {}\nCommit:     {cn} <{ce}>\nCommitDate: {}\n",
+            date_format(at, atz, DateStyle::Default),
+            date_format(ct, ctz, DateStyle::Default)
+        )),
+        _ => ret.push_str(&format!(
+            "Author: {an} <{ae}>\nDate:   {}\n",
+            date_format(at, atz, DateStyle::Default)
+        )),
+    }
+
+    ret.push('\n');
+    let message = match format {
+        LogFormat::Short => subject,
+        _ => commit.message().trim_end().to_string(),
+    };
+    for line in message.lines() {
+        ret.push_str(&format!("    {line}\n"));
+    }
+    (Some(format!("commit {id}")), ret)
+}
+
+/// Split a commit message into its subject, the first paragraph joined into
+/// one line, and the body after it
+fn message_split(message: &str) -> (String, String) {
+    let mut lines = message.lines().skip_while(|l| l.trim().is_empty());
+    let subject: Vec<&str> = lines
+        .by_ref()
+        .take_while(|l| !l.trim().is_empty())
+        .map(|l| l.trim())
+        .collect();
+    let body: Vec<&str> = lines.skip_while(|l| l.trim().is_empty()).collect();
+    let mut body = body.join("\n");
+    if !body.is_empty() {
+        body.push('\n');
+    }
+    (subject.join(" "), body)
+}
+
+/// Expand the `%` placeholders of a `--pretty=format:` template
+fn log_pretty(
+    repo: &GitRepository,
+    sha: &str,
+    commit: &GitCommit,
+    template: &str,
+    decoration: &str,
+) -> String {
+    let person = |line: Option<String>| line.as_deref().and_then(ident_parse).unwrap_or_default();
+    let author = person(commit.author());
+    let committer = person(commit.committer());
+    let message = commit.message();
+    let (subject, body) = message_split(&message);
+    let parents = commit.parents();
+
+    let mut ret = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find('%') {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let mut chars = rest.chars();
+        let (expansion, used) = match chars.next() {
+            Some('%') => ("%".to_string(), 1),
+            Some('n') => ("\n".to_string(), 1),
+            Some('H') => (sha.to_string(), 1),
+            Some('h') => (object_abbrev(repo, sha, 7), 1),
+            Some('T') => (commit.tree(), 1),
+            Some('t') => (object_abbrev(repo, &commit.tree(), 7), 1),
+            Some('P') => (parents.join(" "), 1),
+            Some('p') => {
+                let abbrevs: Vec<String> =
+                    parents.iter().map(|p| object_abbrev(repo, p, 7)).collect();
+                (abbrevs.join(" "), 1)
+            }
+            Some('s') => (subject.clone(), 1),
+            Some('b') => (body.clone(), 1),
+            Some('B') => (message.clone(), 1),
+            Some('d') if !decoration.is_empty() => (format!(" ({decoration})"), 1),
+            Some('D') => (decoration.to_string(), 1),
+            Some('d') => (String::new(), 1),
+            Some('x') => match rest.get(1..3).map(|hex| u8::from_str_radix(hex, 16)) {
+                Some(Ok(byte)) => ((byte as char).to_string(), 3),
+                _ => ("%".to_string(), 0),
+            },
+            Some(role @ ('a' | 'c')) => {
+                let (name, email, secs, tz) = match role {
+                    'a' => &author,
+                    _ => &committer,
+                };
+                let style = |style| date_format(*secs, *tz, style);
+                match chars.next() {
+                    Some('n') => (name.clone(), 2),
+                    Some('e') => (email.clone(), 2),
+                    Some('l') => (email.split('@').next().unwrap_or_default().to_string(), 2),
+                    Some('d') => (style(DateStyle::Default), 2),
+                    Some('D') => (style(DateStyle::Rfc2822), 2),
+                    Some('t') => (style(DateStyle::Unix), 2),
+                    Some('i') => (style(DateStyle::Iso), 2),
+                    Some('I') => (style(DateStyle::IsoStrict), 2),
+                    Some('s') => (style(DateStyle::Short), 2),
+                    Some('r') => (style(DateStyle::Relative), 2),
+                    _ => (format!("%{role}"), 1),
+                }
+            }
+            // Unknown placeholders are kept as they are, like git does
+            _ => ("%".to_string(), 0),
+        };
+        ret.push_str(&expansion);
+        rest = &rest[used..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+/// Write the history as a Graphviz digraph
+fn log_graphviz(out: &mut impl Write, commits: &[(String, GitCommit)]) -> io::Result<()> {
+    writeln!(out, "digraph gitrlog{{")?;
+    writeln!(out, "  node[shape=rect]")?;
+    for (sha, commit) in commits {
+        let (subject, _) = message_split(&commit.message());
+        let subject = subject.replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "  c_{sha} [label=\"{}: {subject}\"]", &sha[0..8])?;
+        for parent in commit.parents() {
+            writeln!(out, "  c_{sha} -> c_{parent};")?;
+        }
+    }
+    writeln!(out, "}}")
+}
+
+/// Options for `pack-objects`
+#[derive(Default)]
+pub struct PackObjectsOptions {
+    /// Write the pack to stdout instead of `<base-name>-<checksum>.pack`
+    pub stdout: bool,
+    /// Number of preceding objects each object is tried against as base
+    pub window: usize,
+    /// Maximum length of a delta chain
+    pub depth: usize,
+}
+
+/// Read the objects listed on stdin, one `<sha> [<name>]` per line, and
+/// pack them
+pub fn cmd_pack_objects(repo: &GitRepository, base_name: Option<&str>, opts: &PackObjectsOptions) {
+    if opts.stdout == base_name.is_some() {
+        eprintln!("usage: git pack-objects --stdout [<options>] [< <ref-list> | < <object-list>]");
+        eprintln!(
+            "   or: git pack-objects [<options>] <base-name> [< <ref-list> | < <object-list>]"
+        );
+        process::exit(129);
+    }
+
+    // Paths need not be UTF-8, so the input is split as bytes
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .expect("Failed to read stdin");
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    for line in input.split(|&c| c == b'\n').filter(|l| !l.is_empty()) {
+        let space = line.iter().position(|&c| c == b' ').unwrap_or(line.len());
+        let (sha, name) = (&line[..space], line.get(space + 1..).unwrap_or_default());
+        let sha = match str::from_utf8(sha) {
+            Ok(sha) if repo.object_format.is_sha(sha) => sha,
+            _ => {
+                let line = String::from_utf8_lossy(line);
+                eprintln!("fatal: expected object ID, got garbage:\n {line}\n");
+                process::exit(128);
+            }
+        };
+        let sha = sha.to_lowercase();
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        let Some(obj) = pack_input_read(repo, &sha, name) else {
+            eprintln!("fatal: unable to read {sha}");
+            process::exit(128);
+        };
+        objects.push(obj);
+    }
+    pack_find_deltas(&mut objects, opts.window, opts.depth);
+
+    let base_name = match base_name {
+        Some(base_name) => base_name,
+        None => {
+            let mut out = io::stdout().lock();
+            pack_write(&mut out, &objects, repo.object_format);
+            out.flush().expect("Failed to write pack");
+            return;
+        }
+    };
+    let mut pack = Vec::new();
+    let (checksum, index) = pack_write(&mut pack, &objects, repo.object_format);
+    let checksum = sha_to_hex(&checksum);
+    for (ext, data) in [("pack", pack), ("idx", index.serialize())] {
+        pack_file_write(Path::new(&format!("{base_name}-{checksum}.{ext}")), &data);
+    }
+    println!("{checksum}");
+}
+
+/// Options for `index-pack`
+#[derive(Default)]
+pub struct IndexPackOptions {
+    /// Read the pack from stdin, storing it in the repository unless a
+    /// pack file is given
+    pub stdin: bool,
+    /// Complete a thin pack with delta bases from the object store
+    pub fix_thin: bool,
+    /// Where to write the index, by default next to the pack
+    pub index_file: Option<String>,
+}
+
+/// Build the index of a pack, see `IndexPackOptions`
+pub fn cmd_index_pack(
+    repo: Option<&GitRepository>,
+    pack_file: Option<&str>,
+    opts: &IndexPackOptions,
+) {
+    if !opts.stdin && pack_file.is_none() {
+        eprintln!("usage: git index-pack [-v] [-o <index-file>] [--keep | --keep=<msg>] [--[no-]rev-index] [--verify] [--strict] (<pack-file> | --stdin [--fix-thin] [<pack-file>])");
+        process::exit(129);
+    }
+    if opts.fix_thin && !opts.stdin {
+        eprintln!("fatal: the option '--fix-thin' requires '--stdin'");
+        process::exit(128);
+    }
+    if opts.stdin && repo.is_none() {
+        eprintln!("fatal: --stdin requires a git repository");
+        process::exit(128);
+    }
+    if let Some(name) = pack_file.filter(|_| opts.index_file.is_none()) {
+        if !name.ends_with(".pack") {
+            eprintln!("fatal: packfile name '{name}' does not end with '.pack'");
+            process::exit(128);
+        }
+    }
+
+    let mut pack = Vec::new();
+    let read = match (opts.stdin, pack_file) {
+        (true, _) => io::stdin().read_to_end(&mut pack).map(|_| ()),
+        (false, Some(name)) => fs::read(name).map(|data| pack = data),
+        (false, None) => unreachable!(),
+    };
+    if let Err(e) = read {
+        let name = pack_file.filter(|_| !opts.stdin).unwrap_or("<stdin>");
+        eprintln!(
+            "fatal: could not open '{name}' for reading: {}",
+            io_error_message(&e)
+        );
+        process::exit(128);
+    }
+
+    let fatal = |msg: String| -> ! {
+        eprintln!("fatal: {msg}");
+        process::exit(128);
+    };
+    let format = repo.map(|r| r.object_format).unwrap_or_default();
+    let lookup = |sha: &[u8]| match (opts.fix_thin, repo) {
+        (true, Some(repo)) => object_read_raw(repo, &sha_to_hex(sha))
+            .map(|(fmt, data)| (pack_type_from_name(&fmt), data)),
+        _ => None,
+    };
+    let (mut entries, _, bases) =
+        pack_resolve_thin(&pack, format, &lookup).unwrap_or_else(|e| fatal(e));
+    if !bases.is_empty() {
+        pack_append_objects(&mut pack, &bases, format);
+        (entries, _) = pack_resolve(&pack, format).unwrap_or_else(|e| fatal(e));
+    }
+    let count = u32::from_be_bytes(pack[8..12].try_into().unwrap()) as usize;
+    match count.checked_sub(entries.len()) {
+        Some(0) => {}
+        Some(unresolved) => fatal(format!("pack has {unresolved} unresolved deltas")),
+        None => fatal(format!("pack has more objects than its {count} entries")),
+    }
+
+    let checksum = pack[pack.len() - format.raw_len()..].to_vec();
+    let index = GitPackIndex::build(
+        2,
+        entries
+            .iter()
+            .map(|e| (e.sha.clone(), e.crc, e.offset))
+            .collect(),
+        &checksum,
+        format,
+    );
+    let checksum = sha_to_hex(&checksum);
+    let pack_path = match (pack_file, repo) {
+        (Some(name), _) => PathBuf::from(name),
+        (None, Some(repo)) => repo_file(repo, &format!("objects/pack/pack-{checksum}.pack"), true)
+            .expect("Failed to create pack directory"),
+        (None, None) => unreachable!(),
+    };
+    let index_path = match &opts.index_file {
+        Some(name) => PathBuf::from(name),
+        None => pack_path.with_extension("idx"),
+    };
+    let mut outputs = vec![(index_path, index.serialize())];
+    if opts.stdin {
+        outputs.push((pack_path, pack));
+    }
+    for (path, data) in outputs {
+        pack_file_write(&path, &data);
+    }
+
+    match opts.stdin {
+        true => println!("pack\t{checksum}"),
+        false => println!("{checksum}"),
+    }
+}
+
+/// Describe an I/O error like `strerror` does
+fn io_error_message(e: &io::Error) -> String {
+    let msg = e.to_string();
+    match msg.find(" (os error") {
+        Some(i) => msg[..i].to_string(),
+        None => msg,
+    }
+}
+
+/// Write a pack or index file, read-only since packs are never modified
+/// in place
+fn pack_file_write(path: &Path, data: &[u8]) {
+    if path.exists() {
+        fs::remove_file(path).expect("Failed to replace pack file");
+    }
+    if let Err(e) = fs::write(path, data) {
+        eprintln!(
+            "fatal: unable to write '{}': {}",
+            path.display(),
+            io_error_message(&e)
+        );
+        process::exit(128);
+    }
+    let mut perms = fs::metadata(path).unwrap().permissions();
+    perms.set_readonly(true);
+    fs::set_permissions(path, perms).expect("Failed to set pack permissions");
+}
+
+/// Options for `verify-pack`
+#[derive(Default)]
+pub struct VerifyPackOptions {
+    /// List every object with its type, sizes, offset and delta base
+    pub verbose: bool,
+    /// Only show the delta chain statistics
+    pub stat_only: bool,
+}
+
+/// Check packs against their indexes, exiting 1 if any is bad
+pub fn cmd_verify_pack(repo: Option<&GitRepository>, names: &[String], opts: &VerifyPackOptions) {
+    if names.is_empty() {
+        eprintln!("usage: git verify-pack [-v | --verbose] [-s | --stat-only] [--] <pack>.idx...");
+        process::exit(129);
+    }
+
+    let format = repo.map(|r| r.object_format).unwrap_or_default();
+    let mut ok = true;
+    for name in names {
+        let base = name
+            .strip_suffix(".idx")
+            .or_else(|| name.strip_suffix(".pack"))
+            .unwrap_or(name);
+        let result = match (
+            fs::read(format!("{base}.idx")),
+            fs::read(format!("{base}.pack")),
+        ) {
+            (Ok(index), Ok(pack)) => verify_pack(&GitPackIndex::parse(&index, format), &pack, opts),
+            _ => Err(format!("Cannot open existing pack file '{name}'")),
+        };
+        if let Err(e) = &result {
+            eprintln!("fatal: {e}");
+            ok = false;
+        }
+        match result {
+            _ if !opts.verbose && !opts.stat_only => {}
+            Err(_) => println!("{base}.pack: bad"),
+            Ok(()) if !opts.stat_only => println!("{base}.pack: ok"),
+            Ok(()) => {}
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn verify_pack(index: &GitPackIndex, pack: &[u8], opts: &VerifyPackOptions) -> Result<(), String> {
+    let (entries, missing) = pack_resolve(pack, index.format)?;
+    if !missing.is_empty() {
+        return Err(format!(
+            "pack has {} unresolved deltas",
+            index.len().saturating_sub(entries.len())
+        ));
+    }
+    if pack[pack.len() - index.pack_checksum.len()..] != index.pack_checksum[..] {
+        return Err("packfile does not match index".to_string());
+    }
+    if entries.len() != index.len() {
+        return Err("pack and index disagree on the object count".to_string());
+    }
+    for e in &entries {
+        let found = index.find(&e.sha).filter(|&i| {
+            index.offsets[i] == e.offset && (index.crcs.is_empty() || index.crcs[i] == e.crc)
+        });
+        if found.is_none() {
+            return Err(format!("index mismatch for object {}", sha_to_hex(&e.sha)));
+        }
+    }
+
+    let mut chains: Vec<usize> = Vec::new();
+    for e in &entries {
+        if chains.len() <= e.depth {
+            chains.resize(e.depth + 1, 0);
+        }
+        chains[e.depth] += 1;
+        if !opts.verbose || opts.stat_only {
+            continue;
+        }
+        let kind = str::from_utf8(pack_type_name(e.kind)).unwrap();
+        let line = format!(
+            "{} {kind:<6} {} {} {}",
+            sha_to_hex(&e.sha),
+            e.entry.size,
+            e.packed_size,
+            e.offset
+        );
+        match &e.base {
+            Some(base) => println!("{line} {} {}", e.depth, sha_to_hex(base)),
+            None => println!("{line}"),
+        }
+    }
+    if opts.verbose || opts.stat_only {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        for (depth, &n) in chains.iter().enumerate().filter(|(_, &n)| n > 0) {
+            match depth {
+                0 => println!("non delta: {n} object{}", plural(n)),
+                _ => println!("chain length = {depth}: {n} object{}", plural(n)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every object reachable from the refs, `HEAD`, the reflogs and the
+/// index, with the path it was found at, commits first as `rev-list
+/// --objects` lists them
+fn reachable_objects(repo: &GitRepository) -> Vec<(String, Vec<u8>)> {
+    let mut tips: Vec<String> = ref_list(repo, "refs")
+        .into_iter()
+        .map(|(_, sha)| sha)
+        .collect();
+    tips.extend(ref_resolve(repo, "HEAD"));
+    for name in reflog_list(repo) {
+        for entry in reflog_read(repo, &name) {
+            tips.extend(
+                [entry.old, entry.new]
+                    .into_iter()
+                    .filter(|s| !sha_is_null(s)),
+            );
+        }
+    }
+
+    // Peel tags down to the commits to walk and the trees and blobs
+    // tagged or referenced directly
+    let mut seen = HashSet::new();
+    let mut tags = Vec::new();
+    let mut roots = Vec::new();
+    let mut walk = RevWalk::new(repo);
+    for tip in tips {
+        let mut sha = tip;
+        while let Some(obj) = object_read(repo, &sha) {
+            let Some(tag) = obj.as_any().downcast_ref::<GitTag>() else {
+                match &obj.fmt()[..] {
+                    b"commit" => walk.push(&sha),
+                    fmt => roots.push((sha, fmt == b"tree")),
+                }
+                break;
+            };
+            if !seen.insert(sha.clone()) {
+                break;
+            }
+            tags.push((sha, tag.tag().unwrap_or_default().into_bytes()));
+            match tag.object() {
+                Some(object) => sha = object,
+                None => break,
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    for (sha, commit) in walk {
+        roots.push((commit.tree(), true));
+        ret.push((sha, Vec::new()));
+    }
+    ret.extend(tags);
+    for (sha, is_tree) in roots {
+        if !is_tree {
+            if seen.insert(sha.clone()) {
+                ret.push((sha, Vec::new()));
+            }
+            continue;
+        }
+        tree_objects(repo, &sha, b"", &mut |sha, path| {
+            let new = seen.insert(sha.to_string());
+            if new {
+                ret.push((sha.to_string(), path.to_vec()));
+            }
+            new
+        });
+    }
+    for entry in index_read(repo).entries {
+        if !entry.intent_to_add && seen.insert(entry.sha.clone()) {
+            ret.push((entry.sha, entry.name));
+        }
+    }
+
+    ret
+}
+
+/// Read an object to be packed, with the path it was found at
+fn pack_input_read(repo: &GitRepository, sha: &str, name: &[u8]) -> Option<GitPackObject> {
+    let (fmt, data) = object_read_raw(repo, sha)?;
+    Some(GitPackObject {
+        sha: sha_from_hex(sha),
+        kind: pack_type_from_name(&fmt),
+        data: Rc::new(data),
+        name_hash: pack_name_hash(name),
+        delta: None,
+        depth: 0,
+    })
+}
+
+/// Options for `repack`
+#[derive(Default)]
+pub struct RepackOptions {
+    /// Pack everything reachable into a single pack instead of only the
+    /// loose objects
+    pub all: bool,
+    /// With `all` and `delete`, turn the unreachable objects of the packs
+    /// being removed into loose objects, if the pack is newer than this
+    /// time, so that `prune` can expire them later
+    pub unpack_unreachable: Option<i64>,
+    /// Remove the packs and loose objects made redundant by the new pack
+    pub delete: bool,
+    /// Number of objects each object is tried against as a delta base
+    pub window: usize,
+    /// Maximum length of a delta chain
+    pub depth: usize,
+    pub quiet: bool,
+}
+
+/// Pack the reachable objects into `objects/pack`, see `RepackOptions`
+pub fn cmd_repack(repo: &GitRepository, opts: &RepackOptions) {
+    let packs = pack_list(repo);
+    let kept = |pack: &GitPack| pack.path.with_extension("keep").exists();
+    let in_pack = |pack: &GitPack, sha: &str| pack.index.find(&sha_from_hex(sha)).is_some();
+
+    let reachable = reachable_objects(repo);
+    let mut objects = Vec::new();
+    for (sha, name) in &reachable {
+        let wanted = match opts.all {
+            true => !packs.iter().any(|p| kept(p) && in_pack(p, sha)),
+            false => !packs.iter().any(|p| in_pack(p, sha)),
+        };
+        if wanted {
+            objects.extend(pack_input_read(repo, sha, name));
+        }
+    }
+
+    let dir = repo_dir(repo, "objects/pack", true).unwrap();
+    let mut new_pack = None;
+    if objects.is_empty() {
+        if !opts.quiet {
+            println!("Nothing new to pack.");
+        }
+    } else {
+        pack_find_deltas(&mut objects, opts.window, opts.depth);
+        let mut pack = Vec::new();
+        let (checksum, index) = pack_write(&mut pack, &objects, repo.object_format);
+        let base = dir.join(format!("pack-{}", sha_to_hex(&checksum)));
+        // The index goes last, readers only look for packs that have one
+        pack_file_write(&base.with_extension("pack"), &pack);
+        pack_file_write(&base.with_extension("idx"), &index.serialize());
+        new_pack = Some(base.with_extension("pack"));
+    }
+
+    if opts.delete && opts.all {
+        let reachable: HashSet<&str> = reachable.iter().map(|(sha, _)| sha.as_str()).collect();
+        for pack in packs.iter() {
+            if kept(pack) || new_pack.as_ref() == Some(&pack.path) {
+                continue;
+            }
+            if let Some(expire) = opts.unpack_unreachable {
+                pack_loosen_unreachable(repo, pack, &reachable, expire);
+            }
+        }
+        for pack in packs.iter() {
+            if kept(pack) || new_pack.as_ref() == Some(&pack.path) {
+                continue;
+            }
+            for ext in ["idx", "pack"] {
+                fs::remove_file(pack.path.with_extension(ext)).expect("Failed to remove pack");
+            }
+        }
+    }
+    pack_list_reset();
+    if opts.delete {
+        prune_packed(repo, false);
+    }
+}
+
+/// Write the unreachable objects of a pack that is about to be removed as
+/// loose objects, keeping the pack's modification time as theirs, unless
+/// the pack is older than `expire`
+fn pack_loosen_unreachable(
+    repo: &GitRepository,
+    pack: &GitPack,
+    reachable: &HashSet<&str>,
+    expire: i64,
+) {
+    let mtime = fs::metadata(&pack.path)
+        .and_then(|m| m.modified())
+        .expect("Failed to stat pack");
+    if file_time(mtime) <= expire {
+        return;
+    }
+    for i in 0..pack.index.len() {
+        let sha = sha_to_hex(pack.index.sha(i));
+        let path = repo_path(repo, &object_loose_name(&sha));
+        if reachable.contains(sha.as_str()) || path.exists() {
+            continue;
+        }
+        let (fmt, data) = object_read_raw(repo, &sha).expect("Failed to read packed object");
+        object_write_raw(Some(repo), &fmt, &data);
+        File::open(&path)
+            .and_then(|f| f.set_modified(mtime))
+            .expect("Failed to set object time");
+    }
+}
+
+/// Seconds since the epoch of a file time
+fn file_time(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Remove the loose objects that are also in a pack, and the fan-out
+/// directories left empty. A dry run only lists them.
+fn prune_packed(repo: &GitRepository, dry_run: bool) {
+    let packs = pack_list(repo);
+    for sha in object_loose_list_or_exit(repo) {
+        let raw = sha_from_hex(&sha);
+        if !packs.iter().any(|p| p.index.find(&raw).is_some()) {
+            continue;
+        }
+        let path = repo_path(repo, &object_loose_name(&sha));
+        match dry_run {
+            true => println!("rm -f {}", path.display()),
+            false => fs::remove_file(&path).expect("Failed to remove loose object"),
+        }
+    }
+    if !dry_run {
+        prune_object_dirs(repo);
+    }
+}
+
+/// The loose objects, exiting when they can't be listed
+fn object_loose_list_or_exit(repo: &GitRepository) -> Vec<String> {
+    object_loose_list(repo).unwrap_or_else(|e| {
+        eprintln!("fatal: {e}");
+        process::exit(128);
+    })
+}
+
+/// Remove the empty fan-out directories under `objects`
+fn prune_object_dirs(repo: &GitRepository) {
+    for i in 0..256 {
+        // Only succeeds when the directory is empty
+        let _ = fs::remove_dir(repo_path(repo, &format!("objects/{i:02x}")));
+    }
+}
+
+/// Options for `prune`
+#[derive(Default)]
+pub struct PruneOptions {
+    /// List what would be removed without removing anything
+    pub dry_run: bool,
+    /// List the objects removed
+    pub verbose: bool,
+    /// Only remove objects last modified at or before this time, all of
+    /// them by default
+    pub expire: Option<i64>,
+}
+
+/// Remove the loose objects that cannot be reached from the refs, reflogs
+/// or the index, see `PruneOptions`
+pub fn cmd_prune(repo: &GitRepository, opts: &PruneOptions) {
+    let reachable: HashSet<String> = reachable_objects(repo)
+        .into_iter()
+        .map(|(sha, _)| sha)
+        .collect();
+    for sha in object_loose_list_or_exit(repo) {
+        if reachable.contains(&sha) {
+            continue;
+        }
+        let path = repo_path(repo, &object_loose_name(&sha));
+        if let Some(expire) = opts.expire {
+            let mtime = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .expect("Failed to stat loose object");
+            if file_time(mtime) > expire {
+                continue;
+            }
+        }
+        if opts.dry_run || opts.verbose {
+            let fmt = object_read_raw(repo, &sha)
+                .map(|(fmt, _)| String::from_utf8_lossy(&fmt).to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            println!("{sha} {fmt}");
+        }
+        if !opts.dry_run {
+            fs::remove_file(&path).expect("Failed to remove loose object");
+        }
+    }
+    prune_packed(repo, opts.dry_run);
+}
+
+/// Move refs into the `packed-refs` file: all of them, or only tags and
+/// refs that are packed already. With `prune` the loose files are removed.
+pub fn cmd_pack_refs(repo: &GitRepository, all: bool, prune: bool) {
+    let mut packed = packed_refs_read(repo);
+    let mut loose = Vec::new();
+    for (name, sha) in ref_list(repo, "refs") {
+        let wanted = all || name.starts_with("refs/tags/") || packed.contains_key(&name);
+        if !wanted || ref_symbolic(repo, &name).is_some() {
+            continue;
+        }
+        // Refs to missing objects are broken, packing them would hide it
+        let Some(peeled) = object_peel_tags(repo, &sha) else {
+            continue;
+        };
+        let peeled = Some(peeled).filter(|peeled| *peeled != sha);
+        packed.insert(
+            name.clone(),
+            PackedRef {
+                sha: sha.clone(),
+                peeled,
+            },
+        );
+        loose.push((name, sha));
+    }
+    packed_refs_write(repo, &packed);
+
+    if prune {
+        for (name, sha) in loose {
+            ref_delete_loose(repo, &name, &sha);
+        }
+    }
+}
+
+/// Options for `gc`
+#[derive(Default)]
+pub struct GcOptions {
+    /// Only do anything when there are too many loose objects or packs,
+    /// see `gc.auto` and `gc.autoPackLimit`
+    pub auto: bool,
+    /// Look harder for deltas, at the cost of time
+    pub aggressive: bool,
+    /// Prune unreachable loose objects older than this date, by default
+    /// `gc.pruneExpire` or two weeks ago; `never` keeps them
+    pub prune: Option<String>,
+    pub quiet: bool,
+}
+
+/// Pack refs, repack everything reachable into one pack and prune the
+/// old unreachable objects
+pub fn cmd_gc(repo: &GitRepository, opts: &GcOptions) {
+    let now = file_time(SystemTime::now());
+    let prune = opts
+        .prune
+        .clone()
+        .or_else(|| repo.config.get("gc.pruneExpire"))
+        .unwrap_or_else(|| "2.weeks.ago".to_string());
+    let Some(expire) = date_expiry(&prune, now) else {
+        eprintln!("fatal: failed to parse prune expiry value {prune}");
+        process::exit(128);
+    };
+
+    if opts.auto {
+        if !gc_auto_needed(repo) {
+            return;
+        }
+        if !opts.quiet {
+            eprintln!("Auto packing the repository for optimum performance.");
+            eprintln!("See \"git help gc\" for manual housekeeping.");
+        }
+    }
+
+    cmd_pack_refs(repo, true, true);
+    let (window, depth) = match opts.aggressive {
+        true => (250, 50),
+        false => (10, 50),
+    };
+    cmd_repack(
+        repo,
+        &RepackOptions {
+            all: true,
+            // Unreachable objects that would be pruned right away are
+            // simply dropped with their pack
+            unpack_unreachable: Some(expire).filter(|expire| *expire < now),
+            delete: true,
+            window,
+            depth,
+            quiet: opts.quiet,
+        },
+    );
+    if expire != i64::MIN {
+        cmd_prune(
+            repo,
+            &PruneOptions {
+                expire: Some(expire),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+/// Whether `gc --auto` has work to do: more loose objects than `gc.auto`,
+/// estimated from the `objects/17` fan-out directory like git does, or more
+/// packs than `gc.autoPackLimit`
+fn gc_auto_needed(repo: &GitRepository) -> bool {
+    let setting = |name: &str, default: i64| {
+        repo.config
+            .get(name)
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .unwrap_or(default)
+    };
+    let auto = setting("gc.auto", 6700);
+    if auto <= 0 {
+        return false;
+    }
+    let limit = (auto + 255) / 256;
+    let loose = match fs::read_dir(repo_path(repo, "objects/17")) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().len() == repo.object_format.hex_len() - 2)
+            .count() as i64,
+        Err(_) => 0,
+    };
+    let pack_limit = setting("gc.autoPackLimit", 50);
+    let packs = pack_list(repo)
+        .iter()
+        .filter(|p| !p.path.with_extension("keep").exists())
+        .count() as i64;
+
+    loose > limit || (pack_limit > 0 && packs > pack_limit)
+}
+
+/// Options for `fsck`
+#[derive(Default)]
+pub struct FsckOptions {
+    /// Only check that the objects reachable from the refs are there,
+    /// without hashing objects or checking their contents
+    pub connectivity_only: bool,
+    /// List every unreachable object, not only the dangling ones
+    pub unreachable: bool,
+    /// Write dangling commits to `lost-found/commit` and other dangling
+    /// objects to `lost-found/other`, leaving reflogs out of the roots
+    pub lost_found: bool,
+}
+
+/// Exit status bits of `fsck`, as in git
+const FSCK_ERROR_OBJECT: i32 = 1;
+const FSCK_ERROR_REACHABLE: i32 = 2;
+const FSCK_ERROR_PACK: i32 = 4;
+
+/// Type and links of each object that could be read
+type FsckObjects = HashMap<String, (String, Vec<(String, &'static str)>)>;
+
+/// Verify the objects of a repository and their connectivity, see
+/// `FsckOptions`. Objects that are not reachable from the refs, `HEAD`, the
+/// reflogs or the index and that no other object points to are reported as
+/// dangling.
+pub fn cmd_fsck(repo: &GitRepository, opts: &FsckOptions) {
+    let errors = fsck(
+        repo,
+        opts,
+        &mut io::stdout().lock(),
+        &mut io::stderr().lock(),
+    );
+    match errors {
+        Ok(0) => {}
+        Ok(errors) => process::exit(errors),
+        // A closed pipe, e.g. when piping into `head`, is not an error
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => panic!("Failed to write: {e}"),
+    }
+}
+
+/// The checks of `cmd_fsck`, writing the findings to `out` and the
+/// problems to `err`. Returns the exit status bits.
+fn fsck(
+    repo: &GitRepository,
+    opts: &FsckOptions,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> io::Result<i32> {
+    let mut errors = 0;
+    let mut objects = FsckObjects::new();
+    // Objects that are there but cannot be used are missing, without
+    // links to them being broken
+    let mut corrupt = HashSet::new();
+
+    let loose = match object_loose_list(repo) {
+        Ok(loose) => loose,
+        Err(e) => {
+            writeln!(err, "error: {e}")?;
+            errors |= FSCK_ERROR_OBJECT;
+            Vec::new()
+        }
+    };
+    for sha in loose {
+        let path = repo_path(repo, &object_loose_name(&sha));
+        let object = fs::read(&path)
+            .map_err(|e| io_error_message(&e))
+            .and_then(|raw| object_loose_parse(&raw));
+        let (fmt, data) = match object {
+            Ok(object) => object,
+            Err(e) => {
+                writeln!(err, "error: {e}")?;
+                writeln!(
+                    err,
+                    "error: {sha}: object corrupt or missing: {}",
+                    path.display()
+                )?;
+                errors |= FSCK_ERROR_OBJECT;
+                corrupt.insert(sha);
+                continue;
+            }
+        };
+        if !matches!(&fmt[..], b"blob" | b"tree" | b"commit" | b"tag") {
+            writeln!(
+                err,
+                "error: {sha}: object is of unknown type '{}': {}",
+                String::from_utf8_lossy(&fmt),
+                path.display()
+            )?;
+            errors |= FSCK_ERROR_OBJECT;
+            continue;
+        }
+        if !opts.connectivity_only && object_sha(repo.object_format, &fmt, &data) != sha {
+            writeln!(
+                err,
+                "error: {sha}: hash-path mismatch, found at: {}",
+                path.display()
+            )?;
+            errors |= FSCK_ERROR_OBJECT;
+            continue;
+        }
+        errors |= fsck_check(repo, &mut objects, &sha, &fmt, &data, opts, err)?;
+    }
+
+    for pack in pack_list(repo).iter() {
+        if !opts.connectivity_only {
+            if let Err(e) = fsck_pack(pack) {
+                writeln!(err, "error: {}: {e}", pack.path.display())?;
+                errors |= FSCK_ERROR_PACK;
+                corrupt.extend((0..pack.index.len()).map(|i| sha_to_hex(pack.index.sha(i))));
+                continue;
+            }
+        }
+        for (i, &offset) in pack.index.offsets.iter().enumerate() {
+            let sha = sha_to_hex(pack.index.sha(i));
+            if !objects.contains_key(&sha) {
+                let (kind, data) = pack_unpack(repo, pack, offset);
+                errors |= fsck_check(
+                    repo,
+                    &mut objects,
+                    &sha,
+                    pack_type_name(kind),
+                    &data,
+                    opts,
+                    err,
+                )?;
+            }
+        }
+    }
+
+    // Objects of the wrong type where others point to them
+    let mut names: Vec<&String> = objects.keys().collect();
+    names.sort();
+    for sha in names {
+        for (link, expected) in &objects[sha].1 {
+            match objects.get(link) {
+                Some((actual, _)) if actual != expected => {
+                    writeln!(
+                        err,
+                        "error: object {link} is a {actual}, not a {expected}, in {sha}"
+                    )?;
+                    errors |= FSCK_ERROR_OBJECT;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Roots of the walk
+    let mut todo = Vec::new();
+    let refs = ref_list(repo, "refs");
+    let mut heads: Vec<(String, String)> = refs.clone();
+    match ref_resolve(repo, "HEAD") {
+        Some(sha) => heads.push(("HEAD".to_string(), sha)),
+        None => {
+            if let Some(target) = ref_symbolic(repo, "HEAD") {
+                let branch = target.strip_prefix("refs/heads/").unwrap_or(&target);
+                writeln!(err, "notice: HEAD points to an unborn branch ({branch})")?;
+            }
+        }
+    }
+    let present = |sha: &str| objects.contains_key(sha) || corrupt.contains(sha);
+    for (name, sha) in heads {
+        match present(&sha) {
+            true => todo.push((sha, "object")),
+            false => {
+                writeln!(err, "error: {name}: invalid sha1 pointer {sha}")?;
+                errors |= FSCK_ERROR_REACHABLE;
+            }
+        }
+    }
+    if refs.is_empty() {
+        writeln!(err, "notice: No default references")?;
+    }
+    if !opts.lost_found {
+        for name in reflog_list(repo) {
+            for entry in reflog_read(repo, &name) {
+                for sha in [entry.old, entry.new] {
+                    if sha_is_null(&sha) {
+                        continue;
+                    }
+                    match present(&sha) {
+                        true => todo.push((sha, "object")),
+                        false => {
+                            writeln!(err, "error: {name}: invalid reflog entry {sha}")?;
+                            errors |= FSCK_ERROR_REACHABLE;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // A broken index is reported like a broken pack, not fatal
+    let path = repo_path(repo, "index");
+    let index = match fs::read(&path) {
+        Ok(raw) => GitIndex::parse(&raw, repo.object_format),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(GitIndex::default()),
+        Err(e) => Err(io_error_message(&e)),
+    };
+    let index = match index {
+        Ok(index) => index,
+        Err(e) => {
+            writeln!(err, "error: {}: {e}", path.display())?;
+            errors |= FSCK_ERROR_OBJECT;
+            GitIndex::default()
+        }
+    };
+
+    let mut missing = HashMap::new();
+    for entry in index.entries {
+        if entry.mode == 0o160000 || entry.intent_to_add {
+            continue;
+        }
+        todo.push((entry.sha, "blob"));
+    }
+
+    let mut reachable = HashSet::new();
+    while let Some((sha, expected)) = todo.pop() {
+        if !reachable.insert(sha.clone()) {
+            continue;
+        }
+        let Some((kind, links)) = objects.get(&sha) else {
+            missing.entry(sha).or_insert(expected);
+            continue;
+        };
+        for (link, expected) in links {
+            match objects.contains_key(link) {
+                true => todo.push((link.clone(), *expected)),
+                false => {
+                    if !corrupt.contains(link) {
+                        writeln!(out, "broken link from {kind:>7} {sha}")?;
+                        writeln!(out, "              to {expected:>7} {link}")?;
+                    }
+                    missing.insert(link.clone(), expected);
+                }
+            }
+        }
+    }
+    if !missing.is_empty() {
+        errors |= FSCK_ERROR_REACHABLE;
+    }
+
+    // Dangling objects are the unreachable ones nothing else points to
+    let referenced: HashSet<&str> = objects
+        .values()
+        .flat_map(|(_, links)| links.iter().map(|(sha, _)| sha.as_str()))
+        .collect();
+    let mut report: Vec<(&str, String)> = missing
+        .iter()
+        .map(|(sha, kind)| (sha.as_str(), format!("missing {kind} {sha}")))
+        .collect();
+    for (sha, (kind, _)) in &objects {
+        if reachable.contains(sha) {
+            continue;
+        }
+        if opts.unreachable {
+            report.push((sha, format!("unreachable {kind} {sha}")));
+        }
+        if referenced.contains(sha.as_str()) {
+            continue;
+        }
+        if !opts.unreachable {
+            report.push((sha, format!("dangling {kind} {sha}")));
+        }
+        if opts.lost_found {
+            fsck_lost_found(repo, sha, kind);
+        }
+    }
+    report.sort();
+    for (_, line) in report {
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(errors)
+}
+
+/// Check the contents of an object unless only connectivity is checked,
+/// reporting problems, and note its links. Returns the error bits.
+fn fsck_check(
+    repo: &GitRepository,
+    objects: &mut FsckObjects,
+    sha: &str,
+    fmt: &[u8],
+    data: &[u8],
+    opts: &FsckOptions,
+    err: &mut impl Write,
+) -> io::Result<i32> {
+    let kind = String::from_utf8_lossy(fmt).to_string();
+    let mut errors = 0;
+    if !opts.connectivity_only {
+        for msg in fsck_object(fmt, data, repo.object_format) {
+            let level = match msg.level {
+                FsckLevel::Error => "error",
+                FsckLevel::Warning => "warning",
+            };
+            writeln!(err, "{level} in {kind} {sha}: {msg}")?;
+            if msg.level == FsckLevel::Error {
+                errors |= FSCK_ERROR_OBJECT;
+            }
+        }
+    }
+    objects.insert(
+        sha.to_string(),
+        (kind, fsck_links(fmt, data, repo.object_format)),
+    );
+    Ok(errors)
+}
+
+/// Re-hash every object of a pack and compare it with the pack index
+fn fsck_pack(pack: &GitPack) -> Result<(), String> {
+    let raw = fs::read(&pack.path).map_err(|e| io_error_message(&e))?;
+    let (resolved, missing) = pack_resolve(&raw, pack.index.format)?;
+    let matches = missing.is_empty()
+        && raw[raw.len() - pack.index.pack_checksum.len()..] == pack.index.pack_checksum
+        && resolved.len() == pack.index.len()
+        && resolved.iter().all(|r| {
+            let i = pack.index.find(&r.sha);
+            i.is_some_and(|i| pack.index.offsets[i] == r.offset)
+        });
+    match matches {
+        true => Ok(()),
+        false => Err("pack does not match its index".to_string()),
+    }
+}
+
+/// Save a dangling object under `lost-found`: the id of a commit, the
+/// contents of a blob
+fn fsck_lost_found(repo: &GitRepository, sha: &str, kind: &str) {
+    let dir = match kind {
+        "commit" => "lost-found/commit",
+        _ => "lost-found/other",
+    };
+    let path = repo_dir(repo, dir, true).unwrap().join(sha);
+    let data = match kind {
+        "blob" => object_read_raw(repo, sha).map(|(_, data)| data).unwrap(),
+        _ => format!("{sha}\n").into_bytes(),
+    };
+    fs::write(path, data).expect("Failed to write to lost-found");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::repo::repo_scratch;
+
+    fn blob(repo: &GitRepository, data: &str) -> String {
+        let obj = Box::new(GitBlob {
+            fmt: b"blob".to_vec(),
+            blobdata: data.as_bytes().to_vec(),
+        });
+        object_write(obj, Some(repo.clone()))
+    }
+
+    #[test]
+    fn test_ls_tree() {
+        let repo = repo_scratch("ls-tree");
+        let (one, two) = (blob(&repo, "1"), blob(&repo, "2"));
+        let mut index = GitIndex::default();
+        for (name, sha) in [("a", &one), ("dir/sub/c", &two), ("q\"x", &one)] {
+            index.add(GitIndexEntry {
+                mode: 0o100644,
+                sha: sha.clone(),
+                name: name.as_bytes().to_vec(),
+                ..Default::default()
+            });
+        }
+        let tree = index_write_tree(&repo, &index);
+        let dir = rev_parse(&repo, &format!("{tree}:dir")).unwrap();
+        let sub = rev_parse(&repo, &format!("{tree}:dir/sub")).unwrap();
+
+        let list = |opts: &LsTreeOptions| {
+            let mut out = Vec::new();
+            ls_tree_write(&repo, &mut out, &tree, opts).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            list(&LsTreeOptions::default()),
+            format!(
+                "100644 blob {one}\ta\n040000 tree {dir}\tdir\n100644 blob {one}\t\"q\\\"x\"\n"
+            )
+        );
+        let recursive = LsTreeOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&recursive),
+            format!(
+                "100644 blob {one}\ta\n100644 blob {two}\tdir/sub/c\n100644 blob {one}\t\"q\\\"x\"\n"
+            )
+        );
+        let trees = LsTreeOptions {
+            show_trees: true,
+            name_only: true,
+            ..recursive
+        };
+        assert_eq!(list(&trees), "a\ndir\ndir/sub\ndir/sub/c\n\"q\\\"x\"\n");
+        // Trees are only shown without -r, or with -t
+        let name_only = LsTreeOptions {
+            name_only: true,
+            ..Default::default()
+        };
+        assert_eq!(list(&name_only), "a\ndir\n\"q\\\"x\"\n");
+        let nul = LsTreeOptions {
+            recursive: true,
+            name_only: true,
+            null_terminated: true,
+            ..Default::default()
+        };
+        assert_eq!(list(&nul), "a\0dir/sub/c\0q\"x\0");
+        let trees = LsTreeOptions {
+            name_only: false,
+            ..trees
+        };
+        assert!(list(&trees).contains(&format!("040000 tree {sub}\tdir/sub\n")));
+    }
+
+    #[test]
+    fn test_object_find_names() {
+        let repo = repo_scratch("object-find-names");
+        let a = blob(&repo, "a");
+        let b = blob(&repo, "b");
+        ref_update(&repo, "refs/heads/master", &a, None, None);
+        ref_update(&repo, "refs/heads/topic", &a, None, None);
+        ref_update(&repo, "refs/tags/topic", &b, None, None);
+
+        assert_eq!(object_find(&repo, "HEAD", None, true), Some(a.clone()));
+        assert_eq!(object_find(&repo, "master", None, true), Some(a.clone()));
+        // Tags take precedence over branches
+        assert_eq!(object_find(&repo, "topic", None, true), Some(b.clone()));
+        assert_eq!(
+            object_find(&repo, "heads/topic", None, true),
+            Some(a.clone())
+        );
+        assert_eq!(object_find(&repo, &b[..7], None, true), Some(b.clone()));
+        assert_eq!(object_find(&repo, "nope", None, true), None);
+        assert_eq!(
+            object_find(&repo, "HEAD", Some(ObjectType::Tree), true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ls_files() {
+        let repo = repo_scratch("ls-files");
+        let mut index = crate::git::index::GitIndex::default();
+        for (name, data) in [("a.txt", "a\n"), ("src/b.rs", "b\n"), ("gone", "g\n")] {
+            let path = repo.worktree.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, data).unwrap();
+            let mut entry = GitIndexEntry {
+                mode: 0o100644,
+                sha: crate::git::worktree::worktree_hash(&repo, name.as_bytes(), true),
+                name: name.as_bytes().to_vec(),
+                ..Default::default()
+            };
+            crate::git::worktree::worktree_stat(&mut entry, &fs::metadata(&path).unwrap());
+            index.add(entry);
+        }
+        crate::git::index::index_write(&repo, &index);
+        fs::remove_file(repo.worktree.join("gone")).unwrap();
+        fs::write(repo.worktree.join("a.txt"), "changed\n").unwrap();
+        fs::write(repo.worktree.join("new"), "n\n").unwrap();
+
+        let list = |specs: &[&str], opts: &LsFilesOptions| -> Vec<(LsFilesKind, Vec<u8>)> {
+            let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+            ls_files(&repo, &specs, opts)
+                .into_iter()
+                .map(|i| (i.kind, i.path))
+                .collect()
+        };
+        let cached = list(&[], &LsFilesOptions::default());
+        assert_eq!(cached.len(), 3);
+        assert_eq!(cached[2], (LsFilesKind::Cached, b"src/b.rs".to_vec()));
+        assert_eq!(list(&["src"], &LsFilesOptions::default()).len(), 1);
+
+        let opts = LsFilesOptions {
+            deleted: true,
+            modified: true,
+            others: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&[], &opts),
+            vec![
+                (LsFilesKind::Other, b"new".to_vec()),
+                (LsFilesKind::Modified, b"a.txt".to_vec()),
+                (LsFilesKind::Deleted, b"gone".to_vec()),
+                (LsFilesKind::Modified, b"gone".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_changes() {
+        let repo = repo_scratch("add-changes");
+        let mut index = GitIndex::default();
+        fs::create_dir_all(repo.worktree.join("src")).unwrap();
+        for name in ["tracked", "src/gone"] {
+            fs::write(repo.worktree.join(name), name).unwrap();
+            index.add(crate::git::worktree::worktree_entry(
+                &repo,
+                name.as_bytes(),
+                None,
+                true,
+            ));
+        }
+        fs::remove_file(repo.worktree.join("src/gone")).unwrap();
+        fs::write(repo.worktree.join("src/new"), "new").unwrap();
+        fs::write(repo.worktree.join("skip.log"), "log").unwrap();
+        fs::write(repo.worktree.join(".gitignore"), "*.log\n").unwrap();
+
+        let specs = |s: &[&str]| -> Vec<String> { s.iter().map(|s| s.to_string()).collect() };
+        let (changes, ignored, unmatched) =
+            add_changes(&repo, &index, &specs(&["src"]), &AddOptions::default());
+        assert_eq!(
+            changes,
+            vec![
+                AddChange::Remove(b"src/gone".to_vec()),
+                AddChange::Add(b"src/new".to_vec()),
+            ]
+        );
+        assert!(ignored.is_empty() && unmatched.is_empty());
+
+        let update = AddOptions {
+            update: true,
+            ..Default::default()
+        };
+        let (changes, _, _) = add_changes(&repo, &index, &[], &update);
+        assert_eq!(changes, vec![AddChange::Remove(b"src/gone".to_vec())]);
+
+        let (changes, ignored, unmatched) = add_changes(
+            &repo,
+            &index,
+            &specs(&["skip.log", "missing"]),
+            &AddOptions::default(),
+        );
+        assert!(changes.is_empty());
+        assert_eq!(ignored, specs(&["skip.log"]));
+        assert_eq!(unmatched, specs(&["missing"]));
+    }
+
+    #[test]
+    fn test_rm_check() {
+        let repo = repo_scratch("rm-check");
+        let mut index = GitIndex::default();
+        for name in ["clean", "dirty"] {
+            fs::write(repo.worktree.join(name), name).unwrap();
+            index.add(crate::git::worktree::worktree_entry(
+                &repo,
+                name.as_bytes(),
+                None,
+                true,
+            ));
+        }
+        fs::write(repo.worktree.join("dirty"), "changed").unwrap();
+        let paths = vec![b"clean".to_vec(), b"dirty".to_vec()];
+
+        // Without a HEAD, everything staged counts as a staged change
+        assert_eq!(
+            rm_check(&repo, &index, &paths, false),
+            vec![
+                (RmProblem::Staged, b"clean".to_vec()),
+                (RmProblem::Both, b"dirty".to_vec()),
+            ]
+        );
+        assert_eq!(
+            rm_check(&repo, &index, &paths, true),
+            vec![(RmProblem::Both, b"dirty".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_checkout_tree() {
+        let repo = repo_scratch("checkout-tree");
+        let mut index = GitIndex::default();
+        for name in ["a", "b"] {
+            fs::write(repo.worktree.join(name), "one").unwrap();
+            index.add(crate::git::worktree::worktree_entry(
+                &repo,
+                name.as_bytes(),
+                None,
+                true,
+            ));
+        }
+        let one = index_write_tree(&repo, &index);
+        fs::write(repo.worktree.join("a"), "two").unwrap();
+        index.add(crate::git::worktree::worktree_entry(
+            &repo, b"a", None, true,
+        ));
+        index_write(&repo, &index);
+        let two = index_write_tree(&repo, &index);
+
+        assert_eq!(checkout_tree(&repo, Some(&two), Some(&one), false), Ok(()));
+        assert_eq!(fs::read_to_string(repo.worktree.join("a")).unwrap(), "one");
+
+        // Local changes to a path that differs between the trees block it
+        fs::write(repo.worktree.join("a"), "local").unwrap();
+        fs::write(repo.worktree.join("b"), "kept").unwrap();
+        assert_eq!(
+            checkout_tree(&repo, Some(&one), Some(&two), false),
+            Err(CheckoutConflicts {
+                modified: vec![b"a".to_vec()],
+                ..Default::default()
+            })
+        );
+        assert_eq!(checkout_tree(&repo, Some(&one), Some(&two), true), Ok(()));
+        assert_eq!(fs::read_to_string(repo.worktree.join("a")).unwrap(), "two");
+        assert_eq!(fs::read_to_string(repo.worktree.join("b")).unwrap(), "one");
+    }
+
+    #[test]
+    fn test_gc_steps() {
+        let repo = repo_scratch("gc-steps");
+        let kept = blob(&repo, "kept");
+        let dropped = blob(&repo, "dropped");
+        ref_update(&repo, "refs/tags/kept", &kept, None, None);
+        let loose = |sha: &str| repo_path(&repo, &object_loose_name(sha)).exists();
+
+        cmd_pack_refs(&repo, true, true);
+        assert!(!repo_path(&repo, "refs/tags/kept").exists());
+        assert!(repo_path(&repo, "refs/tags").is_dir());
+        assert_eq!(ref_resolve(&repo, "refs/tags/kept"), Some(kept.clone()));
+
+        let opts = RepackOptions {
+            all: true,
+            delete: true,
+            window: 10,
+            depth: 50,
+            ..Default::default()
+        };
+        cmd_repack(&repo, &opts);
+        assert_eq!(pack_list(&repo).len(), 1);
+        assert!(!loose(&kept) && loose(&dropped));
+        assert!(object_read_raw(&repo, &kept).is_some());
+
+        // Only objects old enough go
+        let opts = PruneOptions {
+            expire: Some(0),
+            ..Default::default()
+        };
+        cmd_prune(&repo, &opts);
+        assert!(loose(&dropped));
+        cmd_prune(&repo, &PruneOptions::default());
+        assert!(!loose(&dropped));
+        assert!(object_read_raw(&repo, &dropped).is_none());
+    }
+
+    #[test]
+    fn test_fsck_non_utf8_path() {
+        let repo = repo_scratch("fsck-non-utf8");
+        let sha = blob(&repo, "x");
+        let tree = Box::new(GitTree {
+            fmt: b"tree".to_vec(),
+            items: vec![GitTreeLeaf {
+                mode: "100644".to_string(),
+                path: b"caf\xe9".to_vec(),
+                sha: sha.clone(),
+            }],
+        });
+        let tree = object_write(tree, Some(repo.clone()));
+        ref_update(&repo, "refs/tags/tree", &tree, None, None);
+        let mut index = GitIndex::default();
+        index.add(GitIndexEntry {
+            mode: 0o100644,
+            sha,
+            name: b"caf\xe9".to_vec(),
+            ..Default::default()
+        });
+        index_write(&repo, &index);
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let errors = fsck(&repo, &FsckOptions::default(), &mut out, &mut err).unwrap();
+        assert_eq!(errors, 0);
+        assert_eq!(String::from_utf8(out).unwrap(), "");
+        assert_eq!(
+            String::from_utf8(err).unwrap(),
+            "notice: HEAD points to an unborn branch (master)\n"
+        );
+        // Findings that are not errors leave the status alone
+        let lost = blob(&repo, "lost");
+        let mut out = Vec::new();
+        let errors = fsck(&repo, &FsckOptions::default(), &mut out, &mut io::sink()).unwrap();
+        assert_eq!(errors, 0);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("dangling blob {lost}\n")
+        );
+        let (_, data) = object_read_raw(&repo, &tree).unwrap();
+        assert_eq!(fsck_object(b"tree", &data, repo.object_format), vec![]);
+    }
+
+    #[test]
+    fn test_commit() {
+        let mut repo = repo_scratch("commit");
+        repo.config.user = Some(crate::git::config::User {
+            name: Some("C O Mitter".to_string()),
+            email: Some("committer@example.com".to_string()),
+        });
+        let stage = |name: &str, data: &str| {
+            fs::write(repo.worktree.join(name), data).unwrap();
+            let mut index = index_read(&repo);
+            index.add(crate::git::worktree::worktree_entry(
+                &repo,
+                name.as_bytes(),
+                None,
+                true,
+            ));
+            index_write(&repo, &index);
+            index_write_tree(&repo, &index)
+        };
+        let commit = |opts: &CommitOptions| {
+            cmd_commit(&repo, opts);
+            let sha = ref_resolve(&repo, "HEAD").unwrap();
+            (sha.clone(), commit_read(&repo, &sha))
+        };
+        let message = |m: &str| CommitOptions {
+            message: vec![m.to_string()],
+            ..Default::default()
+        };
+
+        let tree = stage("a", "one\n");
+        let (first, root) = commit(&message("first"));
+        assert_eq!(root.tree(), tree);
+        assert!(root.parents().is_empty());
+        assert_eq!(root.message(), "first\n");
+
+        let tree = stage("a", "two\n");
+        let (second, commit2) = commit(&CommitOptions {
+            message: vec!["second".to_string(), "body".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(commit2.tree(), tree);
+        assert_eq!(commit2.parents(), vec![first.clone()]);
+        assert_eq!(commit2.message(), "second\n\nbody\n");
+
+        // Amending replaces the tip, keeping its parents and authorship,
+        // and its message unless a new one is given
+        let tree = stage("b", "new\n");
+        let (amended, commit3) = commit(&CommitOptions {
+            amend: true,
+            ..Default::default()
+        });
+        assert_ne!(amended, second);
+        assert_eq!(commit3.tree(), tree);
+        assert_eq!(commit3.parents(), vec![first.clone()]);
+        assert_eq!(commit3.author(), commit2.author());
+        assert_eq!(commit3.message(), commit2.message());
+        let (_, commit4) = commit(&CommitOptions {
+            amend: true,
+            ..message("reworded")
+        });
+        assert_eq!(commit4.parents(), vec![first.clone()]);
+        assert_eq!(commit4.message(), "reworded\n");
+
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/master"),
+            ref_resolve(&repo, "HEAD")
+        );
+        for log in ["HEAD", "refs/heads/master"] {
+            let messages: Vec<String> = reflog_read(&repo, log)
+                .into_iter()
+                .map(|e| e.message)
+                .collect();
+            assert_eq!(
+                messages,
+                vec![
+                    "commit (initial): first",
+                    "commit: second",
+                    "commit (amend): second",
+                    "commit (amend): reworded",
+                ]
+            );
+        }
+        let log = reflog_read(&repo, "HEAD");
+        assert_eq!(log[0].old, repo.object_format.null_sha());
+        assert_eq!(log[2].old, second);
+        assert_eq!(log[2].new, amended);
+    }
+
+    #[test]
+    fn test_show_ref() {
+        let mut repo = repo_scratch("show-ref");
+        repo.config.user = Some(crate::git::config::User {
+            name: Some("C O Mitter".to_string()),
+            email: Some("committer@example.com".to_string()),
+        });
+        let (a, b) = (blob(&repo, "a"), blob(&repo, "b"));
+        for (name, sha) in [
+            ("refs/heads/master", &a),
+            ("refs/heads/feature/x", &a),
+            ("refs/remotes/origin/master", &b),
+            ("refs/tags/x", &b),
+        ] {
+            ref_update(&repo, name, sha, None, None);
+        }
+        cmd_tag_create(&repo, "v1", &a, true, Some("release"));
+        let tag = ref_resolve(&repo, "refs/tags/v1").unwrap();
+
+        let show = |patterns: &[&str], opts: &ShowRefOptions| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            show_ref(&repo, &patterns, opts)
+        };
+        let line = |sha: &str, name: &str| format!("{sha} {name}");
+        let all = ShowRefOptions::default();
+        assert_eq!(
+            show(&[], &all),
+            Ok(vec![
+                line(&a, "refs/heads/feature/x"),
+                line(&a, "refs/heads/master"),
+                line(&b, "refs/remotes/origin/master"),
+                line(&tag, "refs/tags/v1"),
+                line(&b, "refs/tags/x"),
+            ])
+        );
+
+        // Patterns match whole trailing components
+        assert_eq!(
+            show(&["x"], &all),
+            Ok(vec![
+                line(&a, "refs/heads/feature/x"),
+                line(&b, "refs/tags/x")
+            ])
+        );
+        assert_eq!(
+            show(&["heads/master"], &all),
+            Ok(vec![line(&a, "refs/heads/master")])
+        );
+        assert_eq!(show(&["ster"], &all), Ok(vec![]));
+        let heads = ShowRefOptions {
+            heads: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            show(&["x"], &heads),
+            Ok(vec![line(&a, "refs/heads/feature/x")])
+        );
+
+        let hash = ShowRefOptions {
+            hash: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            show(&["master"], &hash),
+            Ok(vec![a[..10].to_string(), b[..10].to_string()])
+        );
+
+        // Only annotated tags get a peeled line, in full even with --hash
+        let dereference = ShowRefOptions {
+            dereference: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            show(&["tags/v1", "tags/x"], &dereference),
+            Ok(vec![
+                line(&tag, "refs/tags/v1"),
+                line(&a, "refs/tags/v1^{}"),
+                line(&b, "refs/tags/x"),
+            ])
+        );
+        let both = ShowRefOptions {
+            hash: Some(10),
+            ..dereference
+        };
+        assert_eq!(
+            show(&["v1"], &both),
+            Ok(vec![tag[..10].to_string(), line(&a, "refs/tags/v1^{}")])
+        );
+
+        // --verify takes full ref names only, in the order given
+        let verify = ShowRefOptions {
+            verify: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            show(&["refs/tags/x", "HEAD"], &verify),
+            Ok(vec![line(&b, "refs/tags/x"), line(&a, "HEAD")])
+        );
+        assert_eq!(show(&["master"], &verify), Err("master".to_string()));
+        assert_eq!(
+            show(&["refs/heads/master", "refs/heads/nope"], &verify),
+            Err("refs/heads/nope".to_string())
+        );
+        // Quietly verifying an existing ref returns without exiting
+        let quiet = ShowRefOptions {
+            quiet: true,
+            ..verify
+        };
+        cmd_show_ref(&repo, &["refs/heads/master".to_string()], &quiet);
+    }
+
+    #[test]
+    fn test_rev_list() {
+        let repo = repo_scratch("rev-list");
+        let tree = |files: &[(&str, &str)]| {
+            let mut index = GitIndex::default();
+            for (name, sha) in files {
+                index.add(GitIndexEntry {
+                    mode: 0o100644,
+                    sha: sha.to_string(),
+                    name: name.as_bytes().to_vec(),
+                    ..Default::default()
+                });
+            }
+            index_write_tree(&repo, &index)
+        };
+        let commit = |tree: &str, parents: &[&str], time: i64| {
+            let mut kvlm = Dict::new();
+            kvlm.insert(Some(b"tree".to_vec()), vec![tree.as_bytes().to_vec()]);
+            let parents = parents.iter().map(|p| p.as_bytes().to_vec()).collect();
+            kvlm.insert(Some(b"parent".to_vec()), parents);
+            let ident = format!("A U Thor <a@example.com> {time} +0000");
+            kvlm.insert(Some(b"author".to_vec()), vec![ident.clone().into_bytes()]);
+            kvlm.insert(Some(b"committer".to_vec()), vec![ident.into_bytes()]);
+            kvlm.insert(None, vec![b"message\n".to_vec()]);
+            let obj = Box::new(GitCommit {
+                fmt: b"commit".to_vec(),
+                kvlm,
+            });
+            object_write(obj, Some(repo.clone()))
+        };
+        let (one, two, three) = (blob(&repo, "1"), blob(&repo, "2"), blob(&repo, "3"));
+        let root_tree = tree(&[("a", &one), ("dir/b", &one)]);
+        let root = commit(&root_tree, &[], 100);
+        let left = commit(&tree(&[("a", &two), ("dir/b", &one)]), &[&root], 200);
+        let right_tree = tree(&[("a", &one), ("dir/b", &two), ("dir/c", &three)]);
+        let right = commit(&right_tree, &[&root], 300);
+        ref_update(&repo, "refs/heads/master", &left, None, None);
+        ref_update(&repo, "refs/heads/topic", &right, None, None);
+
+        let list = |revs: &[&str], opts: &RevListOptions| {
+            let revs: Vec<String> = revs.iter().map(|r| r.to_string()).collect();
+            let mut out = Vec::new();
+            rev_list(&repo, &mut out, &revs, &[], opts).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let count = RevListOptions {
+            count: true,
+            ..Default::default()
+        };
+        assert_eq!(list(&["master"], &count), "2\n");
+        assert_eq!(list(&["master...topic"], &count), "2\n");
+        let left_right = RevListOptions {
+            left_right: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            list(&["master...topic"], &left_right),
+            format!(">{right}\n<{left}\n")
+        );
+        let both = RevListOptions {
+            count: true,
+            ..left_right
+        };
+        assert_eq!(list(&["master...topic"], &both), "1\t1\n");
+        assert_eq!(list(&["topic..master"], &both), "0\t1\n");
+
+        // Objects the excluded side has are left out; blobs and trees come
+        // with their paths, the top tree with an empty one
+        let objects = RevListOptions {
+            objects: true,
+            ..Default::default()
+        };
+        let dir = |tree: &str| rev_parse(&repo, &format!("{tree}:dir")).unwrap();
+        let (dir, root_dir) = (dir(&right_tree), dir(&root_tree));
+        assert_eq!(
+            list(&["topic", "^master"], &objects),
+            format!("{right}\n{right_tree} \n{dir} dir\n{two} dir/b\n{three} dir/c\n")
+        );
+        // Each object is listed once, at the first path it is found at
+        assert_eq!(
+            list(&[&root], &objects),
+            format!("{root}\n{root_tree} \n{one} a\n{root_dir} dir\n")
+        );
+    }
+
+    #[test]
+    fn test_tag() {
+        let mut repo = repo_scratch("tag");
+        repo.config.user = Some(crate::git::config::User {
+            name: Some("C O Mitter".to_string()),
+            email: Some("committer@example.com".to_string()),
+        });
+        let target = blob(&repo, "tagged");
+        ref_update(&repo, "refs/heads/master", &target, None, None);
+
+        tag_create(&repo, "light", "HEAD", false, None).unwrap();
+        assert_eq!(ref_resolve(&repo, "refs/tags/light"), Some(target.clone()));
+
+        tag_create(
+            &repo,
+            "v1",
+            "master",
+            true,
+            Some("  release\n\n\n notes  \n"),
+        )
+        .unwrap();
+        let sha = ref_resolve(&repo, "refs/tags/v1").unwrap();
+        let obj = object_read(&repo, &sha).unwrap();
+        let tag = obj.as_any().downcast_ref::<GitTag>().unwrap();
+        assert_eq!(tag.object(), Some(target.clone()));
+        assert_eq!(tag.type_().as_deref(), Some("blob"));
+        assert_eq!(tag.tag().as_deref(), Some("v1"));
+        assert!(tag
+            .tagger()
+            .unwrap()
+            .starts_with(&ident(&repo, "COMMITTER")[..10]));
+        assert_eq!(tag.message(), "  release\n\n notes\n");
+
+        // Existing tags are never overwritten
+        let other = blob(&repo, "other");
+        assert_eq!(
+            tag_create(&repo, "v1", &other, false, None),
+            Err("tag 'v1' already exists".to_string())
+        );
+        assert_eq!(ref_resolve(&repo, "refs/tags/v1"), Some(sha.clone()));
+        assert_eq!(
+            tag_create(&repo, "bad..name", &other, false, None),
+            Err("'bad..name' is not a valid tag name.".to_string())
+        );
+        assert_eq!(
+            tag_create(&repo, "v2", "nowhere", false, None),
+            Err("Failed to resolve 'nowhere' as a valid ref.".to_string())
+        );
+        assert_eq!(ref_resolve(&repo, "refs/tags/v2"), None);
+
+        assert_eq!(tag_delete(&repo, "light"), Ok(target));
+        assert_eq!(ref_resolve(&repo, "refs/tags/light"), None);
+        assert_eq!(ref_resolve(&repo, "refs/tags/v1"), Some(sha));
+        assert_eq!(
+            tag_delete(&repo, "light"),
+            Err("tag 'light' not found.".to_string())
+        );
+    }
 
-    let parents = commit.kvlm.get(&Some(b"parent".to_vec())).unwrap();
+    #[test]
+    fn test_message_split() {
+        let (subject, body) = message_split("first\nline\n\n\nbody\n\n  more\n");
+        assert_eq!(subject, "first line");
+        assert_eq!(body, "body\n\n  more\n");
+        assert_eq!(message_split("only\n"), ("only".to_string(), String::new()));
 
-    for p in parents {
-        let parent_str = String::from_utf8(p.clone()).unwrap(); // Decode parent as ASCII string
-        println!("  c_{} -> c_{};", sha, parent_str); // Print parent relationship
-        log_graphviz(repo, &parent_str, seen);
+        assert_eq!("dot".parse(), Ok(LogFormat::Dot));
+        assert_eq!(
+            "%h %s".parse(),
+            Ok(LogFormat::Format {
+                template: "%h %s".to_string(),
+                terminated: true
+            })
+        );
+        assert!("bogus".parse::<LogFormat>().is_err());
     }
 }
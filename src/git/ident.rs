@@ -0,0 +1,506 @@
+use crate::git::repo::GitRepository;
+use std::env;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Build an identity line such as `Name <email> 1527025023 +0200`.
+///
+/// `role` is either `AUTHOR` or `COMMITTER` and selects which `GIT_<role>_*`
+/// environment variables take precedence over the `user` config section.
+pub fn ident(repo: &GitRepository, role: &str) -> String {
+    let (Some(name), Some(email)) = ident_user(repo, role) else {
+        let who = if role == "AUTHOR" {
+            "Author"
+        } else {
+            "Committer"
+        };
+        eprint!("{who} identity unknown\n{IDENT_ADVICE}");
+        eprintln!("fatal: unable to auto-detect email address");
+        process::exit(128);
+    };
+
+    format!("{name} <{email}> {}", ident_date(role))
+}
+
+const IDENT_ADVICE: &str = "
+*** Please tell me who you are.
+
+Run
+
+  git config --global user.email \"you@example.com\"
+  git config --global user.name \"Your Name\"
+
+to set your account's default identity.
+Omit --global to set the identity only in this repository.
+
+";
+
+/// Like `ident`, but falls back to the login name instead of failing when no
+/// identity is configured. Used where git does not insist, e.g. reflogs.
+pub fn ident_or_default(repo: &GitRepository, role: &str) -> String {
+    let (name, email) = ident_user(repo, role);
+    let login = env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let name = name.unwrap_or_else(|| login.clone());
+    let email = email.unwrap_or_else(|| format!("{login}@localhost"));
+
+    format!("{name} <{email}> {}", ident_date(role))
+}
+
+fn ident_user(repo: &GitRepository, role: &str) -> (Option<String>, Option<String>) {
+    ident_user_from(repo, role, |name| env::var(name).ok())
+}
+
+/// Name and email of `role`, looking variables up with `var`
+fn ident_user_from(
+    repo: &GitRepository,
+    role: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> (Option<String>, Option<String>) {
+    let user = repo.config.user.as_ref();
+
+    let name = var(&format!("GIT_{role}_NAME")).or_else(|| user.and_then(|u| u.name.clone()));
+    let email = var(&format!("GIT_{role}_EMAIL"))
+        .or_else(|| user.and_then(|u| u.email.clone()))
+        .or_else(|| var("EMAIL"));
+
+    (name, email)
+}
+
+/// Current time as `<seconds> <tz>`, unless overridden by `GIT_<role>_DATE`
+fn ident_date(role: &str) -> String {
+    let (secs, tz) = match env::var(format!("GIT_{role}_DATE")) {
+        Ok(date) => date_parse(&date).unwrap_or_else(|| panic!("Invalid date format: {date}")),
+        Err(_) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time is before the epoch")
+                .as_secs() as i64;
+            (now, local_offset(now))
+        }
+    };
+
+    format!("{secs} {}", tz_format(tz))
+}
+
+/// Offset from UTC in minutes of the local timezone at the given time
+pub fn local_offset(secs: i64) -> i32 {
+    let t = secs as libc::time_t;
+    // SAFETY: localtime_r only writes into the tm struct we hand it
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_gmtoff / 60) as i32
+    }
+}
+
+/// Format an offset in minutes as git's `+hhmm`
+pub fn tz_format(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs();
+    format!("{sign}{:02}{:02}", offset / 60, offset % 60)
+}
+
+/// Parse git's `+hhmm` timezone into minutes
+pub fn tz_parse(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => (1, tz),
+    };
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Days since the epoch of a proleptic Gregorian date
+pub fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Proleptic Gregorian date of a number of days since the epoch
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (
+        if m <= 2 {
+            yoe + era * 400 + 1
+        } else {
+            yoe + era * 400
+        },
+        m,
+        d,
+    )
+}
+
+/// How dates are shown, after git's `--date` formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateStyle {
+    /// `Tue Nov 14 23:13:20 2023 +0100`
+    #[default]
+    Default,
+    /// `2023-11-14 23:13:20 +0100`
+    Iso,
+    /// `2023-11-14T23:13:20+01:00`
+    IsoStrict,
+    /// `Tue, 14 Nov 2023 23:13:20 +0100`
+    Rfc2822,
+    /// `2023-11-14`
+    Short,
+    /// `1700000000 +0100`
+    Raw,
+    /// `1700000000`
+    Unix,
+    /// `3 weeks ago`
+    Relative,
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format seconds since the epoch, shown in the timezone `tz` (minutes)
+pub fn date_format(secs: i64, tz: i32, style: DateStyle) -> String {
+    let local = secs + tz as i64 * 60;
+    let days = local.div_euclid(86400);
+    let time = local.rem_euclid(86400);
+    let (y, m, d) = civil_from_days(days);
+    let (hh, mm, ss) = (time / 3600, time / 60 % 60, time % 60);
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    let month = MONTHS[m as usize - 1];
+
+    match style {
+        DateStyle::Default => format!(
+            "{weekday} {month} {d} {hh:02}:{mm:02}:{ss:02} {y} {}",
+            tz_format(tz)
+        ),
+        DateStyle::Iso => format!(
+            "{y}-{m:02}-{d:02} {hh:02}:{mm:02}:{ss:02} {}",
+            tz_format(tz)
+        ),
+        DateStyle::IsoStrict => {
+            let tz = tz_format(tz);
+            format!(
+                "{y}-{m:02}-{d:02}T{hh:02}:{mm:02}:{ss:02}{}:{}",
+                &tz[..3],
+                &tz[3..]
+            )
+        }
+        DateStyle::Rfc2822 => format!(
+            "{weekday}, {d} {month} {y} {hh:02}:{mm:02}:{ss:02} {}",
+            tz_format(tz)
+        ),
+        DateStyle::Short => format!("{y}-{m:02}-{d:02}"),
+        DateStyle::Raw => format!("{secs} {}", tz_format(tz)),
+        DateStyle::Unix => secs.to_string(),
+        DateStyle::Relative => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time is before the epoch")
+                .as_secs() as i64;
+            date_relative(secs, now)
+        }
+    }
+}
+
+/// Describe how long before `now` a date is, rounding like git does
+pub fn date_relative(secs: i64, now: i64) -> String {
+    let ago = |n: i64, unit: &str| {
+        let s = if n == 1 { "" } else { "s" };
+        format!("{n} {unit}{s} ago")
+    };
+    if secs > now {
+        return "in the future".to_string();
+    }
+    let diff = now - secs;
+    if diff < 90 {
+        return ago(diff, "second");
+    }
+    let diff = (diff + 30) / 60;
+    if diff < 90 {
+        return ago(diff, "minute");
+    }
+    let diff = (diff + 30) / 60;
+    if diff < 36 {
+        return ago(diff, "hour");
+    }
+    let diff = (diff + 12) / 24;
+    if diff < 14 {
+        return ago(diff, "day");
+    }
+    if diff < 70 {
+        return ago((diff + 3) / 7, "week");
+    }
+    if diff < 365 {
+        return ago((diff + 15) / 30, "month");
+    }
+    if diff < 1825 {
+        let total = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total / 12, total % 12);
+        if months > 0 {
+            let s = if years == 1 { "" } else { "s" };
+            return format!("{years} year{s}, {}", ago(months, "month"));
+        }
+        return ago(years, "year");
+    }
+    ago((diff + 183) / 365, "year")
+}
+
+/// Split an identity line into name, email, seconds and timezone offset
+pub fn ident_parse(line: &str) -> Option<(String, String, i64, i32)> {
+    let open = line.find('<')?;
+    let close = open + line[open..].find('>')?;
+    let name = line[..open].trim_end().to_string();
+    let email = line[open + 1..close].to_string();
+    let mut date = line[close + 1..].split_whitespace();
+    let secs = date.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let tz = date.next().and_then(tz_parse).unwrap_or(0);
+    Some((name, email, secs, tz))
+}
+
+/// Parse a date as accepted by `GIT_AUTHOR_DATE` and friends.
+///
+/// Supports git's internal `[@]<seconds> <tz>` form and ISO 8601
+/// `YYYY-MM-DD[T ]HH:MM:SS [tz]`. Returns seconds and offset in minutes.
+pub fn date_parse(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    let mut parts = date.split_whitespace();
+    let first = parts.next()?;
+    let raw = first.strip_prefix('@').unwrap_or(first);
+
+    if raw.bytes().all(|c| c.is_ascii_digit()) && raw.len() > 8 {
+        let secs = raw.parse().ok()?;
+        let tz = match parts.next() {
+            Some(tz) => tz_parse(tz)?,
+            None => 0,
+        };
+        return Some((secs, tz));
+    }
+
+    // ISO 8601
+    let (day, rest) = match first.split_once('T') {
+        Some((day, time)) => (day, Some(time)),
+        None => (first, None),
+    };
+    let mut fields = Vec::new();
+    let time = rest.or_else(|| parts.next())?;
+    let (time, inline_tz) = match time.find(['+', '-', 'Z']) {
+        Some(i) => (&time[..i], Some(&time[i..])),
+        None => (time, None),
+    };
+    for f in day.split('-').chain(time.split(':')) {
+        fields.push(f.parse::<i64>().ok()?);
+    }
+    if fields.len() != 6 {
+        return None;
+    }
+
+    let tz = match inline_tz.or_else(|| parts.next()) {
+        Some("Z") => 0,
+        Some(tz) => tz_parse(tz)?,
+        None => {
+            let guess = days_from_civil(fields[0], fields[1], fields[2]) * 86400;
+            local_offset(guess)
+        }
+    };
+
+    let local = days_from_civil(fields[0], fields[1], fields[2]) * 86400
+        + fields[3] * 3600
+        + fields[4] * 60
+        + fields[5];
+    Some((local - tz as i64 * 60, tz))
+}
+
+/// Parse an expiry date such as `--expire` or `gc.pruneExpire`: like
+/// `date_approx`, plus `never` (nothing is old enough) and `all`
+pub fn date_expiry(date: &str, now: i64) -> Option<i64> {
+    match date.trim().to_lowercase().as_str() {
+        "never" | "false" => Some(i64::MIN),
+        "all" => Some(i64::MAX),
+        _ => date_approx(date, now),
+    }
+}
+
+/// Parse a date the way `--since` and `--until` take it: anything
+/// `date_parse` understands, a bare `YYYY-MM-DD` (at the current time of
+/// day), `now`, `yesterday` or `<n> <unit>s ago`, also written with dots.
+pub fn date_approx(date: &str, now: i64) -> Option<i64> {
+    if let Some((secs, _)) = date_parse(date) {
+        return Some(secs);
+    }
+    let date = date.trim().to_lowercase();
+    let offset = local_offset(now) as i64 * 60;
+    let local = now + offset;
+    let (days, time) = (local.div_euclid(86400), local.rem_euclid(86400));
+    let (y, m, d) = civil_from_days(days);
+
+    let words: Vec<&str> = date.split(['.', ' ']).filter(|w| !w.is_empty()).collect();
+    match words[..] {
+        ["now"] => return Some(now),
+        ["yesterday"] => return Some(now - 86400),
+        [day] => {
+            let fields: Vec<i64> = day
+                .split('-')
+                .map(|f| f.parse().ok())
+                .collect::<Option<_>>()?;
+            if let [y, m, d] = fields[..] {
+                return Some(days_from_civil(y, m, d) * 86400 + time - offset);
+            }
+            return None;
+        }
+        _ => {}
+    }
+
+    let (n, unit) = match words[..] {
+        [n, unit] | [n, unit, "ago"] => (n.parse::<i64>().ok()?, unit.trim_end_matches('s')),
+        _ => return None,
+    };
+    let secs = match unit {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" | "year" => {
+            let months = match unit {
+                "month" => n,
+                _ => n * 12,
+            };
+            let month = y * 12 + (m - 1) - months;
+            let days = days_from_civil(month.div_euclid(12), month.rem_euclid(12) + 1, d);
+            return Some(days * 86400 + time - offset);
+        }
+        _ => return None,
+    };
+    Some(now - n * secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::config::User;
+    use crate::git::repo::repo_scratch;
+
+    #[test]
+    fn test_ident_user() {
+        let mut repo = repo_scratch("ident-user");
+        let vars = |pairs: &'static [(&str, &str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        let user = |repo: &GitRepository, role, pairs| ident_user_from(repo, role, vars(pairs));
+        assert_eq!(user(&repo, "AUTHOR", &[]), (None, None));
+        assert_eq!(
+            user(&repo, "AUTHOR", &[("EMAIL", "mail@example.com")]),
+            (None, Some("mail@example.com".to_string()))
+        );
+
+        repo.config.user = Some(User {
+            name: Some("Config Name".to_string()),
+            email: Some("config@example.com".to_string()),
+        });
+        let config = (
+            Some("Config Name".to_string()),
+            Some("config@example.com".to_string()),
+        );
+        assert_eq!(
+            user(&repo, "AUTHOR", &[("EMAIL", "mail@example.com")]),
+            config
+        );
+
+        // The variables of the role win over the config, the other role's
+        // don't
+        let env = &[
+            ("GIT_AUTHOR_NAME", "A U Thor"),
+            ("GIT_AUTHOR_EMAIL", "author@example.com"),
+        ];
+        assert_eq!(
+            user(&repo, "AUTHOR", env),
+            (
+                Some("A U Thor".to_string()),
+                Some("author@example.com".to_string())
+            )
+        );
+        assert_eq!(user(&repo, "COMMITTER", env), config);
+    }
+
+    #[test]
+    fn test_date_parse() {
+        assert_eq!(date_parse("1527025023 +0200"), Some((1527025023, 120)));
+        assert_eq!(date_parse("@1527025023 -0130"), Some((1527025023, -90)));
+        assert_eq!(
+            date_parse("2005-04-07T22:13:13 +0200"),
+            Some((1112904793, 120))
+        );
+        assert_eq!(date_parse("2005-04-07 20:13:13 Z"), Some((1112904793, 0)));
+        assert_eq!(date_parse("yesterday"), None);
+    }
+
+    #[test]
+    fn test_date_approx() {
+        let now = 1700000000;
+        assert_eq!(date_approx("now", now), Some(now));
+        assert_eq!(date_approx("2 days ago", now), Some(now - 2 * 86400));
+        assert_eq!(date_approx("3.hours.ago", now), Some(now - 3 * 3600));
+        assert_eq!(date_approx("@1600000000", now), Some(1600000000));
+        assert_eq!(date_approx("2 fortnights ago", now), None);
+
+        assert_eq!(date_expiry("2.weeks.ago", now), Some(now - 14 * 86400));
+        assert_eq!(date_expiry("never", now), Some(i64::MIN));
+        assert_eq!(date_expiry("bogus", now), None);
+    }
+
+    #[test]
+    fn test_date_format() {
+        let t = 1700000000;
+        assert_eq!(
+            date_format(t, 60, DateStyle::Default),
+            "Tue Nov 14 23:13:20 2023 +0100"
+        );
+        assert_eq!(
+            date_format(t, -150, DateStyle::Iso),
+            "2023-11-14 19:43:20 -0230"
+        );
+        assert_eq!(
+            date_format(t, 0, DateStyle::IsoStrict),
+            "2023-11-14T22:13:20+00:00"
+        );
+        assert_eq!(
+            date_format(t, 60, DateStyle::Rfc2822),
+            "Tue, 14 Nov 2023 23:13:20 +0100"
+        );
+        assert_eq!(date_relative(t, t + 30), "30 seconds ago");
+        assert_eq!(date_relative(t, t + 86400 * 400), "1 year, 1 month ago");
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn test_tz_format() {
+        assert_eq!(tz_format(120), "+0200");
+        assert_eq!(tz_format(-90), "-0130");
+        assert_eq!(tz_format(0), "+0000");
+    }
+}
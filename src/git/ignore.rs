@@ -0,0 +1,465 @@
+use crate::git::objects::tree::tree_path_join;
+use crate::git::repo::{repo_path, GitRepository};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// A single pattern from an ignore file
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    pub pattern: String,
+    /// Pattern started with `!` and re-includes what it matches
+    pub negated: bool,
+    /// Pattern ended with `/` and only matches directories
+    pub dir_only: bool,
+    /// Pattern contained a `/` and matches relative to its base directory
+    pub anchored: bool,
+    /// The line as written, for `check-ignore -v`
+    pub text: String,
+    /// Line number in its file, starting at 1
+    pub line: usize,
+}
+
+impl IgnoreRule {
+    /// Parse a line of an ignore file, skipping blanks and comments
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches(['\n', '\r']);
+        // Trailing spaces are ignored unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let text = line.to_string();
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let pattern = match line.strip_prefix('\\') {
+            Some(rest) if rest.starts_with('#') || rest.starts_with('!') => rest,
+            _ => line,
+        };
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+            text,
+            line: 0,
+        })
+    }
+
+    /// Whether the rule matches `path`, given relative to the rule's base
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch_bytes(self.pattern.as_bytes(), path, true)
+        } else {
+            let name = path.rsplit(|&c| c == b'/').next().unwrap_or(path);
+            wildmatch_bytes(self.pattern.as_bytes(), name, true)
+        }
+    }
+}
+
+/// The rule deciding whether a path is ignored, and where it comes from
+#[derive(Debug, Clone)]
+pub struct IgnoreMatch {
+    /// The ignore file, relative to the top of the worktree when inside it
+    pub source: String,
+    pub rule: IgnoreRule,
+}
+
+/// Rules read from one ignore file, applying below `base`
+#[derive(Debug, Clone, Default)]
+struct IgnoreList {
+    /// Directory the rules are relative to, `""` for the top of the worktree
+    base: Vec<u8>,
+    /// Name of the file the rules come from
+    source: String,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreList {
+    fn read(path: PathBuf, base: &[u8], source: String) -> Self {
+        let rules = fs::read_to_string(path)
+            .map(|data| {
+                data.lines()
+                    .enumerate()
+                    .filter_map(|(i, line)| {
+                        let mut rule = IgnoreRule::parse(line)?;
+                        rule.line = i + 1;
+                        Some(rule)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        IgnoreList {
+            base: base.to_vec(),
+            source,
+            rules,
+        }
+    }
+
+    /// The last rule matching `path`, if any
+    fn find(&self, path: &[u8], is_dir: bool) -> Option<IgnoreMatch> {
+        let rel = match self.base.is_empty() {
+            true => path,
+            false => path
+                .strip_prefix(self.base.as_slice())?
+                .strip_prefix(b"/")?,
+        };
+        let rule = self.rules.iter().rev().find(|r| r.matches(rel, is_dir))?;
+        Some(IgnoreMatch {
+            source: self.source.clone(),
+            rule: rule.clone(),
+        })
+    }
+}
+
+/// Decides which worktree paths are ignored, from `.gitignore` files,
+/// `.git/info/exclude` and `core.excludesFile`
+#[derive(Debug)]
+pub struct GitIgnore {
+    worktree: PathBuf,
+    /// Per-directory `.gitignore` files, loaded on demand
+    dirs: HashMap<Vec<u8>, IgnoreList>,
+    /// Repository wide rules, in order of precedence
+    global: Vec<IgnoreList>,
+    /// Directories already known to be excluded, with the rule doing so
+    excluded: HashMap<Vec<u8>, Option<IgnoreMatch>>,
+}
+
+impl GitIgnore {
+    pub fn new(repo: &GitRepository) -> Self {
+        let exclude = repo_path(repo, "info/exclude");
+        let mut global = vec![IgnoreList::read(
+            exclude.clone(),
+            b"",
+            ignore_source(repo, &exclude),
+        )];
+        if let Some(path) = ignore_excludes_file(repo) {
+            let source = path.to_string_lossy().into_owned();
+            global.push(IgnoreList::read(path, b"", source));
+        }
+
+        GitIgnore {
+            worktree: repo.worktree.clone(),
+            dirs: HashMap::new(),
+            global,
+            excluded: HashMap::new(),
+        }
+    }
+
+    fn dir_list(&mut self, dir: &[u8]) -> &IgnoreList {
+        let worktree = &self.worktree;
+        self.dirs.entry(dir.to_vec()).or_insert_with(|| {
+            let file = tree_path_join(dir, b".gitignore");
+            let source = String::from_utf8_lossy(&file).into_owned();
+            IgnoreList::read(worktree.join(OsStr::from_bytes(&file)), dir, source)
+        })
+    }
+
+    /// The rule deciding whether `path` (relative to the worktree) is
+    /// ignored, including negated rules that re-include it.
+    ///
+    /// Everything below an excluded directory is excluded by the rule for
+    /// that directory, whatever deeper rules say. Otherwise, deeper
+    /// `.gitignore` files take precedence, then `info/exclude`, then
+    /// `core.excludesFile`; within a file the last matching pattern wins.
+    pub fn find(&mut self, path: &[u8], is_dir: bool) -> Option<IgnoreMatch> {
+        let mut end = 0;
+        while let Some(i) = path[end..].iter().position(|&c| c == b'/') {
+            let dir = &path[..end + i];
+            if !self.excluded.contains_key(dir) {
+                let rule = self.find_direct(dir, true).filter(|m| !m.rule.negated);
+                self.excluded.insert(dir.to_vec(), rule);
+            }
+            if let Some(rule) = &self.excluded[dir] {
+                return Some(rule.clone());
+            }
+            end += i + 1;
+        }
+        self.find_direct(path, is_dir)
+    }
+
+    /// Like `find`, ignoring whether a parent directory is excluded
+    fn find_direct(&mut self, path: &[u8], is_dir: bool) -> Option<IgnoreMatch> {
+        let mut dir = path;
+        while let Some(i) = dir.iter().rposition(|&c| c == b'/') {
+            dir = &dir[..i];
+            if let Some(rule) = self.dir_list(dir).find(path, is_dir) {
+                return Some(rule);
+            }
+        }
+        if let Some(rule) = self.dir_list(b"").find(path, is_dir) {
+            return Some(rule);
+        }
+        self.global.iter().find_map(|list| list.find(path, is_dir))
+    }
+
+    /// Whether `path` (relative to the worktree) is ignored
+    pub fn is_ignored(&mut self, path: &[u8], is_dir: bool) -> bool {
+        self.find(path, is_dir).is_some_and(|m| !m.rule.negated)
+    }
+}
+
+/// How `check-ignore -v` names an ignore file: relative to the top of the
+/// worktree when inside it, as is otherwise
+fn ignore_source(repo: &GitRepository, path: &Path) -> String {
+    path.strip_prefix(&repo.worktree)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The user's global ignore file: `core.excludesFile` from any config
+/// layer, defaulting to `$XDG_CONFIG_HOME/git/ignore` or
+/// `~/.config/git/ignore`
+fn ignore_excludes_file(repo: &GitRepository) -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    match repo.config.get("core.excludesfile") {
+        Some(path) => match path.strip_prefix("~/") {
+            Some(rest) => Some(home?.join(rest)),
+            None => Some(PathBuf::from(path)),
+        },
+        None => match env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+            Some(xdg) => Some(PathBuf::from(xdg).join("git/ignore")),
+            None => Some(home?.join(".config/git/ignore")),
+        },
+    }
+}
+
+/// Match `text` against a shell glob supporting `*`, `?`, `[...]` classes
+/// and `\` escapes. With `pathname`, wildcards do not match `/`, except
+/// for `**` as a whole path component.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    wildmatch_bytes(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+/// `wildmatch` on raw bytes, for paths that are not UTF-8
+pub fn wildmatch_bytes(p: &[u8], t: &[u8], pathname: bool) -> bool {
+    let (mut pi, mut ti) = (0, 0);
+    // Where to resume after the last `*`, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        let matched = match p.get(pi) {
+            // With `pathname`, a `**` between slashes matches across them
+            Some(b'*')
+                if pathname
+                    && p.get(pi + 1) == Some(&b'*')
+                    && (pi == 0 || p[pi - 1] == b'/')
+                    && matches!(p.get(pi + 2), None | Some(b'/')) =>
+            {
+                let rest = match p.get(pi + 3..) {
+                    Some(rest) => rest,
+                    None => return true,
+                };
+                // `**/` matches zero or more leading directories
+                let mut start = ti;
+                loop {
+                    if wildmatch_bytes(rest, &t[start..], pathname) {
+                        return true;
+                    }
+                    match t[start..].iter().position(|&c| c == b'/') {
+                        Some(i) => start += i + 1,
+                        None => return false,
+                    }
+                }
+            }
+            Some(b'*') => {
+                star = Some((pi, ti));
+                pi += 1;
+                continue;
+            }
+            Some(b'?') if !(pathname && t[ti] == b'/') => {
+                pi += 1;
+                true
+            }
+            Some(b'[') => match class_match(&p[pi..], t[ti], pathname) {
+                Some((true, len)) => {
+                    pi += len;
+                    true
+                }
+                _ => false,
+            },
+            Some(b'\\') if pi + 1 < p.len() && p[pi + 1] == t[ti] => {
+                pi += 2;
+                true
+            }
+            Some(&c) if c != b'?' && c != b'\\' && c == t[ti] => {
+                pi += 1;
+                true
+            }
+            _ => false,
+        };
+
+        if matched {
+            ti += 1;
+            continue;
+        }
+        match star {
+            // Let the last `*` swallow one more byte and retry
+            Some((sp, st)) if !(pathname && t[st] == b'/') => {
+                star = Some((sp, st + 1));
+                pi = sp + 1;
+                ti = st + 1;
+            }
+            _ => return false,
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+/// Match a byte against a `[...]` class at the start of `p`, returning
+/// whether it matched and the length of the class
+fn class_match(p: &[u8], c: u8, pathname: bool) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(p.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = *p.get(i)?;
+        if lo == b']' && !first {
+            break;
+        }
+        first = false;
+        let lo = if lo == b'\\' {
+            i += 1;
+            *p.get(i)?
+        } else {
+            lo
+        };
+
+        if p.get(i + 1) == Some(&b'-') && p.get(i + 2).is_some_and(|&h| h != b']') {
+            let hi = p[i + 2];
+            matched |= lo <= c && c <= hi;
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+
+    if pathname && c == b'/' {
+        return Some((false, i + 1));
+    }
+    Some((matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch("*.o", "main.o", true));
+        assert!(!wildmatch("*.o", "dir/main.o", true));
+        assert!(wildmatch("*.o", "dir/main.o", false));
+        assert!(wildmatch("a?c", "abc", true));
+        assert!(wildmatch("[a-c]x", "bx", true));
+        assert!(!wildmatch("[!a-c]x", "bx", true));
+        assert!(wildmatch("[]]", "]", true));
+        assert!(wildmatch("\\*", "*", true));
+        assert!(!wildmatch("\\*", "a", true));
+        assert!(wildmatch("doc/*.txt", "doc/a.txt", true));
+        assert!(!wildmatch("doc/*.txt", "doc/x/a.txt", true));
+        assert!(wildmatch("*a*b*", "xxaxxbxx", true));
+        assert!(wildmatch("**/foo", "foo", true));
+        assert!(wildmatch("**/foo", "a/b/foo", true));
+        assert!(wildmatch("a/**/b", "a/b", true));
+        assert!(wildmatch("a/**/b", "a/x/y/b", true));
+        assert!(wildmatch("a/**", "a/x/y", true));
+        assert!(!wildmatch("a/**", "a", true));
+        assert!(!wildmatch("a**b", "a/b", true));
+    }
+
+    #[test]
+    fn test_ignore_rule_parse() {
+        let rule = IgnoreRule::parse("!/build/  ").unwrap();
+        assert!(rule.negated && rule.dir_only && rule.anchored);
+        assert_eq!(rule.pattern, "build");
+        assert!(IgnoreRule::parse("# comment").is_none());
+        assert_eq!(IgnoreRule::parse("\\#file").unwrap().pattern, "#file");
+
+        let rule = IgnoreRule::parse("*.log").unwrap();
+        assert!(rule.matches(b"a/b/c.log", false));
+        assert!(rule.matches(b"caf\xe9.log", false));
+        let rule = IgnoreRule::parse("out/").unwrap();
+        assert!(rule.matches(b"src/out", true));
+        assert!(!rule.matches(b"src/out", false));
+    }
+
+    #[test]
+    fn test_git_ignore_find() {
+        let repo = crate::git::repo::repo_scratch("ignore-find");
+        fs::create_dir_all(repo.worktree.join("sub")).unwrap();
+        fs::write(
+            repo.worktree.join(".gitignore"),
+            "*.log\n!keep.log\nbuild/\n",
+        )
+        .unwrap();
+        fs::write(repo.worktree.join("sub/.gitignore"), "!*.log\nx\n").unwrap();
+        let exclude = crate::git::repo::repo_file(&repo, "info/exclude", true).unwrap();
+        fs::write(exclude, "secret\n").unwrap();
+
+        let mut ignore = GitIgnore::new(&repo);
+        let m = ignore.find(b"a.log", false).unwrap();
+        assert_eq!((m.source.as_str(), m.rule.line), (".gitignore", 1));
+        assert!(!ignore.is_ignored(b"keep.log", false));
+        // Deeper files take precedence
+        assert!(!ignore.is_ignored(b"sub/a.log", false));
+        let m = ignore.find(b"sub/x", false).unwrap();
+        assert_eq!(
+            (m.source.as_str(), m.rule.text.as_str()),
+            ("sub/.gitignore", "x")
+        );
+        assert!(ignore.is_ignored(b"secret", false));
+        // Nothing can be re-included below an excluded directory
+        let m = ignore.find(b"build/keep.log", false).unwrap();
+        assert_eq!(m.rule.text, "build/");
+    }
+
+    #[test]
+    fn test_ignore_excludes_file() {
+        let mut repo = crate::git::repo::repo_scratch("ignore-excludes-file");
+        let global = repo.worktree.join("global");
+        let ignore = repo.worktree.join("global-ignore");
+        fs::write(&ignore, "*.tmp\n").unwrap();
+        fs::write(
+            &global,
+            format!("[core]\n\texcludesFile = {}\n", ignore.display()),
+        )
+        .unwrap();
+        // A value from the global layer, read before the repository's own
+        let mut values = crate::git::config::config_read(&global).unwrap();
+        values.push(("core.bare".to_string(), "false".to_string()));
+        repo.config = crate::git::config::GitConfig::from_values(values);
+        assert_eq!(ignore_excludes_file(&repo), Some(ignore.clone()));
+
+        let m = GitIgnore::new(&repo).find(b"a.tmp", false).unwrap();
+        assert_eq!(m.source, ignore.to_string_lossy());
+    }
+}
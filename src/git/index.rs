@@ -0,0 +1,769 @@
+use crate::git::hash::ObjectFormat;
+use crate::git::objects::objects::{object_write, sha_from_hex, sha_to_hex};
+use crate::git::objects::tree::{GitTree, GitTreeLeaf};
+use crate::git::repo::{repo_path, GitRepository};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXT_SKIP_WORKTREE: u16 = 0x4000;
+const EXT_INTENT_TO_ADD: u16 = 0x2000;
+
+/// Size of the fixed part of an on-disk entry, without the object id
+const ENTRY_FIXED_SIZE: usize = 42;
+
+/// Extensions describing the entries that must be dropped once entries change
+const CACHE_EXTENSIONS: [&[u8; 4]; 5] = [b"TREE", b"UNTR", b"FSMN", b"EOIE", b"IEOT"];
+
+/// A single entry of the index
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitIndexEntry {
+    /// Metadata change time as (seconds, nanoseconds)
+    pub ctime: (u32, u32),
+    /// Data modification time as (seconds, nanoseconds)
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    /// Object type and unix permissions, e.g. `0o100644`
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// On-disk size, truncated to 32 bits
+    pub size: u32,
+    /// Hex encoded object id
+    pub sha: String,
+    pub assume_valid: bool,
+    /// Merge stage: 0 normally, 1-3 for the sides of a conflict
+    pub stage: u8,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    /// Path relative to the worktree, with `/` separators. Raw bytes, as
+    /// git does not require paths to be UTF-8.
+    pub name: Vec<u8>,
+}
+
+impl GitIndexEntry {
+    fn has_extended_flags(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+/// An index extension kept as raw bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitIndexExtension {
+    pub signature: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// The index file (`.git/index`), also known as the dircache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitIndex {
+    pub version: u32,
+    /// Entries sorted by name, then stage
+    pub entries: Vec<GitIndexEntry>,
+    pub extensions: Vec<GitIndexExtension>,
+}
+
+impl Default for GitIndex {
+    fn default() -> Self {
+        GitIndex {
+            version: 2,
+            entries: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl GitIndex {
+    /// Parse the binary index format, versions 2 to 4
+    pub fn parse(raw: &[u8], format: ObjectFormat) -> Result<Self, String> {
+        if raw.len() < 12 + format.raw_len() {
+            return Err("Malformed index: too short".to_string());
+        }
+        let (body, checksum) = raw.split_at(raw.len() - format.raw_len());
+        if format.digest(body) != checksum {
+            return Err("Malformed index: bad checksum".to_string());
+        }
+
+        if &body[0..4] != b"DIRC" {
+            return Err("Malformed index: bad signature".to_string());
+        }
+        let version = read_u32(body, 4);
+        if !(2..=4).contains(&version) {
+            return Err(format!("Unsupported index version {version}"));
+        }
+        let count = read_u32(body, 8) as usize;
+
+        let mut pos = 12;
+        let mut entries = Vec::new();
+        let mut previous = Vec::new();
+        for _ in 0..count {
+            let (entry, next) = parse_entry(body, pos, version, &previous, format)?;
+            previous = entry.name.clone();
+            entries.push(entry);
+            pos = next;
+        }
+
+        let mut extensions = Vec::new();
+        while pos + 8 <= body.len() {
+            let signature: [u8; 4] = body[pos..pos + 4].try_into().unwrap();
+            let size = read_u32(body, pos + 4) as usize;
+            let data = body
+                .get(pos + 8..pos + 8 + size)
+                .ok_or("Malformed index: truncated extension")?
+                .to_vec();
+            // Lowercase signatures mark extensions that must be understood
+            if !signature[0].is_ascii_uppercase() {
+                return Err(format!(
+                    "Unsupported index extension {}",
+                    String::from_utf8_lossy(&signature)
+                ));
+            }
+            extensions.push(GitIndexExtension { signature, data });
+            pos += 8 + size;
+        }
+        if pos != body.len() {
+            return Err("Malformed index: trailing garbage".to_string());
+        }
+
+        Ok(GitIndex {
+            version,
+            entries,
+            extensions,
+        })
+    }
+
+    /// Serialize into the binary index format, including the checksum
+    pub fn serialize(&self, format: ObjectFormat) -> Vec<u8> {
+        let mut version = self.version;
+        if version == 2 && self.entries.iter().any(|e| e.has_extended_flags()) {
+            version = 3;
+        }
+
+        let mut ret = Vec::new();
+        ret.extend(b"DIRC");
+        ret.extend(version.to_be_bytes());
+        ret.extend((self.entries.len() as u32).to_be_bytes());
+
+        let mut previous: &[u8] = b"";
+        for entry in &self.entries {
+            serialize_entry(&mut ret, entry, version, previous);
+            previous = &entry.name;
+        }
+
+        for ext in &self.extensions {
+            ret.extend(ext.signature);
+            ret.extend((ext.data.len() as u32).to_be_bytes());
+            ret.extend(&ext.data);
+        }
+
+        let checksum = format.digest(&ret);
+        ret.extend(checksum);
+        ret
+    }
+
+    /// Find the stage 0 entry for a path
+    pub fn get(&self, name: &[u8]) -> Option<&GitIndexEntry> {
+        self.find(name, 0).ok().map(|i| &self.entries[i])
+    }
+
+    fn find(&self, name: &[u8], stage: u8) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|e| e.name.as_slice().cmp(name).then(e.stage.cmp(&stage)))
+    }
+
+    /// Insert or replace an entry. Adding a stage 0 entry resolves any
+    /// conflict recorded for the same path.
+    pub fn add(&mut self, entry: GitIndexEntry) {
+        self.add_all(vec![entry]);
+    }
+
+    /// Insert or replace many entries at once, like `add` for each of them
+    /// in turn but sorting only once
+    pub fn add_all(&mut self, mut entries: Vec<GitIndexEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        self.invalidate_caches();
+        // Stable, so the last of several entries for a path and stage wins
+        entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.stage.cmp(&b.stage)));
+
+        let mut dropped = vec![false; self.entries.len()];
+        for entry in &entries {
+            for i in self.range(&entry.name) {
+                dropped[i] |= entry.stage == 0 || self.entries[i].stage == entry.stage;
+            }
+            // A path cannot be both a file and a directory: replace entries
+            // for its parents, or for paths below it. Both are found by
+            // searching the sorted entries, without a scan.
+            for (i, _) in entry.name.iter().enumerate().filter(|(_, &c)| c == b'/') {
+                for j in self.range(&entry.name[..i]) {
+                    dropped[j] = true;
+                }
+            }
+            let dir = [&entry.name[..], b"/"].concat();
+            let start = self.entries.partition_point(|e| e.name < dir);
+            for (j, e) in self.entries[start..].iter().enumerate() {
+                if !e.name.starts_with(&dir) {
+                    break;
+                }
+                dropped[start + j] = true;
+            }
+        }
+
+        let old = std::mem::take(&mut self.entries)
+            .into_iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| !dropped)
+            .map(|(e, _)| e);
+        let mut new = entries.into_iter().peekable();
+        let mut ret = Vec::new();
+        for e in old {
+            while let Some(n) = new.next_if(|n| (&n.name, n.stage) < (&e.name, e.stage)) {
+                push_replacing(&mut ret, n);
+            }
+            ret.push(e);
+        }
+        for n in new {
+            push_replacing(&mut ret, n);
+        }
+        self.entries = ret;
+    }
+
+    /// Indices of the entries for `name`, all stages
+    fn range(&self, name: &[u8]) -> std::ops::Range<usize> {
+        let start = self.entries.partition_point(|e| e.name.as_slice() < name);
+        let len = self.entries[start..]
+            .iter()
+            .take_while(|e| e.name == name)
+            .count();
+        start..start + len
+    }
+
+    /// Remove every stage of a path, returning whether anything was removed
+    pub fn remove(&mut self, name: &[u8]) -> bool {
+        self.remove_all(&[name])
+    }
+
+    /// Remove every stage of each of `names` in a single pass, returning
+    /// whether anything was removed
+    pub fn remove_all(&mut self, names: &[&[u8]]) -> bool {
+        let names: HashSet<&[u8]> = names.iter().copied().collect();
+        let before = self.entries.len();
+        self.entries.retain(|e| !names.contains(e.name.as_slice()));
+        if self.entries.len() == before {
+            return false;
+        }
+        self.invalidate_caches();
+        true
+    }
+
+    /// Drop extensions that cache information derived from the entries, as
+    /// they become stale when entries change. Others are kept verbatim.
+    pub fn invalidate_caches(&mut self) {
+        self.extensions
+            .retain(|ext| !CACHE_EXTENSIONS.contains(&&ext.signature));
+    }
+}
+
+/// Append an entry to sorted `entries`, replacing the last one if it is
+/// for the same path and stage
+fn push_replacing(entries: &mut Vec<GitIndexEntry>, entry: GitIndexEntry) {
+    match entries.last_mut() {
+        Some(last) if last.name == entry.name && last.stage == entry.stage => *last = entry,
+        _ => entries.push(entry),
+    }
+}
+
+fn read_u32(raw: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(raw[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_u16(raw: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes(raw[pos..pos + 2].try_into().unwrap())
+}
+
+/// Decode git's offset varint used for v4 path compression, `None` when
+/// it runs past the end of `raw`
+fn read_varint(raw: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut c = *raw.get(*pos)?;
+    *pos += 1;
+    let mut val = (c & 0x7f) as usize;
+    while c & 0x80 != 0 {
+        c = *raw.get(*pos)?;
+        *pos += 1;
+        val = ((val + 1) << 7) + (c & 0x7f) as usize;
+    }
+    Some(val)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    let mut buf = vec![(val & 0x7f) as u8];
+    val >>= 7;
+    while val != 0 {
+        val -= 1;
+        buf.push(0x80 | (val & 0x7f) as u8);
+        val >>= 7;
+    }
+    buf.reverse();
+    out.extend(buf);
+}
+
+fn parse_entry(
+    raw: &[u8],
+    start: usize,
+    version: u32,
+    previous: &[u8],
+    format: ObjectFormat,
+) -> Result<(GitIndexEntry, usize), String> {
+    let sha_end = start + 40 + format.raw_len();
+    if start + ENTRY_FIXED_SIZE + format.raw_len() > raw.len() {
+        return Err("Malformed index: truncated entry".to_string());
+    }
+    let flags = read_u16(raw, sha_end);
+    let mut pos = sha_end + 2;
+
+    let mut entry = GitIndexEntry {
+        ctime: (read_u32(raw, start), read_u32(raw, start + 4)),
+        mtime: (read_u32(raw, start + 8), read_u32(raw, start + 12)),
+        dev: read_u32(raw, start + 16),
+        ino: read_u32(raw, start + 20),
+        mode: read_u32(raw, start + 24),
+        uid: read_u32(raw, start + 28),
+        gid: read_u32(raw, start + 32),
+        size: read_u32(raw, start + 36),
+        sha: sha_to_hex(&raw[start + 40..sha_end]),
+        assume_valid: flags & FLAG_ASSUME_VALID != 0,
+        stage: ((flags & FLAG_STAGE_MASK) >> 12) as u8,
+        ..Default::default()
+    };
+
+    if flags & FLAG_EXTENDED != 0 {
+        if version < 3 {
+            return Err("Malformed index: extended flags in v2".to_string());
+        }
+        if pos + 2 > raw.len() {
+            return Err("Malformed index: truncated entry".to_string());
+        }
+        let ext = read_u16(raw, pos);
+        entry.skip_worktree = ext & EXT_SKIP_WORKTREE != 0;
+        entry.intent_to_add = ext & EXT_INTENT_TO_ADD != 0;
+        pos += 2;
+    }
+
+    let mut name = Vec::new();
+    if version == 4 {
+        let keep = read_varint(raw, &mut pos)
+            .and_then(|strip| previous.len().checked_sub(strip))
+            .ok_or("Malformed index: bad path prefix")?;
+        name.extend(&previous[..keep]);
+    }
+    let end = raw[pos..]
+        .iter()
+        .position(|&c| c == 0)
+        .map(|p| p + pos)
+        .ok_or("Malformed index: unterminated path")?;
+    name.extend(&raw[pos..end]);
+    entry.name = name;
+
+    let next = match version {
+        4 => end + 1,
+        // Entries are NUL padded to a multiple of eight bytes
+        _ => start + ((end - start + 8) & !7),
+    };
+
+    Ok((entry, next))
+}
+
+fn serialize_entry(out: &mut Vec<u8>, entry: &GitIndexEntry, version: u32, previous: &[u8]) {
+    let start = out.len();
+    for field in [
+        entry.ctime.0,
+        entry.ctime.1,
+        entry.mtime.0,
+        entry.mtime.1,
+        entry.dev,
+        entry.ino,
+        entry.mode,
+        entry.uid,
+        entry.gid,
+        entry.size,
+    ] {
+        out.extend(field.to_be_bytes());
+    }
+    out.extend(sha_from_hex(&entry.sha));
+
+    let extended = version >= 3 && entry.has_extended_flags();
+    let mut flags = (entry.name.len().min(FLAG_NAME_MASK as usize) as u16)
+        | ((entry.stage as u16) << 12) & FLAG_STAGE_MASK;
+    if entry.assume_valid {
+        flags |= FLAG_ASSUME_VALID;
+    }
+    if extended {
+        flags |= FLAG_EXTENDED;
+    }
+    out.extend(flags.to_be_bytes());
+
+    if extended {
+        let mut ext = 0u16;
+        if entry.skip_worktree {
+            ext |= EXT_SKIP_WORKTREE;
+        }
+        if entry.intent_to_add {
+            ext |= EXT_INTENT_TO_ADD;
+        }
+        out.extend(ext.to_be_bytes());
+    }
+
+    if version == 4 {
+        let common = previous
+            .iter()
+            .zip(&entry.name)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_varint(out, previous.len() - common);
+        out.extend(&entry.name[common..]);
+        out.push(0);
+    } else {
+        out.extend(&entry.name);
+        let len = out.len() - start;
+        let padded = (len + 8) & !7;
+        out.resize(start + padded, 0);
+    }
+}
+
+/// Read the repository's index, or an empty one if there is none yet
+pub fn index_read(repo: &GitRepository) -> GitIndex {
+    match fs::read(repo_path(repo, "index")) {
+        Ok(raw) => GitIndex::parse(&raw, repo.object_format).unwrap_or_else(|e| panic!("{e}")),
+        Err(e) if e.kind() == ErrorKind::NotFound => GitIndex::default(),
+        Err(e) => panic!("Failed to read index: {e}"),
+    }
+}
+
+/// Atomically replace the repository's index through `index.lock`
+pub fn index_write(repo: &GitRepository, index: &GitIndex) {
+    let path = repo_path(repo, "index");
+    let lock = repo_path(repo, "index.lock");
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => panic!(
+            "Unable to create '{}': File exists.\nAnother gitr process seems to be running in this repository",
+            lock.display()
+        ),
+        Err(e) => panic!("Unable to create '{}': {e}", lock.display()),
+    };
+
+    let data = index.serialize(repo.object_format);
+    if let Err(e) = file.write_all(&data).and_then(|_| file.sync_all()) {
+        let _ = fs::remove_file(&lock);
+        panic!("Failed to write index: {e}");
+    }
+    fs::rename(&lock, &path).expect("Failed to rename index.lock");
+}
+
+/// Write tree objects for the whole index and return the root tree id.
+///
+/// Intent-to-add entries are left out, as git does. The index must not
+/// contain conflicts.
+pub fn index_write_tree(repo: &GitRepository, index: &GitIndex) -> String {
+    if let Some(e) = index.entries.iter().find(|e| e.stage > 0) {
+        panic!(
+            "Cannot write a tree: '{}' is unmerged",
+            String::from_utf8_lossy(&e.name)
+        );
+    }
+    let entries: Vec<&GitIndexEntry> = index.entries.iter().filter(|e| !e.intent_to_add).collect();
+    write_tree(repo, &entries, 0)
+}
+
+/// Write the tree for `entries`, which all share a directory prefix of
+/// `skip` bytes
+fn write_tree(repo: &GitRepository, entries: &[&GitIndexEntry], skip: usize) -> String {
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let rest = &entries[i].name[skip..];
+        let leaf = match rest.iter().position(|&c| c == b'/') {
+            None => {
+                i += 1;
+                GitTreeLeaf {
+                    mode: format!("{:o}", entries[i - 1].mode),
+                    path: rest.to_vec(),
+                    sha: entries[i - 1].sha.clone(),
+                }
+            }
+            Some(len) => {
+                // Entries below a directory are contiguous in index order
+                let dir = &rest[..=len];
+                let end = i + entries[i..]
+                    .iter()
+                    .take_while(|e| e.name[skip..].starts_with(dir))
+                    .count();
+                let sha = write_tree(repo, &entries[i..end], skip + len + 1);
+                i = end;
+                GitTreeLeaf {
+                    mode: "40000".to_string(),
+                    path: rest[..len].to_vec(),
+                    sha,
+                }
+            }
+        };
+        items.push(leaf);
+    }
+
+    let tree = Box::new(GitTree {
+        fmt: b"tree".to_vec(),
+        items,
+    });
+    object_write(tree, Some(repo.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> GitIndexEntry {
+        GitIndexEntry {
+            ctime: (1700000000, 12),
+            mtime: (1700000001, 34),
+            dev: 2049,
+            ino: 42,
+            mode: 0o100644,
+            uid: 1000,
+            gid: 1000,
+            size: 6,
+            sha: "ce013625030ba8dba906f756967f9e9ca394464a".to_string(),
+            name: name.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn index(version: u32) -> GitIndex {
+        let mut index = GitIndex {
+            version,
+            ..Default::default()
+        };
+        for name in ["src/lib.rs", "README.md", "src/git/index.rs", "src/git.rs"] {
+            index.add(entry(name));
+        }
+        index
+    }
+
+    #[test]
+    fn test_index_roundtrip_all_versions() {
+        for version in 2..=4 {
+            let mut index = index(version);
+            if version > 2 {
+                index.entries[1].intent_to_add = true;
+                index.entries[2].skip_worktree = true;
+            }
+            index.extensions.push(GitIndexExtension {
+                signature: *b"ZZZZ",
+                data: b"unknown".to_vec(),
+            });
+
+            let raw = index.serialize(ObjectFormat::Sha1);
+            let parsed = GitIndex::parse(&raw, ObjectFormat::Sha1).unwrap();
+            assert_eq!(parsed, index);
+            assert_eq!(parsed.serialize(ObjectFormat::Sha1), raw);
+        }
+    }
+
+    #[test]
+    fn test_index_roundtrip_sha256() {
+        let mut index = index(3);
+        for entry in &mut index.entries {
+            entry.sha = "ab".repeat(32);
+        }
+        index.entries[0].skip_worktree = true;
+        let raw = index.serialize(ObjectFormat::Sha256);
+        // 74 bytes + extended flags (2) + "README.md" (9) padded up to 88
+        assert_eq!(&raw[12 + 76..12 + 88], b"README.md\0\0\0");
+        let parsed = GitIndex::parse(&raw, ObjectFormat::Sha256).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(parsed.serialize(ObjectFormat::Sha256), raw);
+    }
+
+    #[test]
+    fn test_index_sorted_and_padded() {
+        let index = index(2);
+        let names: Vec<&[u8]> = index.entries.iter().map(|e| e.name.as_slice()).collect();
+        let expected: [&[u8]; 4] = [
+            b"README.md",
+            b"src/git.rs",
+            b"src/git/index.rs",
+            b"src/lib.rs",
+        ];
+        assert_eq!(names, expected);
+
+        // 62 bytes + "README.md" (9) padded up to 72
+        let raw = index.serialize(ObjectFormat::Sha1);
+        assert_eq!(&raw[12 + 62..12 + 72], b"README.md\0");
+        assert_eq!(&raw[12 + 72 + 62..12 + 72 + 63], b"s");
+    }
+
+    #[test]
+    fn test_index_v4_prefix_compression() {
+        let raw = index(4).serialize(ObjectFormat::Sha1);
+        // "src/git/index.rs" follows "src/git.rs": strip 3 bytes, add "/index.rs"
+        let needle = b"\x03/index.rs\0";
+        assert!(raw.windows(needle.len()).any(|w| w == needle));
+        assert!(raw.len() < index(2).serialize(ObjectFormat::Sha1).len());
+    }
+
+    #[test]
+    fn test_index_add_all() {
+        let names = |index: &GitIndex| -> Vec<(String, u8)> {
+            index
+                .entries
+                .iter()
+                .map(|e| (String::from_utf8(e.name.clone()).unwrap(), e.stage))
+                .collect()
+        };
+        let mut index = GitIndex::default();
+        for name in ["a", "a-b", "a.c", "b/c/d", "b/e", "z"] {
+            index.add(entry(name));
+        }
+        for stage in 1..=3 {
+            index.add(GitIndexEntry {
+                stage,
+                ..entry("conflict")
+            });
+        }
+
+        let mut batch = vec![entry("a/x"), entry("b/c"), entry("conflict"), entry("new")];
+        batch.push(GitIndexEntry {
+            size: 7,
+            ..entry("new")
+        });
+        let mut one_by_one = index.clone();
+        for e in batch.clone() {
+            one_by_one.add(e);
+        }
+        index.add_all(batch);
+
+        // Files replace directories and the other way around, while
+        // neighbours sorting between them are kept
+        let expected = ["a-b", "a.c", "a/x", "b/c", "b/e", "conflict", "new", "z"];
+        assert_eq!(names(&index), expected.map(|n| (n.to_string(), 0)).to_vec());
+        assert_eq!(index.get(b"new").unwrap().size, 7);
+        assert_eq!(index, one_by_one);
+
+        assert!(index.remove_all(&[b"a-b", b"z", b"missing"]));
+        assert!(!index.remove_all(&[b"missing"]));
+        assert_eq!(index.entries.len(), 6);
+    }
+
+    #[test]
+    fn test_index_varint() {
+        for val in [0, 1, 127, 128, 255, 16383, 16384, 1 << 20] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, val);
+            let mut pos = 0;
+            assert_eq!(read_varint(&buf, &mut pos), Some(val));
+            assert_eq!(pos, buf.len());
+        }
+    }
+
+    #[test]
+    fn test_index_mutation_drops_cache_tree() {
+        let mut index = index(2);
+        index.extensions.push(GitIndexExtension {
+            signature: *b"TREE",
+            data: Vec::new(),
+        });
+        index.extensions.push(GitIndexExtension {
+            signature: *b"ZZZZ",
+            data: Vec::new(),
+        });
+        index.extensions.push(GitIndexExtension {
+            signature: *b"FSMN",
+            data: Vec::new(),
+        });
+        assert!(index.remove(b"README.md"));
+        assert_eq!(index.extensions.len(), 1);
+        assert_eq!(&index.extensions[0].signature, b"ZZZZ");
+    }
+
+    #[test]
+    fn test_index_malformed() {
+        let mut raw = index(2).serialize(ObjectFormat::Sha1);
+        let last = raw.len() - 1;
+        raw[last] ^= 1;
+        assert_eq!(
+            GitIndex::parse(&raw, ObjectFormat::Sha1),
+            Err("Malformed index: bad checksum".to_string())
+        );
+
+        // A path running into the checksum, with the checksum fixed up
+        let mut raw = index(4).serialize(ObjectFormat::Sha1);
+        raw.truncate(raw.len() - 21);
+        let checksum = ObjectFormat::Sha1.digest(&raw);
+        raw.extend(checksum);
+        assert_eq!(
+            GitIndex::parse(&raw, ObjectFormat::Sha1),
+            Err("Malformed index: unterminated path".to_string())
+        );
+    }
+
+    #[test]
+    fn test_index_read_written() {
+        let repo = crate::git::repo::repo_scratch("index-read");
+        assert_eq!(index_read(&repo), GitIndex::default());
+
+        for version in 2..=4 {
+            let mut index = index(version);
+            // Paths need not be UTF-8
+            index.add(GitIndexEntry {
+                name: b"caf\xe9".to_vec(),
+                ..entry("")
+            });
+            index.extensions.push(GitIndexExtension {
+                signature: *b"TREE",
+                data: b"\x001 0\n".to_vec(),
+            });
+            let _ = fs::remove_file(repo_path(&repo, "index"));
+            index_write(&repo, &index);
+            assert_eq!(index_read(&repo), index);
+        }
+    }
+
+    #[test]
+    fn test_index_write_tree() {
+        let repo = crate::git::repo::repo_scratch("write-tree");
+        let mut index = GitIndex::default();
+        for (mode, sha, name) in [
+            (0o100644, "0cfbf08886fca9a91cb753ec8734c84fcbe52c9f", "a-x"),
+            (
+                0o100644,
+                "d00491fd7e5bb6fa28c517a0bb32b8b506539d4d",
+                "a/b/f",
+            ),
+            (0o100644, "00750edc07d6415dcc07ae0351e9397b0222b7ba", "c/g"),
+            (0o120000, "fa7af8bf5fdd704f73beb3adc5612682a98e1af5", "l"),
+            (0o100755, "b8626c4cff2849624fb67f87cd0ad72b163671ad", "z"),
+        ] {
+            index.add(GitIndexEntry {
+                mode,
+                sha: sha.to_string(),
+                name: name.as_bytes().to_vec(),
+                ..Default::default()
+            });
+        }
+        index.add(GitIndexEntry {
+            intent_to_add: true,
+            ..entry("later")
+        });
+
+        // Same tree as git writes for these entries
+        assert_eq!(
+            index_write_tree(&repo, &index),
+            "0eb117eda1d825abd5fbb354e3c7866e3358fc79"
+        );
+    }
+}
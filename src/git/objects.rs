@@ -1,6 +1,7 @@
 pub mod blob;
 pub mod commit;
 pub mod kvlm;
+#[allow(clippy::module_inception)]
 pub mod objects;
 pub mod tag;
 pub mod tree;
@@ -4,12 +4,61 @@ use crate::git::objects::objects::GitObject;
 use crate::git::repo::GitRepository;
 use std::any::Any;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GitCommit {
     pub fmt: Vec<u8>,
     pub kvlm: Dict,
 }
 
+impl GitCommit {
+    fn header(&self, key: &[u8]) -> Option<String> {
+        self.kvlm
+            .get(&Some(key.to_vec()))
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+    }
+
+    /// Object id of the commit's tree
+    pub fn tree(&self) -> String {
+        self.header(b"tree").expect("Malformed commit: no tree")
+    }
+
+    /// Object ids of the parent commits, in order
+    pub fn parents(&self) -> Vec<String> {
+        self.kvlm
+            .get(&Some(b"parent".to_vec()))
+            .map(|v| {
+                v.iter()
+                    .map(|p| String::from_utf8_lossy(p).to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn author(&self) -> Option<String> {
+        self.header(b"author")
+    }
+
+    pub fn committer(&self) -> Option<String> {
+        self.header(b"committer")
+    }
+
+    pub fn message(&self) -> String {
+        self.kvlm
+            .get(&None)
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+            .unwrap_or_default()
+    }
+
+    /// Committer timestamp in seconds since the epoch
+    pub fn time(&self) -> i64 {
+        self.committer()
+            .and_then(|c| c.rsplit(' ').nth(1).and_then(|t| t.parse().ok()))
+            .unwrap_or(0)
+    }
+}
+
 impl GitObject for GitCommit {
     fn fmt(&self) -> Vec<u8> {
         self.fmt.clone()
@@ -20,7 +69,7 @@ impl GitObject for GitCommit {
     }
 
     fn serialize(&self, _: Option<GitRepository>) -> Vec<u8> {
-        return kvlm_serialize(self.kvlm.clone());
+        kvlm_serialize(self.kvlm.clone())
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -86,7 +86,7 @@ pub fn kvlm_serialize(kvlm: Dict) -> Vec<u8> {
         }
     }
 
-    // Append the message
+    // Append the message, which carries its own trailing newline
     ret.push(b'\n');
     ret.extend(
         kvlm.get(&None)
@@ -94,7 +94,6 @@ pub fn kvlm_serialize(kvlm: Dict) -> Vec<u8> {
             .iter()
             .flat_map(|x| x.iter().cloned()),
     );
-    ret.push(b'\n');
 
     ret
 }
@@ -179,4 +178,17 @@ Create first draft"
             ]
         );
     }
+
+    #[test]
+    fn test_kvlm_roundtrip() {
+        let raw = b"object 29ff16c9c14e2652b22f8b78bb08a5a07930c147
+type commit
+tag v1.0
+tagger Thibault Polge <thibault@thb.lt> 1527025023 +0200
+
+Release 1.0
+";
+
+        assert_eq!(kvlm_serialize(kvlm_parse(raw, 0, None)), raw.to_vec());
+    }
 }
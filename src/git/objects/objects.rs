@@ -1,14 +1,16 @@
 use crate::git::{
-    objects::{GitBlob, GitCommit, GitTag, GitTree},
-    repo::{repo_file, GitRepository},
+    hash::ObjectFormat,
+    objects::{tree::tree_parse, GitBlob, GitCommit, GitTag, GitTree},
+    pack::pack_object_read,
+    repo::{repo_file, repo_path, GitRepository},
 };
 use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression};
 use ordermap::OrderMap;
-use sha1::{Digest, Sha1};
 use std::any::Any;
+use std::fmt::Write as _;
 use std::{
     fs,
-    io::{prelude::*, BufWriter, Write},
+    io::{self, prelude::*, BufWriter, Write},
 };
 
 /// Trait for Git objects
@@ -26,60 +28,52 @@ pub trait GitObject: Any {
     fn as_any(&self) -> &dyn Any;
 }
 
-pub fn object_read(repo: &GitRepository, sha: &str) -> Option<Box<dyn GitObject>> {
-    let path = repo_file(
-        &repo,
-        &format!("objects/{}/{}", &sha[0..2], &sha[2..]),
-        false,
-    )?;
-
-    if !path.is_file() {
-        return None;
-    }
+/// Encode a binary object id as lowercase hex
+pub fn sha_to_hex(raw: &[u8]) -> String {
+    raw.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
 
-    let read_data = fs::read(path).expect("Failed to read object file for {sha}");
-    let mut decompressor = ZlibDecoder::new(&read_data[..]);
-    let mut decompressed_data: Vec<u8> = Vec::new();
-    decompressor
-        .read_to_end(&mut decompressed_data)
-        .expect("Failed to decompress object file for {sha}");
-    let raw_data = decompressed_data;
-    let x = raw_data.iter().position(|&b| b == b' ')?;
-    let y = raw_data.iter().position(|&b| b == b'\x00')?;
-    let fmt = &raw_data[0..x];
-    let size_str = &raw_data[x + 1..y];
-    let size: usize = std::str::from_utf8(size_str)
-        .expect("Malformed object {sha} : bad size")
-        .parse()
-        .expect("Malformed object {sha} : bad size");
-
-    if size != raw_data[..].len() - y - 1 {
-        panic!("Malformed object {sha}: bad length")
-    }
+/// Decode a hex object id into its binary form
+pub fn sha_from_hex(sha: &str) -> Vec<u8> {
+    (0..sha.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&sha[i..i + 2], 16).expect("Bad object id"))
+        .collect()
+}
 
-    let obj: Box<dyn GitObject> = match fmt {
+pub fn object_read(repo: &GitRepository, sha: &str) -> Option<Box<dyn GitObject>> {
+    let (fmt, data) = object_read_raw(repo, sha)?;
+    let obj: Box<dyn GitObject> = match &fmt[..] {
         b"commit" => {
             let mut commit = Box::new(GitCommit {
                 fmt: b"commit".to_vec(),
                 kvlm: OrderMap::new(),
             });
-            commit.deserialize(raw_data[y + 1..].to_vec());
+            commit.deserialize(data);
             commit
         }
+        // Entries hold binary ids, whose length depends on the repository
         b"tree" => Box::new(GitTree {
             fmt: b"tree".to_vec(),
-            data: raw_data[y + 1..].to_vec(),
-        }),
-        b"tag" => Box::new(GitTag {
-            fmt: b"tag".to_vec(),
-            data: raw_data[y + 1..].to_vec(),
+            items: tree_parse(&data, repo.object_format).unwrap_or_else(|e| panic!("{e}")),
         }),
+        b"tag" => {
+            let mut tag = Box::new(GitTag {
+                fmt: b"tag".to_vec(),
+                kvlm: OrderMap::new(),
+            });
+            tag.deserialize(data);
+            tag
+        }
         b"blob" => Box::new(GitBlob {
             fmt: b"blob".to_vec(),
-            blobdata: raw_data[y + 1..].to_vec(),
+            blobdata: data,
         }),
         _ => {
-            let fmt_str = std::str::from_utf8(fmt).unwrap();
+            let fmt_str = String::from_utf8_lossy(&fmt);
             panic!("Unknown type {fmt_str} for object {sha}")
         }
     };
@@ -87,30 +81,68 @@ pub fn object_read(repo: &GitRepository, sha: &str) -> Option<Box<dyn GitObject>
     Some(obj)
 }
 
+/// Read the type and data of an object, loose or packed
+pub fn object_read_raw(repo: &GitRepository, sha: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    object_read_loose(repo, sha).or_else(|| pack_object_read(repo, sha))
+}
+
+fn object_read_loose(repo: &GitRepository, sha: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let path = repo_file(repo, &object_loose_name(sha), false)?;
+
+    if !path.is_file() {
+        return None;
+    }
+
+    let raw = fs::read(path).unwrap_or_else(|_| panic!("Failed to read object file for {sha}"));
+    let object = object_loose_parse(&raw).unwrap_or_else(|e| panic!("Malformed object {sha}: {e}"));
+    Some(object)
+}
+
+/// Inflate a loose object file and split it into type and data, checking
+/// the length given in its header
+pub fn object_loose_parse(raw: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut decompressor = ZlibDecoder::new(raw);
+    let mut raw_data: Vec<u8> = Vec::new();
+    decompressor
+        .read_to_end(&mut raw_data)
+        .map_err(|e| format!("inflate: {e}"))?;
+    let y = raw_data
+        .iter()
+        .position(|&b| b == b'\x00')
+        .ok_or("bad header")?;
+    let x = raw_data[..y]
+        .iter()
+        .position(|&b| b == b' ')
+        .ok_or("bad header")?;
+    let fmt = raw_data[0..x].to_vec();
+    let size: usize = std::str::from_utf8(&raw_data[x + 1..y])
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .ok_or("bad size")?;
+
+    if size != raw_data.len() - y - 1 {
+        return Err("bad length".to_string());
+    }
+
+    raw_data.drain(..y + 1);
+    Ok((fmt, raw_data))
+}
+
 pub fn object_write(object: Box<dyn GitObject>, repo: Option<GitRepository>) -> String {
     // serialize object data
     let data = object.serialize(repo.clone());
+    object_write_raw(repo.as_ref(), &object.fmt(), &data)
+}
 
-    // Add header
-    let mut result = object.fmt();
-    result.push(b' ');
-    result.extend(data.len().to_string().as_bytes());
-    result.push(b'\x00');
-    result.extend(data);
-
-    // compute sha1
-    let mut hasher = Sha1::new();
-    hasher.update(&result);
-    let sha = hasher.finalize();
-    let sha = format!("{:x}", sha);
+/// Hash an object given its type and data, and store it as a loose object
+/// when `repo` is given and it is not there yet
+pub fn object_write_raw(repo: Option<&GitRepository>, fmt: &[u8], data: &[u8]) -> String {
+    let result = object_with_header(fmt, data);
+    let format = repo.map(|repo| repo.object_format).unwrap_or_default();
+    let sha = sha_to_hex(&format.digest(&result));
 
-    if repo.is_some() {
-        let path = repo_file(
-            &repo.unwrap(),
-            &format!("objects/{}/{}", &sha[0..2], &sha[2..]),
-            true,
-        )
-        .unwrap();
+    if let Some(repo) = repo {
+        let path = repo_file(repo, &object_loose_name(&sha), true).unwrap();
 
         if !path.exists() {
             // compress and write
@@ -127,58 +159,120 @@ pub fn object_write(object: Box<dyn GitObject>, repo: Option<GitRepository>) ->
     sha
 }
 
+/// Object id of an object given its type and data, without storing it
+pub fn object_sha(format: ObjectFormat, fmt: &[u8], data: &[u8]) -> String {
+    sha_to_hex(&format.digest(&object_with_header(fmt, data)))
+}
+
+/// The `<type> <size>\0` header followed by the data, which is what gets
+/// hashed and stored
+fn object_with_header(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut result = fmt.to_vec();
+    result.push(b' ');
+    result.extend(data.len().to_string().as_bytes());
+    result.push(b'\x00');
+    result.extend(data);
+    result
+}
+
+/// Path of a loose object relative to the git directory
+pub fn object_loose_name(sha: &str) -> String {
+    format!("objects/{}/{}", &sha[0..2], &sha[2..])
+}
+
+/// Ids of all loose objects, sorted, or why a directory of them could not
+/// be read
+pub fn object_loose_list(repo: &GitRepository) -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    let objects = repo_path(repo, "objects");
+    let entries = match fs::read_dir(&objects) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ret),
+        Err(e) => return Err(format!("unable to open {}: {e}", objects.display())),
+    };
+    for dir in entries {
+        let dir = dir.map_err(|e| format!("unable to read {}: {e}", objects.display()))?;
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !prefix.bytes().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        let path = dir.path();
+        let error = |e: io::Error| format!("unable to open {}: {e}", path.display());
+        for entry in fs::read_dir(&path).map_err(error)? {
+            let rest = entry
+                .map_err(error)?
+                .file_name()
+                .to_string_lossy()
+                .to_string();
+            if repo.object_format.is_sha(&format!("{prefix}{rest}")) {
+                ret.push(format!("{prefix}{rest}"));
+            }
+        }
+    }
+    ret.sort();
+
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::git::repo::{repo_scratch, repo_scratch_format};
 
     #[test]
     fn test_read_git_object_success() {
-        let repo = GitRepository::new(".", true);
-
-        // read the current git repo
-        let mut paths = fs::read_dir("./.git/objects").unwrap();
-        //get the first object
-        let path = paths.next().unwrap().unwrap().path();
-        let path_first_obj = fs::read_dir(path).unwrap().next().unwrap().unwrap().path();
-        let sha = String::from(
-            path_first_obj
-                .parent()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-        ) + path_first_obj.file_name().unwrap().to_str().unwrap();
-
-        dbg!(&sha);
-        let obj = object_read(&repo, &sha);
-        assert!(obj.is_some());
+        let repo = repo_scratch("object-read");
+        let obj = Box::new(GitBlob {
+            fmt: b"blob".to_vec(),
+            blobdata: b"Hello World".to_vec(),
+        });
+        let sha = object_write(obj, Some(repo.clone()));
+
+        let obj = object_read(&repo, &sha).unwrap();
+        let blob = obj.as_any().downcast_ref::<GitBlob>().unwrap();
+        assert_eq!(blob.blobdata, b"Hello World");
     }
 
     #[test]
     fn test_read_git_object_failure() {
-        let repo = GitRepository::new(".", true);
-
-        // read the current git repo
-        let mut paths = fs::read_dir("./.git/objects").unwrap();
-        //get the first object
-        let path = paths.next().unwrap().unwrap().path();
-        let path_first_obj = fs::read_dir(path).unwrap().next().unwrap().unwrap().path();
-        let sha = String::from(
-            path_first_obj
-                .parent()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap(),
-        ) + path_first_obj.file_name().unwrap().to_str().unwrap();
-
-        let sha = sha + "123";
+        let repo = repo_scratch("object-read-missing");
+        let sha = object_write(
+            Box::new(GitBlob {
+                fmt: b"blob".to_vec(),
+                blobdata: b"Hello World".to_vec(),
+            }),
+            Some(repo.clone()),
+        );
 
-        let obj = object_read(&repo, &sha);
+        let obj = object_read(&repo, &(sha.clone() + "123"));
         assert!(obj.is_none());
+        let obj = object_read(&repo, &"0".repeat(40));
+        assert!(obj.is_none());
+    }
+
+    #[test]
+    fn test_sha256_repo() {
+        let repo = repo_scratch_format("object-sha256", ObjectFormat::Sha256);
+        let reopened = GitRepository::new(repo.worktree.to_str().unwrap(), false);
+        assert_eq!(reopened.object_format, ObjectFormat::Sha256);
+
+        let blob = |data: &[u8]| {
+            Box::new(GitBlob {
+                fmt: b"blob".to_vec(),
+                blobdata: data.to_vec(),
+            })
+        };
+        assert_eq!(
+            object_write(blob(b""), Some(reopened.clone())),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"
+        );
+        let sha = object_write(blob(b"Hello World"), Some(reopened.clone()));
+        assert_eq!(sha.len(), 64);
+        assert_eq!(
+            object_read_raw(&reopened, &sha),
+            Some((b"blob".to_vec(), b"Hello World".to_vec()))
+        );
+        assert_eq!(object_loose_list(&reopened).unwrap().len(), 2);
     }
 
     #[test]
@@ -191,4 +285,26 @@ mod tests {
         let result = object_write(obj, None);
         assert_eq!(result.len(), 40);
     }
+
+    #[test]
+    fn test_loose_parse() {
+        let compress = |data: &[u8]| {
+            let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(data).unwrap();
+            e.finish().unwrap()
+        };
+        assert_eq!(
+            object_loose_parse(&compress(b"blob 2\0hi")),
+            Ok((b"blob".to_vec(), b"hi".to_vec()))
+        );
+        assert_eq!(
+            object_loose_parse(&compress(b"blob 3\0hi")),
+            Err("bad length".to_string())
+        );
+        assert_eq!(
+            object_loose_parse(&compress(b"blob2\0hi")),
+            Err("bad header".to_string())
+        );
+        assert!(object_loose_parse(b"garbage").is_err());
+    }
 }
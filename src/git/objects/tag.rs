@@ -1,10 +1,50 @@
+use crate::git::objects::kvlm::Dict;
+use crate::git::objects::kvlm::{kvlm_parse, kvlm_serialize};
 use crate::git::objects::objects::GitObject;
+use crate::git::repo::GitRepository;
 use std::any::Any;
 
+/// An annotated tag. Shares the commit key-value-list-with-message format.
 #[derive(Debug)]
 pub struct GitTag {
     pub fmt: Vec<u8>,
-    pub data: Vec<u8>,
+    pub kvlm: Dict,
+}
+
+impl GitTag {
+    fn header(&self, key: &[u8]) -> Option<String> {
+        self.kvlm
+            .get(&Some(key.to_vec()))
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+    }
+
+    /// Object id of the tagged object
+    pub fn object(&self) -> Option<String> {
+        self.header(b"object")
+    }
+
+    /// Type of the tagged object
+    pub fn type_(&self) -> Option<String> {
+        self.header(b"type")
+    }
+
+    /// Name of the tag
+    pub fn tag(&self) -> Option<String> {
+        self.header(b"tag")
+    }
+
+    pub fn tagger(&self) -> Option<String> {
+        self.header(b"tagger")
+    }
+
+    pub fn message(&self) -> String {
+        self.kvlm
+            .get(&None)
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).to_string())
+            .unwrap_or_default()
+    }
 }
 
 impl GitObject for GitTag {
@@ -12,6 +52,14 @@ impl GitObject for GitTag {
         self.fmt.clone()
     }
 
+    fn deserialize(&mut self, data: Vec<u8>) {
+        self.kvlm = kvlm_parse(&data, 0, None);
+    }
+
+    fn serialize(&self, _: Option<GitRepository>) -> Vec<u8> {
+        kvlm_serialize(self.kvlm.clone())
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
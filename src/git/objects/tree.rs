@@ -1,10 +1,52 @@
-use crate::git::objects::objects::GitObject;
+use crate::git::hash::ObjectFormat;
+use crate::git::objects::objects::{object_read, sha_from_hex, sha_to_hex, GitObject};
+use crate::git::repo::GitRepository;
 use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A single entry of a tree object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitTreeLeaf {
+    /// File mode exactly as stored, e.g. `100644` or `40000`
+    pub mode: String,
+    /// Name of the entry, raw bytes since git does not require UTF-8
+    pub path: Vec<u8>,
+    /// Hex encoded object id
+    pub sha: String,
+}
+
+impl GitTreeLeaf {
+    /// Whether this entry points to another tree
+    pub fn is_tree(&self) -> bool {
+        self.mode_padded().starts_with("04")
+    }
+
+    /// Mode padded to six digits, the way git prints it
+    pub fn mode_padded(&self) -> String {
+        format!("{:0>6}", self.mode)
+    }
+
+    /// Mode as a number, comparable with index entry modes
+    pub fn mode_bits(&self) -> u32 {
+        u32::from_str_radix(&self.mode, 8).expect("Malformed tree: bad mode")
+    }
+
+    /// Type of the object the entry points to
+    pub fn object_type(&self) -> &'static str {
+        match self.mode_padded().as_str() {
+            m if m.starts_with("04") => "tree",
+            m if m.starts_with("10") || m.starts_with("12") => "blob",
+            m if m.starts_with("16") => "commit",
+            m => panic!("Weird tree leaf mode {m}"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct GitTree {
     pub fmt: Vec<u8>,
-    pub data: Vec<u8>,
+    pub items: Vec<GitTreeLeaf>,
 }
 
 impl GitObject for GitTree {
@@ -12,7 +54,225 @@ impl GitObject for GitTree {
         self.fmt.clone()
     }
 
+    /// Entries are read with SHA-1 ids, as the trait knows no repository;
+    /// `object_read` parses with the repository's format instead
+    fn deserialize(&mut self, data: Vec<u8>) {
+        self.items = tree_parse(&data, ObjectFormat::Sha1).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    fn serialize(&self, _: Option<GitRepository>) -> Vec<u8> {
+        tree_serialize(&self.items)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+/// Parse a single leaf starting at `start`, returning the index right after it
+fn tree_parse_one(
+    raw: &[u8],
+    start: usize,
+    format: ObjectFormat,
+) -> Result<(usize, GitTreeLeaf), String> {
+    let x = raw[start..]
+        .iter()
+        .position(|&c| c == b' ')
+        .map(|p| p + start)
+        .ok_or("Malformed tree: missing mode terminator")?;
+    // Like git, any octal mode is accepted here; fsck judges whether it is
+    // a valid one
+    let mode = &raw[start..x];
+    if mode.is_empty() || !mode.iter().all(|c| (b'0'..=b'7').contains(c)) {
+        return Err("Malformed tree: bad mode".to_string());
+    }
+    let mode = String::from_utf8(mode.to_vec()).unwrap();
+
+    let y = raw[x..]
+        .iter()
+        .position(|&c| c == b'\x00')
+        .map(|p| p + x)
+        .ok_or("Malformed tree: missing path terminator")?;
+    let path = raw[x + 1..y].to_vec();
+    if path.is_empty() {
+        return Err("Malformed tree: empty path".to_string());
+    }
+
+    let end = y + 1 + format.raw_len();
+    if end > raw.len() {
+        return Err("Malformed tree: truncated object id".to_string());
+    }
+    let sha = sha_to_hex(&raw[y + 1..end]);
+
+    Ok((end, GitTreeLeaf { mode, path, sha }))
+}
+
+/// Parse the binary representation of a tree object
+pub fn tree_parse(raw: &[u8], format: ObjectFormat) -> Result<Vec<GitTreeLeaf>, String> {
+    let mut pos = 0;
+    let mut ret = Vec::new();
+    while pos < raw.len() {
+        let (next, leaf) = tree_parse_one(raw, pos, format)?;
+        pos = next;
+        ret.push(leaf);
+    }
+
+    Ok(ret)
+}
+
+/// Git orders entries as if directories had a trailing `/`
+pub fn tree_leaf_cmp(a: &GitTreeLeaf, b: &GitTreeLeaf) -> Ordering {
+    let key = |l: &GitTreeLeaf| {
+        let mut k = l.path.clone();
+        if l.is_tree() {
+            k.push(b'/');
+        }
+        k
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Serialize leaves into the binary tree format, in canonical order
+pub fn tree_serialize(items: &[GitTreeLeaf]) -> Vec<u8> {
+    let mut items = items.to_vec();
+    items.sort_by(tree_leaf_cmp);
+
+    let mut ret = Vec::new();
+    for leaf in items {
+        ret.extend(leaf.mode.as_bytes());
+        ret.push(b' ');
+        ret.extend(&leaf.path);
+        ret.push(b'\x00');
+        ret.extend(sha_from_hex(&leaf.sha));
+    }
+
+    ret
+}
+
+/// The path of `name` inside directory `prefix`, `""` being the top
+pub fn tree_path_join(prefix: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut ret = prefix.to_vec();
+    if !ret.is_empty() {
+        ret.push(b'/');
+    }
+    ret.extend(name);
+    ret
+}
+
+/// Read a tree recursively, returning every non-tree entry keyed by its
+/// full path. Leaf paths are full paths too.
+pub fn tree_flatten(repo: &GitRepository, sha: &str) -> BTreeMap<Vec<u8>, GitTreeLeaf> {
+    let mut ret = BTreeMap::new();
+    tree_flatten_into(repo, sha, b"", &mut ret);
+    ret
+}
+
+fn tree_flatten_into(
+    repo: &GitRepository,
+    sha: &str,
+    prefix: &[u8],
+    out: &mut BTreeMap<Vec<u8>, GitTreeLeaf>,
+) {
+    let obj = object_read(repo, sha).expect("Failed to read tree object");
+    let tree = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .expect("Not a tree object");
+
+    for leaf in &tree.items {
+        let path = tree_path_join(prefix, &leaf.path);
+        if leaf.is_tree() {
+            tree_flatten_into(repo, &leaf.sha, &path, out);
+        } else {
+            let leaf = GitTreeLeaf {
+                path: path.clone(),
+                ..leaf.clone()
+            };
+            out.insert(path, leaf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(mode: &str, path: &str, sha: &str) -> GitTreeLeaf {
+        GitTreeLeaf {
+            mode: mode.to_string(),
+            path: path.as_bytes().to_vec(),
+            sha: sha.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_tree_roundtrip() {
+        for format in [ObjectFormat::Sha1, ObjectFormat::Sha256] {
+            let len = format.raw_len();
+            let mut raw = Vec::new();
+            raw.extend(b"100644 README.md\x00");
+            raw.extend(vec![0x11; len]);
+            raw.extend(b"40000 src\x00");
+            raw.extend(vec![0xab; len]);
+
+            let items = tree_parse(&raw, format).unwrap();
+            assert_eq!(
+                items,
+                vec![
+                    leaf("100644", "README.md", &"11".repeat(len)),
+                    leaf("40000", "src", &"ab".repeat(len)),
+                ]
+            );
+            assert_eq!(tree_serialize(&items), raw);
+        }
+
+        // Through the object trait, which knows SHA-1 ids only
+        let mut tree = GitTree {
+            fmt: b"tree".to_vec(),
+            items: Vec::new(),
+        };
+        let items = vec![leaf("100644", "a", &"11".repeat(20))];
+        tree.deserialize(tree_serialize(&items));
+        assert_eq!(tree.items, items);
+        assert_eq!(tree.serialize(None), tree_serialize(&items));
+    }
+
+    #[test]
+    fn test_tree_sort_order() {
+        let sha = "00".repeat(20);
+        let items = vec![
+            leaf("40000", "foo", &sha),
+            leaf("100644", "foo.c", &sha),
+            leaf("100644", "foo-bar", &sha),
+            leaf("100644", "foo0", &sha),
+        ];
+
+        let parsed = tree_parse(&tree_serialize(&items), ObjectFormat::Sha1).unwrap();
+        let paths: Vec<&[u8]> = parsed.iter().map(|l| &l.path[..]).collect();
+        assert_eq!(paths, vec![&b"foo-bar"[..], b"foo.c", b"foo", b"foo0"]);
+    }
+
+    #[test]
+    fn test_tree_non_utf8_path() {
+        let mut raw = b"100644 caf\xe9\x00".to_vec();
+        raw.extend([0x11; 20]);
+        let items = tree_parse(&raw, ObjectFormat::Sha1).unwrap();
+        assert_eq!(items[0].path, b"caf\xe9");
+        assert_eq!(tree_serialize(&items), raw);
+    }
+
+    #[test]
+    fn test_tree_parse_malformed() {
+        let sha = [0x11; 20];
+        for (raw, err) in [
+            (b"100644 file".to_vec(), "missing path terminator"),
+            (b"100644file\x00".to_vec(), "missing mode terminator"),
+            ([&b"10x644 file\x00"[..], &sha].concat(), "bad mode"),
+            ([&b"100644 \x00"[..], &sha].concat(), "empty path"),
+            (b"100644 file\x00\x11\x11".to_vec(), "truncated object id"),
+        ] {
+            let e = tree_parse(&raw, ObjectFormat::Sha1).unwrap_err();
+            assert_eq!(e, format!("Malformed tree: {err}"));
+        }
+    }
+}
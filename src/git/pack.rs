@@ -0,0 +1,1480 @@
+use crate::git::hash::{ObjectFormat, ObjectHasher};
+use crate::git::objects::objects::{object_read_raw, sha_from_hex, sha_to_hex};
+use crate::git::repo::{repo_path, GitRepository};
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+/// Magic number starting a version 2 or later pack index
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+
+/// Offsets with this bit set point into the 64-bit offset table
+const IDX_LARGE_OFFSET: u32 = 0x8000_0000;
+
+/// Upper bound on the bytes kept in the delta base cache, as git's
+/// default `core.deltaBaseCacheLimit`
+const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+/// Name of a base object type as used in object headers
+pub fn pack_type_name(kind: u8) -> &'static [u8] {
+    match kind {
+        OBJ_COMMIT => b"commit",
+        OBJ_TREE => b"tree",
+        OBJ_BLOB => b"blob",
+        OBJ_TAG => b"tag",
+        _ => panic!("Malformed pack: bad object type {kind}"),
+    }
+}
+
+/// Object type of an object header name, see `pack_type_name`
+pub fn pack_type_from_name(fmt: &[u8]) -> u8 {
+    match fmt {
+        b"commit" => OBJ_COMMIT,
+        b"tree" => OBJ_TREE,
+        b"blob" => OBJ_BLOB,
+        b"tag" => OBJ_TAG,
+        _ => panic!("Unknown type {}", String::from_utf8_lossy(fmt)),
+    }
+}
+
+/// A pack index (`objects/pack/pack-*.idx`), version 1 or 2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitPackIndex {
+    pub version: u32,
+    fanout: Vec<u32>,
+    /// Sorted binary object ids, concatenated
+    names: Vec<u8>,
+    /// CRC32 of each packed entry, empty for version 1
+    pub crcs: Vec<u32>,
+    pub offsets: Vec<u64>,
+    /// Checksum of the pack this index describes
+    pub pack_checksum: Vec<u8>,
+    pub format: ObjectFormat,
+}
+
+impl GitPackIndex {
+    /// Parse the binary index format, versions 1 and 2
+    pub fn parse(raw: &[u8], format: ObjectFormat) -> Self {
+        let hash_len = format.raw_len();
+        assert!(
+            raw.len() >= 256 * 4 + 2 * hash_len,
+            "Malformed pack index: too short"
+        );
+        let (body, checksum) = raw.split_at(raw.len() - hash_len);
+        if format.digest(body) != checksum {
+            panic!("Malformed pack index: bad checksum");
+        }
+        let pack_checksum = body[body.len() - hash_len..].to_vec();
+
+        let (version, mut pos) = if &raw[0..4] == IDX_MAGIC {
+            let version = read_u32(raw, 4);
+            if version != 2 {
+                panic!("Unsupported pack index version {version}");
+            }
+            (version, 8)
+        } else {
+            (1, 0)
+        };
+
+        let fanout: Vec<u32> = (0..256).map(|i| read_u32(raw, pos + i * 4)).collect();
+        if fanout.windows(2).any(|w| w[0] > w[1]) {
+            panic!("Malformed pack index: non-monotonic fanout");
+        }
+        pos += 256 * 4;
+        let count = fanout[255] as usize;
+
+        let mut names = Vec::with_capacity(count * hash_len);
+        let mut crcs = Vec::new();
+        let mut offsets = Vec::with_capacity(count);
+        if version == 1 {
+            let entries = raw
+                .get(pos..pos + count * (4 + hash_len))
+                .expect("Malformed pack index: truncated entries");
+            for entry in entries.chunks(4 + hash_len) {
+                offsets.push(read_u32(entry, 0) as u64);
+                names.extend(&entry[4..]);
+            }
+            pos += entries.len();
+        } else {
+            let tables = raw
+                .get(pos..pos + count * (hash_len + 8))
+                .expect("Malformed pack index: truncated entries");
+            names.extend(&tables[..count * hash_len]);
+            pos += count * hash_len;
+            crcs = (0..count).map(|i| read_u32(raw, pos + i * 4)).collect();
+            pos += count * 4;
+            let large = pos + count * 4;
+            let mut large_count = 0;
+            for i in 0..count {
+                let offset = read_u32(raw, pos + i * 4);
+                if offset & IDX_LARGE_OFFSET == 0 {
+                    offsets.push(offset as u64);
+                    continue;
+                }
+                let at = large + (offset & !IDX_LARGE_OFFSET) as usize * 8;
+                let bytes = body[..body.len() - hash_len]
+                    .get(at..at + 8)
+                    .expect("Malformed pack index: bad large offset");
+                offsets.push(u64::from_be_bytes(bytes.try_into().unwrap()));
+                large_count = large_count.max((offset & !IDX_LARGE_OFFSET) as usize + 1);
+            }
+            pos = large + large_count * 8;
+        }
+        assert_eq!(
+            pos,
+            body.len() - hash_len,
+            "Malformed pack index: trailing garbage"
+        );
+        if names
+            .chunks(hash_len)
+            .zip(names.chunks(hash_len).skip(1))
+            .any(|(a, b)| a >= b)
+        {
+            panic!("Malformed pack index: unsorted object ids");
+        }
+
+        GitPackIndex {
+            version,
+            fanout,
+            names,
+            crcs,
+            offsets,
+            pack_checksum,
+            format,
+        }
+    }
+
+    /// Index the (id, crc32, offset) entries of a pack
+    pub fn build(
+        version: u32,
+        mut entries: Vec<(Vec<u8>, u32, u64)>,
+        pack_checksum: &[u8],
+        format: ObjectFormat,
+    ) -> Self {
+        entries.sort();
+        let mut fanout = vec![0u32; 256];
+        for (sha, _, _) in &entries {
+            fanout[sha[0] as usize] += 1;
+        }
+        for i in 1..256 {
+            fanout[i] += fanout[i - 1];
+        }
+
+        GitPackIndex {
+            version,
+            fanout,
+            names: entries.iter().flat_map(|(sha, _, _)| sha.clone()).collect(),
+            crcs: match version {
+                1 => Vec::new(),
+                _ => entries.iter().map(|(_, crc, _)| *crc).collect(),
+            },
+            offsets: entries.iter().map(|(_, _, offset)| *offset).collect(),
+            pack_checksum: pack_checksum.to_vec(),
+            format,
+        }
+    }
+
+    /// Serialize into the binary index format, including the checksum
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        if self.version != 1 {
+            ret.extend(IDX_MAGIC);
+            ret.extend(self.version.to_be_bytes());
+        }
+        self.fanout.iter().for_each(|n| ret.extend(n.to_be_bytes()));
+
+        if self.version == 1 {
+            for (i, offset) in self.offsets.iter().enumerate() {
+                let offset: u32 = (*offset)
+                    .try_into()
+                    .expect("Pack too large for index version 1");
+                ret.extend(offset.to_be_bytes());
+                ret.extend(self.sha(i));
+            }
+        } else {
+            ret.extend(&self.names);
+            self.crcs
+                .iter()
+                .for_each(|crc| ret.extend(crc.to_be_bytes()));
+            let mut large = Vec::new();
+            for offset in &self.offsets {
+                if *offset < IDX_LARGE_OFFSET as u64 {
+                    ret.extend((*offset as u32).to_be_bytes());
+                } else {
+                    ret.extend((IDX_LARGE_OFFSET | (large.len() / 8) as u32).to_be_bytes());
+                    large.extend(offset.to_be_bytes());
+                }
+            }
+            ret.extend(large);
+        }
+
+        ret.extend(&self.pack_checksum);
+        let checksum = self.format.digest(&ret);
+        ret.extend(checksum);
+        ret
+    }
+
+    /// Number of objects in the pack
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Binary object id of the `i`-th entry, in sorted order
+    pub fn sha(&self, i: usize) -> &[u8] {
+        let hash_len = self.format.raw_len();
+        &self.names[i * hash_len..(i + 1) * hash_len]
+    }
+
+    /// Position of a binary object id among the entries
+    pub fn find(&self, sha: &[u8]) -> Option<usize> {
+        let first = sha[0] as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1]
+        } as usize;
+        let mut hi = self.fanout[first] as usize;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.sha(mid).cmp(sha) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Hex ids of the entries starting with a hex prefix
+    pub fn prefix_search(&self, prefix: &str) -> Vec<String> {
+        let nibbles: Vec<u8> = prefix
+            .chars()
+            .map(|c| c.to_digit(16).expect("Bad object id") as u8)
+            .collect();
+        if nibbles.len() > self.format.raw_len() * 2 {
+            return Vec::new();
+        }
+        // The lowest id with the prefix, which starts the matching range
+        let low: Vec<u8> = nibbles
+            .chunks(2)
+            .map(|c| c[0] << 4 | c.get(1).unwrap_or(&0))
+            .collect();
+        let matches = |sha: &[u8]| {
+            nibbles.iter().enumerate().all(|(i, n)| {
+                let byte = sha[i / 2];
+                *n == if i % 2 == 0 { byte >> 4 } else { byte & 0xf }
+            })
+        };
+
+        let first = low[0] as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1]
+        } as usize;
+        let hi = self.fanout[first] as usize;
+        let mut end = hi;
+        while lo < end {
+            let mid = (lo + end) / 2;
+            if self.sha(mid) < &low[..] {
+                lo = mid + 1;
+            } else {
+                end = mid;
+            }
+        }
+        (lo..hi)
+            .take_while(|i| matches(self.sha(*i)))
+            .map(|i| sha_to_hex(self.sha(i)))
+            .collect()
+    }
+}
+
+/// A packfile together with its index
+#[derive(Debug)]
+pub struct GitPack {
+    /// Path to the `.pack` file
+    pub path: PathBuf,
+    pub index: GitPackIndex,
+}
+
+/// Header of an entry in a packfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitPackEntry {
+    /// One of the `OBJ_*` types
+    pub kind: u8,
+    /// Inflated size of the entry data, the delta itself for deltas
+    pub size: usize,
+    /// Pack offset of the base of an `OBJ_OFS_DELTA`
+    pub base_offset: Option<u64>,
+    /// Binary id of the base of an `OBJ_REF_DELTA`
+    pub base_sha: Option<Vec<u8>>,
+    /// Offset of the compressed data following the header
+    pub data_offset: u64,
+}
+
+/// Read the header of the entry at `offset`
+pub fn pack_entry_read<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    format: ObjectFormat,
+) -> GitPackEntry {
+    reader
+        .seek(SeekFrom::Start(offset))
+        .expect("Failed to seek in pack");
+    let mut next = || {
+        let mut byte = [0u8];
+        reader
+            .read_exact(&mut byte)
+            .expect("Malformed pack: truncated entry");
+        byte[0]
+    };
+
+    let mut c = next();
+    let kind = (c >> 4) & 7;
+    let mut size = (c & 15) as usize;
+    let mut shift = 4;
+    let mut len = 1;
+    while c & 0x80 != 0 {
+        c = next();
+        size |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+        len += 1;
+    }
+
+    let mut base_offset = None;
+    let mut base_sha = None;
+    match kind {
+        OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => {}
+        OBJ_OFS_DELTA => {
+            c = next();
+            len += 1;
+            let mut distance = (c & 0x7f) as u64;
+            while c & 0x80 != 0 {
+                c = next();
+                len += 1;
+                distance = ((distance + 1) << 7) | (c & 0x7f) as u64;
+            }
+            if distance == 0 || distance > offset {
+                panic!("Malformed pack: delta base offset out of bound");
+            }
+            base_offset = Some(offset - distance);
+        }
+        OBJ_REF_DELTA => {
+            let sha: Vec<u8> = (0..format.raw_len()).map(|_| next()).collect();
+            len += format.raw_len();
+            base_sha = Some(sha);
+        }
+        _ => panic!("Malformed pack: bad object type {kind}"),
+    }
+
+    GitPackEntry {
+        kind,
+        size,
+        base_offset,
+        base_sha,
+        data_offset: offset + len as u64,
+    }
+}
+
+/// Encode the type and inflated size starting a pack entry
+pub fn pack_entry_header(kind: u8, size: usize) -> Vec<u8> {
+    let mut ret = vec![(kind << 4) | (size & 15) as u8];
+    let mut size = size >> 4;
+    while size > 0 {
+        *ret.last_mut().unwrap() |= 0x80;
+        ret.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+    ret
+}
+
+/// Encode the distance back to the base of an `OBJ_OFS_DELTA`
+fn pack_delta_offset(distance: u64) -> Vec<u8> {
+    let mut ret = vec![(distance & 0x7f) as u8];
+    let mut distance = distance >> 7;
+    while distance > 0 {
+        distance -= 1;
+        ret.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    ret.reverse();
+    ret
+}
+
+/// Inflate the data of an entry, checking it against the recorded size
+pub fn pack_entry_data<R: Read + Seek>(reader: &mut R, entry: &GitPackEntry) -> Vec<u8> {
+    reader
+        .seek(SeekFrom::Start(entry.data_offset))
+        .expect("Failed to seek in pack");
+    let mut data = Vec::with_capacity(entry.size);
+    ZlibDecoder::new(BufReader::new(reader))
+        .read_to_end(&mut data)
+        .expect("Malformed pack: failed to inflate entry");
+    if data.len() != entry.size {
+        panic!("Malformed pack: bad entry size");
+    }
+    data
+}
+
+fn delta_header_size(delta: &[u8], pos: &mut usize) -> usize {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let c = *delta.get(*pos).expect("Malformed delta: truncated header");
+        *pos += 1;
+        size |= ((c & 0x7f) as usize) << shift;
+        shift += 7;
+        if c & 0x80 == 0 {
+            return size;
+        }
+    }
+}
+
+/// Apply a delta to its base, producing the target object data
+pub fn delta_apply(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    if delta_header_size(delta, &mut pos) != base.len() {
+        panic!("Malformed delta: base size mismatch");
+    }
+    let size = delta_header_size(delta, &mut pos);
+
+    let mut ret = Vec::with_capacity(size);
+    while pos < delta.len() {
+        let cmd = delta[pos];
+        pos += 1;
+        if cmd & 0x80 != 0 {
+            // Copy from base: the low bits tell which offset and size
+            // bytes are present
+            let mut arg = |bits: u8, shift: usize| {
+                let mut value = 0;
+                for i in 0..bits {
+                    if cmd & (1 << (shift + i as usize)) != 0 {
+                        let b = *delta.get(pos).expect("Malformed delta: truncated copy");
+                        value |= (b as usize) << (i * 8);
+                        pos += 1;
+                    }
+                }
+                value
+            };
+            let offset = arg(4, 0);
+            let len = match arg(3, 4) {
+                0 => 0x10000,
+                len => len,
+            };
+            let chunk = base
+                .get(offset..offset + len)
+                .expect("Malformed delta: copy out of bound");
+            ret.extend(chunk);
+        } else if cmd != 0 {
+            let chunk = delta
+                .get(pos..pos + cmd as usize)
+                .expect("Malformed delta: truncated insert");
+            ret.extend(chunk);
+            pos += cmd as usize;
+        } else {
+            panic!("Malformed delta: unexpected opcode 0");
+        }
+    }
+    if ret.len() != size {
+        panic!("Malformed delta: result size mismatch");
+    }
+
+    ret
+}
+
+/// Size of the base blocks indexed when looking for copies
+const DELTA_BLOCK: usize = 16;
+
+/// Candidates kept per block hash, bounding the work on repetitive data
+const DELTA_BUCKET_LIMIT: usize = 64;
+
+fn delta_block_hash(block: &[u8]) -> u64 {
+    let lo = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let hi = u64::from_le_bytes(block[8..DELTA_BLOCK].try_into().unwrap());
+    (lo.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ hi).wrapping_mul(0xff51_afd7_ed55_8ccd)
+}
+
+fn delta_header_push(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+fn delta_insert_push(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        out.push(chunk.len() as u8);
+        out.extend(chunk);
+    }
+}
+
+fn delta_copy_push(out: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let chunk = len.min(0x10000);
+        let at = out.len();
+        out.push(0x80);
+        for i in 0..4 {
+            let b = (offset >> (i * 8)) as u8;
+            if b != 0 {
+                out[at] |= 1 << i;
+                out.push(b);
+            }
+        }
+        // A size of 0x10000 is encoded as no size bytes at all
+        for i in 0..3 {
+            let b = ((chunk & 0xffff) >> (i * 8)) as u8;
+            if b != 0 {
+                out[at] |= 1 << (4 + i);
+                out.push(b);
+            }
+        }
+        offset += chunk;
+        len -= chunk;
+    }
+}
+
+/// Block index of a delta base, reused for every target tried against it
+pub struct DeltaIndex {
+    base: Rc<Vec<u8>>,
+    blocks: HashMap<u64, Vec<usize>>,
+}
+
+impl DeltaIndex {
+    pub fn new(base: Rc<Vec<u8>>) -> Self {
+        let mut blocks: HashMap<u64, Vec<usize>> = HashMap::new();
+        for at in (0..base.len().saturating_sub(DELTA_BLOCK - 1)).step_by(DELTA_BLOCK) {
+            let bucket = blocks
+                .entry(delta_block_hash(&base[at..at + DELTA_BLOCK]))
+                .or_default();
+            if bucket.len() < DELTA_BUCKET_LIMIT {
+                bucket.push(at);
+            }
+        }
+        DeltaIndex { base, blocks }
+    }
+
+    /// Encode `target` as copies from the base and inserts, giving up
+    /// once the delta grows past `max_size`
+    pub fn create(&self, target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        let base = &self.base[..];
+        let mut out = Vec::new();
+        delta_header_push(&mut out, base.len());
+        delta_header_push(&mut out, target.len());
+
+        let mut pending = 0;
+        let mut pos = 0;
+        while pos + DELTA_BLOCK <= target.len() {
+            let hash = delta_block_hash(&target[pos..pos + DELTA_BLOCK]);
+            let (mut from, mut len) = (0, 0);
+            for &at in self.blocks.get(&hash).into_iter().flatten() {
+                let common = base[at..]
+                    .iter()
+                    .zip(&target[pos..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if common > len {
+                    (from, len) = (at, common);
+                }
+            }
+            if len < DELTA_BLOCK {
+                pos += 1;
+                continue;
+            }
+
+            // Grow the copy backwards over bytes pending insertion
+            let mut start = pos;
+            while start > pending && from > 0 && base[from - 1] == target[start - 1] {
+                (from, start, len) = (from - 1, start - 1, len + 1);
+            }
+            delta_insert_push(&mut out, &target[pending..start]);
+            delta_copy_push(&mut out, from, len);
+            pos = start + len;
+            pending = pos;
+            if out.len() > max_size {
+                return None;
+            }
+        }
+        delta_insert_push(&mut out, &target[pending..]);
+
+        (out.len() <= max_size).then_some(out)
+    }
+}
+
+/// Hash of the path an object was found at, so that sorting by it puts
+/// versions of the same file next to each other
+pub fn pack_name_hash(name: &[u8]) -> u32 {
+    name.iter()
+        .copied()
+        .filter(|c| !c.is_ascii_whitespace())
+        .fold(0u32, |hash, c| (hash >> 2).wrapping_add((c as u32) << 24))
+}
+
+/// An object selected for writing into a pack
+#[derive(Debug, Clone)]
+pub struct GitPackObject {
+    /// Binary object id
+    pub sha: Vec<u8>,
+    /// One of the base `OBJ_*` types
+    pub kind: u8,
+    pub data: Rc<Vec<u8>>,
+    /// See `pack_name_hash`
+    pub name_hash: u32,
+    /// Position of the delta base among the packed objects, and the delta
+    pub delta: Option<(usize, Vec<u8>)>,
+    /// Length of the delta chain down to a full object
+    pub depth: usize,
+}
+
+/// Choose delta bases: each object is tried against the `window` objects
+/// before it when sorted by type, name hash and size, keeping the
+/// smallest delta whose chain stays within `max_depth`
+pub fn pack_find_deltas(objects: &mut [GitPackObject], window: usize, max_depth: usize) {
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+    order.sort_by(|&a, &b| {
+        let (x, y) = (&objects[a], &objects[b]);
+        y.kind
+            .cmp(&x.kind)
+            .then(y.name_hash.cmp(&x.name_hash))
+            .then(y.data.len().cmp(&x.data.len()))
+            .then(a.cmp(&b))
+    });
+
+    let mut candidates: VecDeque<(usize, DeltaIndex)> = VecDeque::new();
+    for i in order {
+        let target = objects[i].data.clone();
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for (j, index) in candidates.iter().rev() {
+            let base = &objects[*j];
+            if base.kind != objects[i].kind || base.depth >= max_depth {
+                continue;
+            }
+            // Deeper bases must earn their place with smaller deltas
+            let (max_size, ref_depth) = match &best {
+                Some((k, delta)) => (delta.len() - 1, objects[*k].depth + 1),
+                None => ((target.len() / 2).saturating_sub(objects[i].sha.len()), 1),
+            };
+            let max_size = max_size * (max_depth - base.depth) / (max_depth - ref_depth + 1);
+            let growth = target.len().saturating_sub(base.data.len());
+            if max_size == 0 || growth >= max_size || target.len() < base.data.len() / 32 {
+                continue;
+            }
+            if let Some(delta) = index.create(&target, max_size) {
+                best = Some((*j, delta));
+            }
+        }
+        if let Some((j, delta)) = best {
+            objects[i].depth = objects[j].depth + 1;
+            objects[i].delta = Some((j, delta));
+        }
+
+        if window > 0 {
+            if candidates.len() == window {
+                candidates.pop_front();
+            }
+            candidates.push_back((i, DeltaIndex::new(target)));
+        }
+    }
+}
+
+/// Write a version 2 pack of the objects, bases ahead of their deltas,
+/// returning its checksum and index
+pub fn pack_write<W: Write>(
+    out: &mut W,
+    objects: &[GitPackObject],
+    format: ObjectFormat,
+) -> (Vec<u8>, GitPackIndex) {
+    struct Writer<'a, W: Write> {
+        out: &'a mut W,
+        hasher: ObjectHasher,
+        offset: u64,
+    }
+
+    impl<W: Write> Writer<'_, W> {
+        fn write(&mut self, data: &[u8]) {
+            self.out.write_all(data).expect("Failed to write pack");
+            self.hasher.update(data);
+            self.offset += data.len() as u64;
+        }
+
+        fn write_one(
+            &mut self,
+            objects: &[GitPackObject],
+            i: usize,
+            offsets: &mut Vec<Option<u64>>,
+            entries: &mut Vec<(Vec<u8>, u32, u64)>,
+        ) {
+            if offsets[i].is_some() {
+                return;
+            }
+            let obj = &objects[i];
+            let mut raw = match &obj.delta {
+                Some((base, delta)) => {
+                    self.write_one(objects, *base, offsets, entries);
+                    let mut raw = pack_entry_header(OBJ_OFS_DELTA, delta.len());
+                    raw.extend(pack_delta_offset(self.offset - offsets[*base].unwrap()));
+                    raw
+                }
+                None => pack_entry_header(obj.kind, obj.data.len()),
+            };
+            let data = match &obj.delta {
+                Some((_, delta)) => &delta[..],
+                None => &obj.data[..],
+            };
+            let mut encoder = ZlibEncoder::new(raw, Compression::default());
+            encoder.write_all(data).expect("Failed to compress object");
+            raw = encoder.finish().expect("Failed to compress object");
+
+            let mut crc = Crc::new();
+            crc.update(&raw);
+            offsets[i] = Some(self.offset);
+            entries.push((obj.sha.clone(), crc.sum(), self.offset));
+            self.write(&raw);
+        }
+    }
+
+    let mut writer = Writer {
+        out,
+        hasher: format.hasher(),
+        offset: 0,
+    };
+    writer.write(b"PACK");
+    writer.write(&2u32.to_be_bytes());
+    writer.write(&(objects.len() as u32).to_be_bytes());
+
+    let mut offsets = vec![None; objects.len()];
+    let mut entries = Vec::with_capacity(objects.len());
+    for i in 0..objects.len() {
+        writer.write_one(objects, i, &mut offsets, &mut entries);
+    }
+
+    let checksum = writer.hasher.finalize();
+    writer
+        .out
+        .write_all(&checksum)
+        .expect("Failed to write pack");
+    let index = GitPackIndex::build(2, entries, &checksum, format);
+    (checksum, index)
+}
+
+/// Object id of an object given its base type and data
+pub fn pack_object_sha(kind: u8, data: &[u8], format: ObjectFormat) -> Vec<u8> {
+    let mut hasher = format.hasher();
+    hasher.update(pack_type_name(kind));
+    hasher.update(format!(" {}\0", data.len()).as_bytes());
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// An entry of a pack stream, with its deltas resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitPackResolved {
+    pub offset: u64,
+    pub entry: GitPackEntry,
+    /// Bytes taken by the entry in the pack, header included
+    pub packed_size: u64,
+    pub crc: u32,
+    /// Binary object id
+    pub sha: Vec<u8>,
+    /// Base type of the object, even when stored as a delta
+    pub kind: u8,
+    /// Length of the delta chain down to a full object
+    pub depth: usize,
+    /// Binary id of the delta base
+    pub base: Option<Vec<u8>>,
+}
+
+/// Parse a whole pack stream and resolve its deltas, hashing objects on
+/// all available cores. Returns the resolved entries in pack order, and
+/// the ids of delta bases missing from the pack (a thin pack).
+pub fn pack_resolve(
+    pack: &[u8],
+    format: ObjectFormat,
+) -> Result<(Vec<GitPackResolved>, Vec<Vec<u8>>), String> {
+    let (resolved, missing, _) = pack_resolve_thin(pack, format, &|_| None)?;
+    Ok((resolved, missing))
+}
+
+/// Type and data of a delta base from outside a pack
+pub type PackBase = (u8, Vec<u8>);
+
+/// Resolved entries, ids of missing bases, and the bases found outside
+pub type PackThinResolved = (Vec<GitPackResolved>, Vec<Vec<u8>>, Vec<PackBase>);
+
+/// Like `pack_resolve`, but looks bases missing from the pack up with
+/// `lookup`, which gives their type and data. Also returns the bases that
+/// were used that way, which completing the pack takes. The ids still
+/// missing are those of bases neither found nor made inside the pack.
+pub fn pack_resolve_thin(
+    pack: &[u8],
+    format: ObjectFormat,
+    lookup: &dyn Fn(&[u8]) -> Option<PackBase>,
+) -> Result<PackThinResolved, String> {
+    let hash_len = format.raw_len();
+    if pack.len() < 12 + hash_len || &pack[0..4] != b"PACK" {
+        return Err("pack signature mismatch".to_string());
+    }
+    let version = read_u32(pack, 4);
+    if version != 2 && version != 3 {
+        return Err(format!("pack version {version} unsupported"));
+    }
+    let count = read_u32(pack, 8) as usize;
+    let (body, checksum) = pack.split_at(pack.len() - hash_len);
+
+    // Inflate every entry in order, noting where each one ends
+    let mut reader = std::io::Cursor::new(body);
+    let mut entries = Vec::with_capacity(count);
+    let mut datas = Vec::with_capacity(count);
+    let mut offset = 12;
+    for _ in 0..count {
+        if offset >= body.len() as u64 {
+            return Err("pack is truncated".to_string());
+        }
+        let entry = pack_entry_read(&mut reader, offset, format);
+        let mut decoder = ZlibDecoder::new(&body[entry.data_offset as usize..]);
+        let mut data = Vec::with_capacity(entry.size);
+        if let Err(e) = decoder.read_to_end(&mut data) {
+            // Report the zlib status like git: Z_BUF_ERROR or Z_DATA_ERROR
+            let status = match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => -5,
+                _ => -3,
+            };
+            return Err(format!(
+                "pack has bad object at offset {offset}: inflate returned {status}"
+            ));
+        }
+        if data.len() != entry.size {
+            return Err(format!("pack has bad object at offset {offset}: bad size"));
+        }
+        let end = entry.data_offset + decoder.total_in();
+        let mut crc = Crc::new();
+        crc.update(&body[offset as usize..end as usize]);
+        entries.push(GitPackResolved {
+            offset,
+            entry,
+            packed_size: end - offset,
+            crc: crc.sum(),
+            sha: Vec::new(),
+            kind: 0,
+            depth: 0,
+            base: None,
+        });
+        datas.push(data);
+        offset = end;
+    }
+    if offset != body.len() as u64 {
+        return Err("pack has junk at the end".to_string());
+    }
+    if format.digest(body) != checksum {
+        let name = format.name().to_uppercase();
+        return Err(format!("pack is corrupted ({name} mismatch)"));
+    }
+
+    let by_offset: HashMap<u64, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (e.offset, i))
+        .collect();
+    let mut ofs_children: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut ref_children: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    let mut roots = Vec::new();
+    for (i, e) in entries.iter().enumerate() {
+        if let Some(base) = e.entry.base_offset {
+            match by_offset.get(&base) {
+                Some(&j) => ofs_children.entry(j).or_default().push(i),
+                None => return Err(format!("pack has bad delta base at offset {}", e.offset)),
+            }
+        } else if let Some(base) = &e.entry.base_sha {
+            ref_children.entry(base.clone()).or_default().push(i);
+        } else {
+            roots.push(i);
+        }
+    }
+
+    // Resolve the deltas below a full object, which is one of the entries
+    // or, without an index, a base from outside the pack
+    let resolve_below = |root: Option<usize>, kind: u8, data: &[u8]| {
+        let mut ret = Vec::new();
+        let mut stack = vec![(root, Cow::from(data), 0, None)];
+        while let Some((i, data, depth, base)) = stack.pop() {
+            let sha = pack_object_sha(kind, &data, format);
+            let children = i.and_then(|i| ofs_children.get(&i)).into_iter().flatten();
+            let children = children.chain(ref_children.get(&sha).into_iter().flatten());
+            for &child in children {
+                let target = delta_apply(&data, &datas[child]);
+                stack.push((Some(child), target.into(), depth + 1, Some(sha.clone())));
+            }
+            if let Some(i) = i {
+                ret.push(GitPackResolved {
+                    sha,
+                    kind,
+                    depth,
+                    base,
+                    ..entries[i].clone()
+                });
+            }
+        }
+        ret
+    };
+
+    // Each worker walks the delta trees below its share of full objects
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(1, roots.len().max(1));
+    let mut resolved: Vec<GitPackResolved> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|t| {
+                let (roots, entries, datas) = (&roots, &entries, &datas);
+                let resolve_below = &resolve_below;
+                scope.spawn(move || {
+                    roots
+                        .iter()
+                        .skip(t)
+                        .step_by(threads)
+                        .flat_map(|&root| {
+                            resolve_below(Some(root), entries[root].entry.kind, &datas[root])
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("Failed to resolve deltas"))
+            .collect()
+    });
+
+    // Outside bases are looked up in the order of the first delta on them,
+    // as a base made by an earlier delta is no longer missing once that
+    // delta is resolved
+    let mut known: HashSet<Vec<u8>> = resolved.iter().map(|e| e.sha.clone()).collect();
+    let mut wanted: Vec<(u64, &Vec<u8>)> = ref_children
+        .iter()
+        .map(|(sha, children)| (entries[children[0]].offset, sha))
+        .collect();
+    wanted.sort();
+    let mut outside = Vec::new();
+    for (_, sha) in wanted {
+        if known.contains(sha) {
+            continue;
+        }
+        if let Some((kind, data)) = lookup(sha) {
+            let below = resolve_below(None, kind, &data);
+            known.extend(below.iter().map(|e| e.sha.clone()));
+            known.insert(sha.clone());
+            resolved.extend(below);
+            outside.push((kind, data));
+        }
+    }
+    resolved.sort_by_key(|e| e.offset);
+    // An entry below a base that is in the pack twice is resolved twice
+    resolved.dedup_by_key(|e| e.offset);
+
+    let mut missing: Vec<Vec<u8>> = ref_children
+        .keys()
+        .filter(|sha| !known.contains(*sha))
+        .cloned()
+        .collect();
+    missing.sort();
+
+    Ok((resolved, missing, outside))
+}
+
+/// Complete a thin pack by appending the given objects in full
+pub fn pack_append_objects(pack: &mut Vec<u8>, objects: &[(u8, Vec<u8>)], format: ObjectFormat) {
+    pack.truncate(pack.len() - format.raw_len());
+    let count = read_u32(pack, 8) + objects.len() as u32;
+    pack[8..12].copy_from_slice(&count.to_be_bytes());
+    for (kind, data) in objects {
+        let mut encoder =
+            ZlibEncoder::new(pack_entry_header(*kind, data.len()), Compression::default());
+        encoder.write_all(data).expect("Failed to compress object");
+        pack.extend(encoder.finish().expect("Failed to compress object"));
+    }
+    let checksum = format.digest(pack);
+    pack.extend(checksum);
+}
+
+/// Type and data of an unpacked object, shared with the base cache
+pub type PackedObject = (u8, Rc<Vec<u8>>);
+
+/// Recently used delta bases, by pack path and offset
+#[derive(Default)]
+struct DeltaBaseCache {
+    entries: HashMap<(PathBuf, u64), PackedObject>,
+    order: VecDeque<(PathBuf, u64)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&self, path: &Path, offset: u64) -> Option<PackedObject> {
+        self.entries.get(&(path.to_path_buf(), offset)).cloned()
+    }
+
+    fn add(&mut self, path: &Path, offset: u64, kind: u8, data: Rc<Vec<u8>>) {
+        let key = (path.to_path_buf(), offset);
+        if data.len() > DELTA_BASE_CACHE_LIMIT || self.entries.contains_key(&key) {
+            return;
+        }
+        self.size += data.len();
+        while self.size > DELTA_BASE_CACHE_LIMIT {
+            let old = self.order.pop_front().unwrap();
+            self.size -= self.entries.remove(&old).unwrap().1.len();
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (kind, data));
+    }
+}
+
+type PackListCache = HashMap<PathBuf, (Option<SystemTime>, Rc<Vec<GitPack>>)>;
+
+thread_local! {
+    /// Packs of each object directory, with the directory mtime they were
+    /// listed at
+    static PACKS: RefCell<PackListCache> =
+        RefCell::new(HashMap::new());
+    static DELTA_BASES: RefCell<DeltaBaseCache> = RefCell::new(DeltaBaseCache::default());
+}
+
+/// All packs of a repository which have both an index and a pack file
+pub fn pack_list(repo: &GitRepository) -> Rc<Vec<GitPack>> {
+    let dir = repo_path(repo, "objects/pack");
+    let mtime = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+    if let Some(packs) = PACKS.with_borrow(|packs| {
+        packs
+            .get(&dir)
+            .filter(|(time, _)| *time == mtime)
+            .map(|(_, packs)| packs.clone())
+    }) {
+        return packs;
+    }
+
+    let mut names: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .map(|e| e.expect("Failed to read pack directory").path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "idx"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    let packs: Vec<GitPack> = names
+        .into_iter()
+        .filter(|idx| idx.with_extension("pack").is_file())
+        .map(|idx| GitPack {
+            path: idx.with_extension("pack"),
+            index: GitPackIndex::parse(
+                &fs::read(&idx).expect("Failed to read pack index"),
+                repo.object_format,
+            ),
+        })
+        .collect();
+    let packs = Rc::new(packs);
+    PACKS.with_borrow_mut(|cache| cache.insert(dir, (mtime, packs.clone())));
+    packs
+}
+
+/// Forget the packs listed so far, after packs were added or removed
+pub fn pack_list_reset() {
+    PACKS.with_borrow_mut(|packs| packs.clear());
+}
+
+/// Unpack the entry at `offset`, resolving deltas, as (type, data)
+pub fn pack_unpack(repo: &GitRepository, pack: &GitPack, offset: u64) -> PackedObject {
+    let file = File::open(&pack.path).expect("Failed to open pack file");
+    let mut reader = BufReader::new(file);
+
+    // Follow the chain down to a base we have, then apply deltas back up
+    let mut chain = Vec::new();
+    let mut at = Some(offset);
+    let (kind, mut data) = loop {
+        let offset = at.unwrap();
+        if let Some(hit) = DELTA_BASES.with_borrow(|cache| cache.get(&pack.path, offset)) {
+            break hit;
+        }
+        let entry = pack_entry_read(&mut reader, offset, pack.index.format);
+        match entry.kind {
+            OBJ_OFS_DELTA => {
+                chain.push((offset, pack_entry_data(&mut reader, &entry)));
+                at = entry.base_offset;
+            }
+            OBJ_REF_DELTA => {
+                chain.push((offset, pack_entry_data(&mut reader, &entry)));
+                let base = entry.base_sha.unwrap();
+                at = pack.index.find(&base).map(|i| pack.index.offsets[i]);
+                if at.is_none() {
+                    let sha = sha_to_hex(&base);
+                    let (fmt, data) = object_read_raw(repo, &sha)
+                        .unwrap_or_else(|| panic!("Missing delta base {sha}"));
+                    break (pack_type_from_name(&fmt), Rc::new(data));
+                }
+            }
+            kind => break (kind, Rc::new(pack_entry_data(&mut reader, &entry))),
+        }
+        if chain.len() > 10000 {
+            panic!("Malformed pack: delta chain too long");
+        }
+    };
+
+    while let Some((offset, delta)) = chain.pop() {
+        if let Some(at) = at {
+            DELTA_BASES.with_borrow_mut(|cache| cache.add(&pack.path, at, kind, data.clone()));
+        }
+        data = Rc::new(delta_apply(&data, &delta));
+        at = Some(offset);
+    }
+
+    (kind, data)
+}
+
+/// Read an object from the packs of a repository as (fmt, data)
+pub fn pack_object_read(repo: &GitRepository, sha: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    if !repo.object_format.is_sha(sha) {
+        return None;
+    }
+    let raw = sha_from_hex(&sha.to_lowercase());
+    let packs = pack_list(repo);
+    let (pack, i) = packs
+        .iter()
+        .find_map(|pack| pack.index.find(&raw).map(|i| (pack, i)))?;
+    let (kind, data) = pack_unpack(repo, pack, pack.index.offsets[i]);
+    let data = Rc::try_unwrap(data).unwrap_or_else(|data| (*data).clone());
+    Some((pack_type_name(kind).to_vec(), data))
+}
+
+/// Find all packed objects whose id starts with the given hex prefix
+pub fn pack_prefix_search(repo: &GitRepository, prefix: &str) -> Vec<String> {
+    pack_list(repo)
+        .iter()
+        .flat_map(|pack| pack.index.prefix_search(prefix))
+        .collect()
+}
+
+fn read_u32(raw: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(
+        raw.get(pos..pos + 4)
+            .expect("Malformed pack index: truncated")
+            .try_into()
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::repo::repo_scratch;
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn blob_sha(data: &[u8]) -> Vec<u8> {
+        pack_object_sha(OBJ_BLOB, data, ObjectFormat::Sha1)
+    }
+
+    #[test]
+    fn test_delta_apply() {
+        // Copy "hello " from the base, then insert "there!"
+        let delta = b"\x0b\x0c\x91\x00\x06\x06there!";
+        assert_eq!(delta_apply(b"hello world", delta), b"hello there!");
+    }
+
+    #[test]
+    fn test_delta_create() {
+        let base: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect();
+        let mut target = base.clone();
+        target.splice(1000..1000, b"inserted".iter().copied());
+        target.truncate(60_000);
+        target.extend(b"tail");
+
+        let index = DeltaIndex::new(Rc::new(base.clone()));
+        let delta = index.create(&target, 1000).unwrap();
+        assert!(delta.len() < 100);
+        assert_eq!(delta_apply(&base, &delta), target);
+        // Sizes take 2 and 1 bytes, the insert 1 plus the data
+        assert_eq!(index.create(b"unrelated", 1000).unwrap().len(), 14);
+        assert_eq!(index.create(&target, 10), None);
+    }
+
+    #[test]
+    fn test_pack_index_versions() {
+        for (format, version) in [
+            (ObjectFormat::Sha1, 1),
+            (ObjectFormat::Sha1, 2),
+            (ObjectFormat::Sha256, 2),
+        ] {
+            let hash_len = format.raw_len();
+            let mut entries = vec![
+                (vec![0x10; hash_len], 1, 12),
+                (vec![0xab; hash_len], 2, 1 << 33),
+                (vec![0x00; hash_len], 3, 40),
+            ];
+            if version == 1 {
+                entries[1].2 = 99;
+            }
+            let checksum = vec![7u8; hash_len];
+            let built = GitPackIndex::build(version, entries.clone(), &checksum, format);
+            let index = GitPackIndex::parse(&built.serialize(), format);
+            assert_eq!(index, built);
+            assert_eq!(index.version, version);
+            assert_eq!(index.len(), 3);
+            assert_eq!(index.pack_checksum, checksum);
+            assert_eq!(index.sha(0), &vec![0x00; hash_len][..]);
+            for (sha, _, offset) in &entries {
+                assert_eq!(index.offsets[index.find(sha).unwrap()], *offset);
+            }
+            assert_eq!(index.find(&vec![0x11; hash_len]), None);
+            assert_eq!(index.prefix_search("ab"), vec!["ab".repeat(hash_len)]);
+            assert_eq!(index.prefix_search("1"), vec!["10".repeat(hash_len)]);
+            assert_eq!(index.prefix_search("101"), vec!["10".repeat(hash_len)]);
+            assert_eq!(index.prefix_search("1010a"), Vec::<String>::new());
+            assert_eq!(index.prefix_search("0000"), vec!["00".repeat(hash_len)]);
+            assert_eq!(
+                index.prefix_search(&"ab".repeat(hash_len + 1)),
+                Vec::<String>::new()
+            );
+
+            // Several ids in the same fanout bucket
+            let id = |start: &[u8]| {
+                let mut sha = start.to_vec();
+                sha.resize(hash_len, 0);
+                sha
+            };
+            let entries = [
+                &[0xab, 0x00][..],
+                &[0xab, 0x11],
+                &[0xab, 0x12],
+                &[0xab, 0x20],
+            ]
+            .iter()
+            .enumerate()
+            .map(|(i, start)| (id(start), 0, i as u64 * 10))
+            .collect();
+            let index = GitPackIndex::build(version, entries, &checksum, format);
+            let found = index.prefix_search("ab1");
+            assert_eq!(
+                found,
+                vec![
+                    sha_to_hex(&id(&[0xab, 0x11])),
+                    sha_to_hex(&id(&[0xab, 0x12]))
+                ]
+            );
+            assert_eq!(index.prefix_search("ab3"), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn test_pack_write() {
+        let repo = repo_scratch("pack-write");
+        let versions: Vec<Vec<u8>> = (0..20)
+            .map(|i| format!("line\n{}", "common text\n".repeat(i + 10)).into_bytes())
+            .collect();
+        let mut objects: Vec<GitPackObject> = versions
+            .iter()
+            .map(|data| GitPackObject {
+                sha: blob_sha(data),
+                kind: OBJ_BLOB,
+                data: Rc::new(data.clone()),
+                name_hash: pack_name_hash(b"file.txt"),
+                delta: None,
+                depth: 0,
+            })
+            .collect();
+        pack_find_deltas(&mut objects, 10, 3);
+        assert!(objects.iter().filter(|o| o.delta.is_some()).count() >= 15);
+        assert!(objects.iter().all(|o| o.depth <= 3));
+
+        let mut pack = Vec::new();
+        let (checksum, index) = pack_write(&mut pack, &objects, ObjectFormat::Sha1);
+        assert_eq!(index.len(), objects.len());
+        let (entries, _) = pack_resolve(&pack, ObjectFormat::Sha1).unwrap();
+        let resolved = entries
+            .iter()
+            .map(|e| (e.sha.clone(), e.crc, e.offset))
+            .collect();
+        assert_eq!(
+            GitPackIndex::build(2, resolved, &checksum, ObjectFormat::Sha1),
+            index
+        );
+        let dir = repo_path(&repo, "objects/pack");
+        fs::create_dir_all(&dir).unwrap();
+        let name = format!("pack-{}", sha_to_hex(&checksum));
+        fs::write(dir.join(format!("{name}.pack")), pack).unwrap();
+        fs::write(dir.join(format!("{name}.idx")), index.serialize()).unwrap();
+
+        for data in versions {
+            let sha = sha_to_hex(&blob_sha(&data));
+            assert_eq!(object_read_raw(&repo, &sha), Some((b"blob".to_vec(), data)));
+        }
+    }
+
+    #[test]
+    fn test_pack_resolve() {
+        for format in [ObjectFormat::Sha1, ObjectFormat::Sha256] {
+            let blob_sha = |data: &[u8]| pack_object_sha(OBJ_BLOB, data, format);
+            let base = b"hello world".to_vec();
+            let target = b"hello world, hello".to_vec();
+            let mut pack = b"PACK".to_vec();
+            pack.extend(2u32.to_be_bytes());
+            pack.extend(1u32.to_be_bytes());
+            let delta = b"\x0b\x12\x90\x0b\x07, hello";
+            pack.extend(pack_entry_header(OBJ_REF_DELTA, delta.len()));
+            pack.extend(blob_sha(&base));
+            pack.extend(deflate(delta));
+            let checksum = format.digest(&pack);
+            pack.extend(checksum);
+
+            // A thin pack: the base is not in the pack
+            let (entries, missing) = pack_resolve(&pack, format).unwrap();
+            assert!(entries.is_empty());
+            assert_eq!(missing, vec![blob_sha(&base)]);
+
+            pack_append_objects(&mut pack, &[(OBJ_BLOB, base.clone())], format);
+            let (entries, missing) = pack_resolve(&pack, format).unwrap();
+            assert!(missing.is_empty());
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].sha, blob_sha(&target));
+            assert_eq!(entries[0].kind, OBJ_BLOB);
+            assert_eq!(entries[0].depth, 1);
+            assert_eq!(entries[0].base, Some(blob_sha(&base)));
+            assert_eq!(entries[1].sha, blob_sha(&base));
+            assert_eq!(entries[1].depth, 0);
+
+            let last = pack.len() - 1;
+            pack[last] ^= 1;
+            let expected = match format {
+                ObjectFormat::Sha1 => "pack is corrupted (SHA1 mismatch)",
+                ObjectFormat::Sha256 => "pack is corrupted (SHA256 mismatch)",
+            };
+            assert_eq!(pack_resolve(&pack, format), Err(expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_pack_resolve_thin() {
+        let format = ObjectFormat::Sha1;
+        let text = |end: &str| format!("{}{end}", "common text\n".repeat(10)).into_bytes();
+        let (base, x, y, z) = (text("m"), text("mx"), text("mxy"), text("mz"));
+        let delta = |from: &[u8], to: &[u8]| {
+            DeltaIndex::new(Rc::new(from.to_vec()))
+                .create(to, usize::MAX)
+                .unwrap()
+        };
+        // A chain base -> x -> y, and z sharing the base with x
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(3u32.to_be_bytes());
+        for (from, to) in [(&base, &x), (&x, &y), (&base, &z)] {
+            let delta = delta(from, to);
+            pack.extend(pack_entry_header(OBJ_REF_DELTA, delta.len()));
+            pack.extend(blob_sha(from));
+            pack.extend(deflate(&delta));
+        }
+        let checksum = format.digest(&pack);
+        pack.extend(checksum);
+
+        let (entries, missing) = pack_resolve(&pack, format).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(missing, {
+            let mut missing = vec![blob_sha(&base), blob_sha(&x)];
+            missing.sort();
+            missing
+        });
+
+        // x is made inside the pack, so only the base is looked up, once,
+        // even where a copy of x is at hand as well
+        let asked = std::cell::RefCell::new(Vec::new());
+        let lookup = |sha: &[u8]| {
+            asked.borrow_mut().push(sha.to_vec());
+            [&base, &x]
+                .into_iter()
+                .find(|data| blob_sha(data) == sha)
+                .map(|data| (OBJ_BLOB, data.clone()))
+        };
+        let (entries, missing, outside) = pack_resolve_thin(&pack, format, &lookup).unwrap();
+        assert_eq!(*asked.borrow(), vec![blob_sha(&base)]);
+        assert_eq!(outside, vec![(OBJ_BLOB, base.clone())]);
+        assert!(missing.is_empty());
+        let shas: Vec<Vec<u8>> = entries.iter().map(|e| e.sha.clone()).collect();
+        assert_eq!(shas, vec![blob_sha(&x), blob_sha(&y), blob_sha(&z)]);
+        assert_eq!(entries[1].depth, 2);
+
+        pack_append_objects(&mut pack, &outside, format);
+        let (entries, missing) = pack_resolve(&pack, format).unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[3].sha, blob_sha(&base));
+    }
+
+    #[test]
+    fn test_pack_read() {
+        let repo = repo_scratch("pack-read");
+        let base = b"hello world".to_vec();
+        let ofs_target = b"hello there!".to_vec();
+        let ref_target = b"hello world, hello".to_vec();
+
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend(3u32.to_be_bytes());
+
+        let base_offset = pack.len() as u64;
+        pack.extend(pack_entry_header(OBJ_BLOB, base.len()));
+        pack.extend(deflate(&base));
+
+        let ofs_offset = pack.len() as u64;
+        let delta = b"\x0b\x0c\x91\x00\x06\x06there!";
+        pack.extend(pack_entry_header(OBJ_OFS_DELTA, delta.len()));
+        pack.push((ofs_offset - base_offset) as u8);
+        pack.extend(deflate(delta));
+
+        let ref_offset = pack.len() as u64;
+        let delta = b"\x0b\x12\x90\x0b\x07, hello";
+        pack.extend(pack_entry_header(OBJ_REF_DELTA, delta.len()));
+        pack.extend(blob_sha(&base));
+        pack.extend(deflate(delta));
+
+        let checksum = ObjectFormat::Sha1.digest(&pack);
+        pack.extend(&checksum);
+        let entries = vec![
+            (blob_sha(&base), 0, base_offset),
+            (blob_sha(&ofs_target), 0, ofs_offset),
+            (blob_sha(&ref_target), 0, ref_offset),
+        ];
+        let dir = repo_path(&repo, "objects/pack");
+        fs::create_dir_all(&dir).unwrap();
+        let name = format!("pack-{}", sha_to_hex(&checksum));
+        fs::write(dir.join(format!("{name}.pack")), pack).unwrap();
+        fs::write(
+            dir.join(format!("{name}.idx")),
+            GitPackIndex::build(2, entries, &checksum, ObjectFormat::Sha1).serialize(),
+        )
+        .unwrap();
+
+        for data in [base, ofs_target, ref_target] {
+            let sha = sha_to_hex(&blob_sha(&data));
+            assert_eq!(object_read_raw(&repo, &sha), Some((b"blob".to_vec(), data)));
+        }
+        assert_eq!(pack_object_read(&repo, &"0".repeat(40)), None);
+    }
+}
@@ -0,0 +1,518 @@
+use crate::git::hash::sha_is_null;
+use crate::git::ident::ident_or_default;
+use crate::git::repo::{repo_dir, repo_file, repo_path, GitRepository};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// How deep symbolic refs may be nested before we give up
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// An entry of the `packed-refs` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedRef {
+    pub sha: String,
+    /// What an annotated tag ultimately points to, from a `^` line
+    pub peeled: Option<String>,
+}
+
+/// Whether `name` is acceptable as a ref name, following the rules of
+/// `git check-ref-format`
+pub fn ref_check_format(name: &str) -> bool {
+    !name.is_empty()
+        && name != "@"
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name
+            .bytes()
+            .any(|c| c < 0x20 || c == 0x7f || b" ~^:?*[\\".contains(&c))
+        && name
+            .split('/')
+            .all(|c| !c.starts_with('.') && !c.ends_with(".lock"))
+}
+
+/// Read a loose ref file, returning its raw trimmed content
+fn ref_read_loose(repo: &GitRepository, name: &str) -> Option<String> {
+    let path = repo_path(repo, name);
+    if !path.is_file() {
+        return None;
+    }
+    let data = fs::read_to_string(path).expect("Failed to read ref");
+    Some(data.trim_end().to_string())
+}
+
+/// Resolve a reference to an object id, following symbolic refs
+pub fn ref_resolve(repo: &GitRepository, name: &str) -> Option<String> {
+    let name = ref_deref(repo, name);
+    match ref_read_loose(repo, &name) {
+        Some(data) => Some(data),
+        None => packed_refs_read(repo).remove(&name).map(|r| r.sha),
+    }
+}
+
+/// Follow symbolic refs starting at `name`, returning the name of the ref
+/// that holds an object id (which may not exist yet)
+pub fn ref_deref(repo: &GitRepository, name: &str) -> String {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match ref_symbolic(repo, &name) {
+            Some(target) => name = target,
+            None => return name,
+        }
+    }
+    panic!("Symbolic ref {name} nested too deeply");
+}
+
+/// Return the target of a symbolic ref such as `HEAD`, if it is one
+pub fn ref_symbolic(repo: &GitRepository, name: &str) -> Option<String> {
+    ref_read_loose(repo, name)?
+        .strip_prefix("ref: ")
+        .map(|t| t.to_string())
+}
+
+/// Expand a short ref name to the full name of an existing ref, trying
+/// `<name>`, `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`,
+/// `refs/remotes/<name>` and `refs/remotes/<name>/HEAD` in that order
+pub fn ref_dwim(repo: &GitRepository, name: &str) -> Option<String> {
+    // Only HEAD-like pseudo refs live directly in the git directory
+    let pseudo =
+        name.starts_with("refs/") || name.bytes().all(|c| c.is_ascii_uppercase() || c == b'_');
+
+    [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ]
+    .into_iter()
+    .enumerate()
+    .filter(|(i, _)| *i > 0 || pseudo)
+    .map(|(_, r)| r)
+    .find(|r| ref_resolve(repo, r).is_some())
+}
+
+/// Read the `packed-refs` file
+pub fn packed_refs_read(repo: &GitRepository) -> BTreeMap<String, PackedRef> {
+    let mut ret = BTreeMap::new();
+    let data = match fs::read_to_string(repo_path(repo, "packed-refs")) {
+        Ok(data) => data,
+        Err(_) => return ret,
+    };
+
+    let mut last: Option<String> = None;
+    for line in data.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let name = last
+                .as_ref()
+                .expect("Malformed packed-refs: orphan peel line");
+            let entry: &mut PackedRef = ret.get_mut(name).unwrap();
+            entry.peeled = Some(peeled.to_string());
+            continue;
+        }
+        let (sha, name) = line
+            .split_once(' ')
+            .expect("Malformed packed-refs: bad line");
+        ret.insert(
+            name.to_string(),
+            PackedRef {
+                sha: sha.to_string(),
+                peeled: None,
+            },
+        );
+        last = Some(name.to_string());
+    }
+
+    ret
+}
+
+/// Atomically replace the `packed-refs` file
+pub fn packed_refs_write(repo: &GitRepository, refs: &BTreeMap<String, PackedRef>) {
+    let mut data = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, r) in refs {
+        data.push_str(&format!("{} {name}\n", r.sha));
+        if let Some(peeled) = &r.peeled {
+            data.push_str(&format!("^{peeled}\n"));
+        }
+    }
+
+    let lock = RefLock::acquire(repo, "packed-refs");
+    lock.commit(data.as_bytes());
+}
+
+/// A `<name>.lock` file guarding updates to a ref
+struct RefLock {
+    path: PathBuf,
+    lock: PathBuf,
+}
+
+impl RefLock {
+    fn acquire(repo: &GitRepository, name: &str) -> Self {
+        let path = repo_file(repo, name, true).expect("Failed to get ref file");
+        let lock = PathBuf::from(format!("{}.lock", path.display()));
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(_) => RefLock { path, lock },
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => panic!(
+                "Unable to create '{}': File exists.\nAnother gitr process seems to be running in this repository",
+                lock.display()
+            ),
+            Err(e) => panic!("Unable to create '{}': {e}", lock.display()),
+        }
+    }
+
+    /// Write the new content and move it into place
+    fn commit(self, data: &[u8]) {
+        let mut file = fs::File::create(&self.lock).expect("Failed to write lock file");
+        file.write_all(data).expect("Failed to write lock file");
+        file.sync_all().expect("Failed to write lock file");
+        fs::rename(&self.lock, &self.path).expect("Failed to rename lock file");
+    }
+
+    /// Give up the lock without touching the ref
+    fn release(self) {
+        let _ = fs::remove_file(&self.lock);
+    }
+}
+
+/// Check the current value of a ref against `old` while holding its lock.
+///
+/// `old` of `None` skips the check, the null id requires that the ref does
+/// not exist yet.
+fn ref_verify_old(repo: &GitRepository, name: &str, old: Option<&str>, lock: RefLock) -> RefLock {
+    let old = match old {
+        Some(old) => old,
+        None => return lock,
+    };
+    let current = ref_resolve(repo, name);
+    let ok = match &current {
+        Some(current) => current == old,
+        None => sha_is_null(old),
+    };
+    if !ok {
+        lock.release();
+        match current {
+            Some(current) if sha_is_null(old) => {
+                panic!("Cannot lock ref '{name}': reference already exists ({current})")
+            }
+            Some(current) => {
+                panic!("Cannot lock ref '{name}': is at {current} but expected {old}")
+            }
+            None => panic!("Cannot lock ref '{name}': unable to resolve reference"),
+        }
+    }
+    lock
+}
+
+/// Point a ref at `new`, following symbolic refs.
+///
+/// The update happens through a `.lock` file so readers never see a partial
+/// write. When `old` is given the ref must currently hold that value (or not
+/// exist, for the null id), otherwise the update is refused. A reflog entry is
+/// written when `message` is given.
+pub fn ref_update(
+    repo: &GitRepository,
+    name: &str,
+    new: &str,
+    old: Option<&str>,
+    message: Option<&str>,
+) {
+    let target = ref_deref(repo, name);
+    if target != "HEAD" && !ref_check_format(&target) {
+        panic!("'{target}' is not a valid ref name");
+    }
+
+    let lock = RefLock::acquire(repo, &target);
+    let lock = ref_verify_old(repo, &target, old, lock);
+    let previous = ref_resolve(repo, &target).unwrap_or_else(|| repo.object_format.null_sha());
+    lock.commit(format!("{new}\n").as_bytes());
+
+    if let Some(message) = message {
+        reflog_append(repo, &target, &previous, new, message);
+        if target != "HEAD" && ref_symbolic(repo, "HEAD").as_deref() == Some(target.as_str()) {
+            reflog_append(repo, "HEAD", &previous, new, message);
+        }
+    }
+}
+
+/// Point `name` itself at `new`, replacing it even when it is a symbolic
+/// ref. This is how `HEAD` gets detached.
+pub fn ref_update_no_deref(repo: &GitRepository, name: &str, new: &str, message: Option<&str>) {
+    let lock = RefLock::acquire(repo, name);
+    let previous = ref_resolve(repo, name).unwrap_or_else(|| repo.object_format.null_sha());
+    // Like git, an update that changes nothing is not logged
+    let unchanged = previous == new && ref_symbolic(repo, name).is_none();
+    lock.commit(format!("{new}\n").as_bytes());
+
+    if let (Some(message), false) = (message, unchanged) {
+        reflog_append(repo, name, &previous, new, message);
+    }
+}
+
+/// Make `name` a symbolic ref pointing at `target`
+pub fn ref_symbolic_update(repo: &GitRepository, name: &str, target: &str) {
+    let lock = RefLock::acquire(repo, name);
+    lock.commit(format!("ref: {target}\n").as_bytes());
+}
+
+/// Delete a ref from both the loose and packed stores, returning whether it
+/// existed. `old` is checked as for `ref_update`.
+pub fn ref_delete(repo: &GitRepository, name: &str, old: Option<&str>) -> bool {
+    let lock = RefLock::acquire(repo, name);
+    let lock = ref_verify_old(repo, name, old, lock);
+
+    let mut existed = false;
+    let path = repo_path(repo, name);
+    if path.is_file() {
+        fs::remove_file(&path).expect("Failed to delete ref");
+        existed = true;
+    }
+
+    let mut packed = packed_refs_read(repo);
+    if packed.remove(name).is_some() {
+        packed_refs_write(repo, &packed);
+        existed = true;
+    }
+
+    let log = repo_path(repo, &format!("logs/{name}"));
+    if log.is_file() {
+        fs::remove_file(log).expect("Failed to delete reflog");
+    }
+
+    lock.release();
+    ref_prune_dirs(repo, &path);
+    existed
+}
+
+/// Remove the loose file of a ref that has been packed, provided it still
+/// holds `sha`
+pub fn ref_delete_loose(repo: &GitRepository, name: &str, sha: &str) {
+    let lock = RefLock::acquire(repo, name);
+    let path = repo_path(repo, name);
+    if ref_read_loose(repo, name).as_deref() == Some(sha) {
+        fs::remove_file(&path).expect("Failed to delete ref");
+    }
+    lock.release();
+    ref_prune_dirs(repo, &path);
+}
+
+/// Remove directories left empty after deleting the loose ref at `path`,
+/// keeping the top level ones such as `refs/heads` like git does
+fn ref_prune_dirs(repo: &GitRepository, path: &Path) {
+    let refs = repo_path(repo, "refs");
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d.parent() == Some(&refs) || !d.starts_with(&refs) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// A single line of a reflog
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub ident: String,
+    pub message: String,
+}
+
+/// Read the reflog of a ref, oldest entry first
+pub fn reflog_read(repo: &GitRepository, name: &str) -> Vec<ReflogEntry> {
+    let data = match fs::read_to_string(repo_path(repo, &format!("logs/{name}"))) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+
+    data.lines()
+        .filter_map(|line| {
+            let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+            let mut parts = head.splitn(3, ' ');
+            Some(ReflogEntry {
+                old: parts.next()?.to_string(),
+                new: parts.next()?.to_string(),
+                ident: parts.next()?.to_string(),
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Names of all refs that have a reflog, sorted
+pub fn reflog_list(repo: &GitRepository) -> Vec<String> {
+    fn walk(dir: &Path, name: &str, out: &mut Vec<String>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries {
+            let entry = entry.expect("Failed to read logs directory");
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let child = match name {
+                "" => file_name,
+                _ => format!("{name}/{file_name}"),
+            };
+            if entry.path().is_dir() {
+                walk(&entry.path(), &child, out);
+            } else {
+                out.push(child);
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    walk(&repo_path(repo, "logs"), "", &mut ret);
+    ret.sort();
+    ret
+}
+
+/// Append an entry to the reflog of `name`.
+///
+/// Like git, logs are kept for `HEAD`, branches and remote-tracking refs when
+/// `core.logallrefupdates` is set, and for any ref that already has a log.
+pub fn reflog_append(repo: &GitRepository, name: &str, old: &str, new: &str, message: &str) {
+    let path = repo_path(repo, &format!("logs/{name}"));
+    let enabled = repo.config.core.logallrefupdates.as_deref() == Some("true")
+        && (name == "HEAD" || name.starts_with("refs/heads/") || name.starts_with("refs/remotes/"));
+    if !enabled && !path.is_file() {
+        return;
+    }
+
+    let path = repo_file(repo, &format!("logs/{name}"), true).expect("Failed to get reflog file");
+    let message = message.lines().next().unwrap_or("");
+    let line = format!(
+        "{old} {new} {}\t{message}\n",
+        ident_or_default(repo, "COMMITTER")
+    );
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .expect("Failed to open reflog");
+    file.write_all(line.as_bytes())
+        .expect("Failed to write reflog");
+}
+
+/// List refs under `prefix` (e.g. `refs/tags`) from both the loose and packed
+/// stores, sorted by name. Loose refs take precedence over packed ones.
+pub fn ref_list(repo: &GitRepository, prefix: &str) -> Vec<(String, String)> {
+    let mut ret: BTreeMap<String, String> = packed_refs_read(repo)
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&format!("{prefix}/")))
+        .map(|(name, r)| (name, r.sha))
+        .collect();
+    ref_list_loose(repo, prefix, &mut ret);
+
+    ret.into_iter().collect()
+}
+
+fn ref_list_loose(repo: &GitRepository, prefix: &str, out: &mut BTreeMap<String, String>) {
+    let dir = match repo_dir(repo, prefix, false) {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    for entry in fs::read_dir(dir).expect("Failed to read refs directory") {
+        let entry = entry.unwrap();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let name = format!("{prefix}/{file_name}");
+        if entry.path().is_dir() {
+            ref_list_loose(repo, &name, out);
+        } else if file_name.ends_with(".lock") {
+            continue;
+        } else if let Some(sha) = ref_resolve(repo, &name) {
+            out.insert(name, sha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::repo::repo_scratch;
+
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_ref_update_and_resolve() {
+        let repo = repo_scratch("refs-update");
+        ref_update(
+            &repo,
+            "refs/heads/master",
+            A,
+            Some(&repo.object_format.null_sha()),
+            Some("create"),
+        );
+        assert_eq!(ref_resolve(&repo, "HEAD"), Some(A.to_string()));
+
+        // Updating HEAD goes through to the branch it points at
+        ref_update(&repo, "HEAD", B, Some(A), Some("move"));
+        assert_eq!(ref_resolve(&repo, "refs/heads/master"), Some(B.to_string()));
+        assert_eq!(
+            ref_symbolic(&repo, "HEAD"),
+            Some("refs/heads/master".to_string())
+        );
+
+        let log = reflog_read(&repo, "refs/heads/master");
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[1].old.as_str(), log[1].new.as_str()), (A, B));
+        assert_eq!(reflog_read(&repo, "HEAD").len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "but expected")]
+    fn test_ref_update_stale_old_value() {
+        let repo = repo_scratch("refs-stale");
+        ref_update(&repo, "refs/heads/master", A, None, None);
+        ref_update(&repo, "refs/heads/master", B, Some(B), None);
+    }
+
+    #[test]
+    fn test_packed_refs() {
+        let repo = repo_scratch("refs-packed");
+        fs::write(
+            repo_path(&repo, "packed-refs"),
+            format!("# pack-refs with: peeled fully-peeled sorted \n{A} refs/heads/master\n{B} refs/tags/v1\n^{A}\n"),
+        )
+        .unwrap();
+
+        let packed = packed_refs_read(&repo);
+        assert_eq!(packed["refs/tags/v1"].peeled.as_deref(), Some(A));
+        assert_eq!(ref_resolve(&repo, "HEAD"), Some(A.to_string()));
+
+        // Loose refs shadow packed ones
+        ref_update(&repo, "refs/heads/master", B, Some(A), None);
+        assert_eq!(
+            ref_list(&repo, "refs"),
+            vec![
+                ("refs/heads/master".to_string(), B.to_string()),
+                ("refs/tags/v1".to_string(), B.to_string()),
+            ]
+        );
+
+        assert!(ref_delete(&repo, "refs/heads/master", Some(B)));
+        assert_eq!(ref_resolve(&repo, "refs/heads/master"), None);
+        assert!(!packed_refs_read(&repo).contains_key("refs/heads/master"));
+        assert!(packed_refs_read(&repo).contains_key("refs/tags/v1"));
+    }
+
+    #[test]
+    fn test_ref_check_format() {
+        assert!(ref_check_format("refs/heads/feature/x"));
+        assert!(!ref_check_format("refs/heads/a..b"));
+        assert!(!ref_check_format("refs/heads/a b"));
+        assert!(!ref_check_format("refs/heads/x.lock"));
+        assert!(!ref_check_format("refs/heads/.hidden"));
+        assert!(!ref_check_format("refs/heads/x@{1}"));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::git::config::GitConfig;
+use crate::git::config::{config_global_paths, config_read, GitConfig};
+use crate::git::hash::ObjectFormat;
 use std::{fs, path::Path, path::PathBuf};
 
 /// Represents a Git Repository
@@ -7,6 +8,9 @@ pub struct GitRepository {
     pub worktree: PathBuf,
     pub gitdir: PathBuf,
     pub config: GitConfig,
+    /// Hash function of the object ids, SHA-1 unless the config asks for
+    /// another with `extensions.objectFormat`
+    pub object_format: ObjectFormat,
 }
 
 impl GitRepository {
@@ -20,16 +24,24 @@ impl GitRepository {
                 worktree: path,
                 gitdir: gitdir_path,
                 config: GitConfig::default(),
+                object_format: ObjectFormat::Sha1,
             };
             let config_path = repo_file(&repo, "config", false);
 
+            // The global layer comes first so the repository's own values win
+            let mut values: Vec<(String, String)> = config_global_paths()
+                .iter()
+                .filter_map(|path| config_read(path))
+                .flatten()
+                .collect();
             if config_path.is_some() {
-                if config_path.clone().unwrap().exists() {
-                    let read_config = fs::read_to_string(config_path.unwrap())
-                        .expect("Failed to read .git/config file");
-                    let config: GitConfig =
-                        serde_ini::from_str(&read_config).expect("Failed to parse .git/config");
-                    repo.config = config;
+                match config_read(&config_path.unwrap()) {
+                    Some(local) => {
+                        values.extend(local);
+                        repo.config = GitConfig::from_values(values);
+                    }
+                    // A repository being created starts from the defaults
+                    None => repo.config.values = values,
                 }
             } else if !force {
                 panic!(".git/config file missing");
@@ -42,8 +54,16 @@ impl GitRepository {
                     .repositoryformatversion
                     .as_ref()
                     .expect("No repositoryformatversion in .git/config");
-                if ver != "0" {
-                    panic!("Unsupported repositoryformatversion {}", ver);
+                match ver.as_str() {
+                    "0" => {}
+                    // Extensions are only honoured from version 1 on
+                    "1" => {
+                        if let Some(name) = repo.config.get("extensions.objectFormat") {
+                            repo.object_format = ObjectFormat::from_name(&name)
+                                .unwrap_or_else(|| panic!("Unknown object format {name}"));
+                        }
+                    }
+                    _ => panic!("Unsupported repositoryformatversion {}", ver),
                 }
             }
 
@@ -80,14 +100,14 @@ pub fn repo_dir(repo: &GitRepository, path: &str, mkdir: bool) -> Option<PathBuf
     let path = repo_path(repo, path);
     if path.exists() {
         match path.is_dir() {
-            true => return Some(PathBuf::from(path)),
+            true => return Some(path),
             false => panic!("Not a directory {}", path.display()),
         }
     }
 
     if mkdir {
         fs::create_dir_all(&path).expect("Failed to create directory");
-        return Some(PathBuf::from(path));
+        return Some(path);
     }
     None
 }
@@ -101,7 +121,7 @@ pub fn repo_find(path: &str, required: bool) -> Option<GitRepository> {
     if gitdir.is_dir() {
         return Some(GitRepository::new(path.to_str().unwrap(), false));
     }
-    let parent = path.join("../");
+    let parent = Path::canonicalize(&path.join("../")).expect("Failed to canonicalize path");
 
     if parent == path {
         if required {
@@ -118,3 +138,19 @@ pub fn repo_find(path: &str, required: bool) -> Option<GitRepository> {
         required,
     )
 }
+
+/// Create a fresh repository in a scratch directory for tests
+#[cfg(test)]
+pub fn repo_scratch(name: &str) -> GitRepository {
+    repo_scratch_format(name, ObjectFormat::Sha1)
+}
+
+/// Like `repo_scratch`, with objects named by the given hash function
+#[cfg(test)]
+pub fn repo_scratch_format(name: &str, format: ObjectFormat) -> GitRepository {
+    let path = std::env::temp_dir().join(format!("gitr-test-{}-{name}", std::process::id()));
+    if path.exists() {
+        fs::remove_dir_all(&path).expect("Failed to clean scratch directory");
+    }
+    crate::git::helpers::cmd_repo_create(path.to_str().unwrap(), format)
+}
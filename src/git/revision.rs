@@ -0,0 +1,1358 @@
+use crate::cli::ObjectType;
+use crate::git::helpers::{
+    object_abbrev, object_find, object_peel, object_peel_tags, object_resolve,
+};
+use crate::git::ident::ident_parse;
+use crate::git::objects::objects::object_read;
+use crate::git::objects::{GitCommit, GitTree};
+use crate::git::refs::{ref_dwim, ref_list, ref_resolve, ref_symbolic, reflog_read};
+use crate::git::repo::GitRepository;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::process;
+
+/// Resolve a revision expression to an object id.
+///
+/// Understands git's revision grammar: a name optionally followed by
+/// `@{upstream}` or `@{N}`, any number of `^`, `^N`, `~N`, `^{type}`,
+/// `^{}` and `^{/regex}` suffixes, and an optional `:path` into the tree.
+/// `:/regex` finds the youngest commit reachable from any ref whose message
+/// matches.
+pub fn rev_parse(repo: &GitRepository, spec: &str) -> Option<String> {
+    if let Some(pattern) = spec.strip_prefix(":/") {
+        let mut tips: Vec<String> = ref_list(repo, "refs")
+            .into_iter()
+            .map(|(_, sha)| sha)
+            .collect();
+        tips.extend(ref_resolve(repo, "HEAD"));
+        return rev_search_message(repo, tips, pattern);
+    }
+
+    if let Some(i) = find_outside_braces(spec, |c| c == b':') {
+        let (rev, path) = (&spec[..i], &spec[i + 1..]);
+        // Paths in the index (`:path`) are not supported
+        if rev.is_empty() {
+            return None;
+        }
+        let tree = object_peel(repo, &rev_parse(repo, rev)?, "tree", true)?;
+        return tree_lookup(repo, &tree, path);
+    }
+
+    let split = find_outside_braces(spec, |c| c == b'^' || c == b'~').unwrap_or(spec.len());
+    let (base, suffix) = spec.split_at(split);
+    let mut sha = rev_parse_base(repo, base)?;
+
+    let suffix = suffix.as_bytes();
+    let mut i = 0;
+    while i < suffix.len() {
+        let op = suffix[i];
+        i += 1;
+
+        if op == b'^' && suffix.get(i) == Some(&b'{') {
+            let close = suffix[i..].iter().position(|&c| c == b'}')? + i;
+            let inner = std::str::from_utf8(&suffix[i + 1..close]).ok()?;
+            sha = rev_peel(repo, &sha, inner)?;
+            i = close + 1;
+            continue;
+        }
+
+        let start = i;
+        while i < suffix.len() && suffix[i].is_ascii_digit() {
+            i += 1;
+        }
+        let n: usize = match start == i {
+            true => 1,
+            false => std::str::from_utf8(&suffix[start..i]).ok()?.parse().ok()?,
+        };
+
+        match op {
+            b'~' => {
+                for _ in 0..n {
+                    sha = commit_parent(repo, &sha, 1)?;
+                }
+            }
+            b'^' if n == 0 => sha = object_peel(repo, &sha, "commit", true)?,
+            b'^' => sha = commit_parent(repo, &sha, n)?,
+            _ => return None,
+        }
+    }
+
+    Some(sha)
+}
+
+/// Make the path of a `rev:./path` or `rev:../path` spec, given relative
+/// to the directory `prefix`, relative to the top of the worktree instead.
+/// Other specs are returned as they are, `None` if the path leads outside
+/// the worktree.
+pub fn rev_relative(spec: &str, prefix: &str) -> Option<String> {
+    let Some(i) = find_outside_braces(spec, |c| c == b':') else {
+        return Some(spec.to_string());
+    };
+    let (rev, path) = (&spec[..i], &spec[i + 1..]);
+    if !path.starts_with("./") && !path.starts_with("../") {
+        return Some(spec.to_string());
+    }
+    let mut parts: Vec<&str> = prefix.split('/').filter(|p| !p.is_empty()).collect();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            _ => parts.push(part),
+        }
+    }
+    Some(format!("{rev}:{}", parts.join("/")))
+}
+
+/// Position of the first byte matching `pred` that is not inside `{...}`
+fn find_outside_braces(spec: &str, pred: impl Fn(u8) -> bool) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in spec.bytes().enumerate() {
+        match c {
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            c if depth == 0 && pred(c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolve the name part of a revision, including `@{...}` selectors
+fn rev_parse_base(repo: &GitRepository, base: &str) -> Option<String> {
+    if base == "@" {
+        return ref_resolve(repo, "HEAD");
+    }
+
+    let (name, selector) = match base.find("@{") {
+        Some(i) => (&base[..i], base[i + 2..].strip_suffix('}')?),
+        None => {
+            let candidates = object_resolve(repo, base);
+            return match candidates.len() {
+                0 => None,
+                1 => Some(candidates[0].clone()),
+                _ => {
+                    eprint!("{}", rev_ambiguous(repo, base, &candidates));
+                    eprintln!(
+                        "fatal: ambiguous argument '{base}': unknown revision or path not in the working tree."
+                    );
+                    process::exit(128);
+                }
+            };
+        }
+    };
+
+    if selector.eq_ignore_ascii_case("upstream") || selector.eq_ignore_ascii_case("u") {
+        return ref_resolve(repo, &branch_upstream(repo, name)?);
+    }
+
+    let n: usize = selector.parse().ok()?;
+    let refname = match name {
+        "" => ref_symbolic(repo, "HEAD").unwrap_or_else(|| "HEAD".to_string()),
+        _ => ref_dwim(repo, name)?,
+    };
+    let log = reflog_read(repo, &refname);
+    if n == 0 && log.is_empty() {
+        return ref_resolve(repo, &refname);
+    }
+    log.iter().rev().nth(n).map(|e| e.new.clone())
+}
+
+/// git's report of a short object id that names more than one object
+fn rev_ambiguous(repo: &GitRepository, base: &str, candidates: &[String]) -> String {
+    let mut ret =
+        format!("error: short object ID {base} is ambiguous\nhint: The candidates are:\n");
+    for sha in candidates {
+        let kind = object_read(repo, sha).map(|obj| obj.fmt());
+        let kind = String::from_utf8_lossy(kind.as_deref().unwrap_or(b"unknown"));
+        ret += &format!("hint:   {} {kind}\n", object_abbrev(repo, sha, 7));
+    }
+    ret
+}
+
+/// Full ref name of the upstream of a branch, or of the current branch
+pub fn branch_upstream(repo: &GitRepository, branch: &str) -> Option<String> {
+    let branch = match branch {
+        "" | "HEAD" => ref_symbolic(repo, "HEAD")?
+            .strip_prefix("refs/heads/")?
+            .to_string(),
+        _ => branch.to_string(),
+    };
+
+    let remote = repo.config.get(&format!("branch.{branch}.remote"))?;
+    let merge = repo.config.get(&format!("branch.{branch}.merge"))?;
+    if remote == "." {
+        return Some(merge);
+    }
+    // The merged ref is named as on the remote; the first fetch refspec of
+    // the configured remote that takes it says where it is kept here
+    repo.config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .iter()
+        .find_map(|fetch| refspec_map(fetch, &merge))
+}
+
+/// Map a ref through a `[+]src:dst` refspec, where `src` and `dst` may
+/// each hold one `*`
+fn refspec_map(refspec: &str, name: &str) -> Option<String> {
+    let refspec = refspec.strip_prefix('+').unwrap_or(refspec);
+    let (src, dst) = refspec.split_once(':')?;
+    if dst.is_empty() {
+        return None;
+    }
+    match (src.split_once('*'), dst.split_once('*')) {
+        (Some((prefix, suffix)), Some((dst_prefix, dst_suffix))) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{dst_prefix}{matched}{dst_suffix}"))
+        }
+        (None, None) if src == name => Some(dst.to_string()),
+        _ => None,
+    }
+}
+
+/// Count the commits reachable from `a` but not `b`, and from `b` but not `a`
+///
+/// Walks back from both tips youngest first, passing on which side each
+/// commit is reachable from, and stops once everything left to walk is
+/// reachable from both.
+pub fn rev_ahead_behind(repo: &GitRepository, a: &str, b: &str) -> (usize, usize) {
+    const BOTH: u8 = 3;
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    // Parents of the commits queued so far
+    let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let enqueue = |queue: &mut BinaryHeap<_>, parents: &mut HashMap<_, _>, sha: String| {
+        let commit = commit_read(repo, &sha);
+        queue.push((commit.time(), sha.clone()));
+        parents.insert(sha, commit.parents());
+    };
+    for (sha, flag) in [(a, 1), (b, 2)] {
+        *flags.entry(sha.to_string()).or_default() |= flag;
+        enqueue(&mut queue, &mut parents, sha.to_string());
+    }
+    while queue.iter().any(|(_, sha)| flags[sha] != BOTH) {
+        let (_, sha) = queue.pop().unwrap();
+        let flag = flags[&sha];
+        for parent in parents[&sha].clone() {
+            let old = flags.get(&parent).copied().unwrap_or(0);
+            if old | flag != old {
+                flags.insert(parent.clone(), old | flag);
+                enqueue(&mut queue, &mut parents, parent);
+            }
+        }
+    }
+    let count = |side| flags.values().filter(|f| **f == side).count();
+    (count(1), count(2))
+}
+
+// Per commit state of the paint-down walk of `rev_merge_bases`
+
+/// Reachable from the first commit
+const PARENT1: u8 = 1;
+/// Reachable from one of the others
+const PARENT2: u8 = 1 << 1;
+/// Below a common ancestor, so not a best one
+const STALE: u8 = 1 << 2;
+/// Already taken as a common ancestor
+const RESULT: u8 = 1 << 3;
+
+/// The best common ancestors of two commits: those reachable from both
+/// that are not ancestors of another such commit
+pub fn rev_merge_bases(repo: &GitRepository, a: &str, b: &str) -> Vec<String> {
+    let (mut bases, _) = rev_paint_down(repo, a, &[b.to_string()]);
+    // A base found early may still be an ancestor of one found later
+    if bases.len() > 1 {
+        let mut redundant = vec![false; bases.len()];
+        for i in 0..bases.len() {
+            if redundant[i] {
+                continue;
+            }
+            let others: Vec<usize> = (0..bases.len())
+                .filter(|&j| j != i && !redundant[j])
+                .collect();
+            let twos: Vec<String> = others.iter().map(|&j| bases[j].clone()).collect();
+            let (_, flags) = rev_paint_down(repo, &bases[i], &twos);
+            let flag = |sha: &String| flags.get(sha).copied().unwrap_or(0);
+            if flag(&bases[i]) & PARENT2 != 0 {
+                redundant[i] = true;
+            }
+            for j in others {
+                if flag(&bases[j]) & PARENT1 != 0 {
+                    redundant[j] = true;
+                }
+            }
+        }
+        let mut redundant = redundant.into_iter();
+        bases.retain(|_| !redundant.next().unwrap());
+    }
+    bases.sort();
+    bases
+}
+
+/// git's paint-down walk, youngest commit first: what `one` reaches is
+/// marked PARENT1, what any of `twos` reaches PARENT2. A commit with both
+/// is a common ancestor and everything below it is STALE, so the walk
+/// stops once only stale commits are queued. Returns the common ancestors
+/// found that are not stale, with the flags of every commit visited.
+fn rev_paint_down(
+    repo: &GitRepository,
+    one: &str,
+    twos: &[String],
+) -> (Vec<String>, HashMap<String, u8>) {
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let push = |queue: &mut BinaryHeap<_>, sha: String| {
+        queue.push((commit_read(repo, &sha).time(), sha));
+    };
+    flags.insert(one.to_string(), PARENT1);
+    push(&mut queue, one.to_string());
+    for two in twos {
+        *flags.entry(two.clone()).or_default() |= PARENT2;
+        push(&mut queue, two.clone());
+    }
+
+    let mut ret = Vec::new();
+    while queue.iter().any(|(_, sha)| flags[sha] & STALE == 0) {
+        let (_, sha) = queue.pop().unwrap();
+        let mut flag = flags[&sha] & (PARENT1 | PARENT2 | STALE);
+        if flag == PARENT1 | PARENT2 {
+            if flags[&sha] & RESULT == 0 {
+                *flags.get_mut(&sha).unwrap() |= RESULT;
+                ret.push(sha.clone());
+            }
+            flag |= STALE;
+        }
+        for parent in commit_read(repo, &sha).parents() {
+            let old = flags.get(&parent).copied().unwrap_or(0);
+            if old & flag == flag {
+                continue;
+            }
+            flags.insert(parent.clone(), old | flag);
+            push(&mut queue, parent);
+        }
+    }
+    ret.retain(|sha| flags[sha] & STALE == 0);
+    (ret, flags)
+}
+
+/// How a `RevWalk` orders the commits it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RevSort {
+    /// Youngest committer date first, as the walk finds them
+    #[default]
+    Default,
+    /// No parent before its children, otherwise by committer date
+    Date,
+    /// No parent before its children, otherwise by author date
+    AuthorDate,
+    /// No parent before its children, keeping each line of history
+    /// together
+    Topo,
+}
+
+// Per commit state of a walk
+
+/// Found by the walk
+const SEEN: u8 = 1;
+/// Reachable from an excluded commit, never shown
+const UNINTERESTING: u8 = 1 << 1;
+/// Reachable from the left side of `A...B`
+const LEFT: u8 = 1 << 2;
+/// Excluded explicitly, a starting point for `--ancestry-path`
+const BOTTOM: u8 = 1 << 3;
+/// Same as a parent at the limiting paths, simplified away
+const TREESAME: u8 = 1 << 4;
+
+/// Commits still walked after only excluded ones are left, in case of
+/// clock skew
+const SLOP: usize = 5;
+
+/// Walks the commits reachable from a set of tips but not from a set of
+/// excluded commits, each commit once.
+///
+/// By default the walk goes youngest committer date first and returns
+/// commits as it finds them. Excluded commits, sorting, `ancestry_path` and
+/// path limiting need the whole history up front, which the first call to
+/// `next` works out.
+pub struct RevWalk<'a> {
+    repo: &'a GitRepository,
+    /// Commits waiting to be walked, by date then in the order they were
+    /// found
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    pending: HashMap<String, GitCommit>,
+    flags: HashMap<String, u8>,
+    /// Parents of the walked commits, after simplification
+    parents: HashMap<String, Vec<String>>,
+    counter: usize,
+    started: bool,
+    /// The commits left to return, when the walk had to be done up front
+    limited: Option<VecDeque<(String, GitCommit)>>,
+    /// Only follow the first parent of merges
+    pub first_parent: bool,
+    pub sort: RevSort,
+    /// Only show commits that descend from an excluded commit
+    pub ancestry_path: bool,
+    /// Skip commits older than this, and their parents
+    pub since: Option<i64>,
+    /// Skip commits younger than this
+    pub until: Option<i64>,
+    /// Only show commits whose author matches one of these
+    pub authors: Vec<Regex>,
+    /// Only show commits whose message matches one of these
+    pub greps: Vec<Regex>,
+    /// Only show commits that change these paths, simplifying the history
+    /// to the parents they came from
+    pub paths: Vec<String>,
+    /// Only show commits with at least and at most this many parents
+    pub min_parents: usize,
+    pub max_parents: Option<usize>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repo: &'a GitRepository) -> Self {
+        RevWalk {
+            repo,
+            queue: BinaryHeap::new(),
+            pending: HashMap::new(),
+            flags: HashMap::new(),
+            parents: HashMap::new(),
+            counter: 0,
+            started: false,
+            limited: None,
+            first_parent: false,
+            sort: RevSort::Default,
+            ancestry_path: false,
+            since: None,
+            until: None,
+            authors: Vec::new(),
+            greps: Vec::new(),
+            paths: Vec::new(),
+            min_parents: 0,
+            max_parents: None,
+        }
+    }
+
+    /// Start walking from a commit
+    pub fn push(&mut self, sha: &str) {
+        self.add(sha, 0);
+    }
+
+    /// Leave out a commit and everything reachable from it
+    pub fn hide(&mut self, sha: &str) {
+        self.add(sha, UNINTERESTING | BOTTOM);
+    }
+
+    /// Add the commits named by revision arguments: `A`, `^A`, `A..B`,
+    /// `A...B` (commits in either but not both, the left side marked) and
+    /// `--not`, which flips the meaning of the ones after it. An empty side
+    /// of a range means `HEAD`. Returns the argument that did not resolve
+    /// to a commit on error.
+    pub fn push_revs(&mut self, revs: &[String]) -> Result<(), String> {
+        let mut flags = 0;
+        for rev in revs {
+            if rev == "--not" {
+                flags ^= UNINTERESTING | BOTTOM;
+                continue;
+            }
+            let exclude = flags ^ (UNINTERESTING | BOTTOM);
+            let resolve = |name: &str| {
+                let name = if name.is_empty() { "HEAD" } else { name };
+                object_find(self.repo, name, Some(ObjectType::Commit), true).ok_or(rev.clone())
+            };
+
+            if let Some((a, b)) = rev.split_once("...") {
+                let (a, b) = (resolve(a)?, resolve(b)?);
+                for base in rev_merge_bases(self.repo, &a, &b) {
+                    self.add(&base, exclude);
+                }
+                self.add(&a, flags | LEFT);
+                self.add(&b, flags);
+            } else if let Some((a, b)) = rev.split_once("..") {
+                let (a, b) = (resolve(a)?, resolve(b)?);
+                self.add(&a, exclude);
+                self.add(&b, flags);
+            } else if let Some(a) = rev.strip_prefix('^') {
+                let a = resolve(a)?;
+                self.add(&a, exclude);
+            } else {
+                let a = resolve(rev)?;
+                self.add(&a, flags);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any commit was excluded explicitly
+    pub fn has_bottom(&self) -> bool {
+        self.flags.values().any(|f| f & BOTTOM != 0)
+    }
+
+    /// Whether a returned commit is reachable from the left side of a
+    /// symmetric difference
+    pub fn is_left(&self, sha: &str) -> bool {
+        self.flag(sha) & LEFT != 0
+    }
+
+    /// Whether a walked commit was excluded
+    pub fn is_uninteresting(&self, sha: &str) -> bool {
+        self.flag(sha) & UNINTERESTING != 0
+    }
+
+    /// Whether a commit is one the walk would show, for drawing edges to
+    /// parents
+    pub fn is_shown(&self, sha: &str) -> bool {
+        self.flag(sha) & SEEN != 0 && self.shows(sha, &commit_read(self.repo, sha))
+    }
+
+    /// The parents of a returned commit, skipping those that path limiting
+    /// simplified away
+    pub fn parents(&self, sha: &str) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for parent in self.parents.get(sha).into_iter().flatten() {
+            let mut parent = Some(parent);
+            while let Some(p) = parent.filter(|p| self.flag(p) & TREESAME != 0) {
+                parent = self.parents.get(p).and_then(|p| p.first());
+            }
+            if let Some(p) = parent.filter(|p| !ret.contains(p)) {
+                ret.push(p.clone());
+            }
+        }
+        ret
+    }
+
+    fn flag(&self, sha: &str) -> u8 {
+        self.flags.get(sha).copied().unwrap_or(0)
+    }
+
+    fn set_flag(&mut self, sha: &str, flag: u8) {
+        *self.flags.entry(sha.to_string()).or_default() |= flag;
+    }
+
+    /// Queue a commit unless it was already found. Excluding a commit that
+    /// was found also excludes what was found below it.
+    fn add(&mut self, sha: &str, flags: u8) {
+        if self.flag(sha) & SEEN != 0 {
+            self.set_flag(sha, flags & (LEFT | BOTTOM));
+            if flags & UNINTERESTING != 0 {
+                self.mark_uninteresting(sha);
+            }
+            return;
+        }
+        let commit = commit_read(self.repo, sha);
+        self.set_flag(sha, flags | SEEN);
+        self.queue
+            .push((commit.time(), Reverse(self.counter), sha.to_string()));
+        self.pending.insert(sha.to_string(), commit);
+        self.counter += 1;
+    }
+
+    fn mark_uninteresting(&mut self, sha: &str) {
+        let mut todo = vec![sha.to_string()];
+        while let Some(sha) = todo.pop() {
+            if self.flag(&sha) & UNINTERESTING != 0 {
+                continue;
+            }
+            self.set_flag(&sha, UNINTERESTING);
+            match (self.parents.get(&sha), self.pending.get(&sha)) {
+                (Some(parents), _) => todo.extend(parents.iter().cloned()),
+                (None, Some(commit)) => todo.extend(commit.parents()),
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Take a commit off the queue and queue its parents. With
+    /// `first_parent` only the first parent is walked, but the others still
+    /// count for the order of the commits.
+    fn process(&mut self, sha: &str, commit: &GitCommit) {
+        let flags = self.flag(sha);
+        let mut parents = commit.parents();
+
+        if flags & UNINTERESTING != 0 {
+            for parent in &parents {
+                self.add(parent, UNINTERESTING);
+            }
+        } else if self.since.is_some_and(|since| commit.time() < since) {
+            self.set_flag(sha, UNINTERESTING);
+            parents.clear();
+        } else {
+            if !self.paths.is_empty() {
+                parents = self.simplify(sha, commit, parents);
+            }
+            let walked = if self.first_parent { 1 } else { parents.len() };
+            for parent in &parents[..walked.min(parents.len())] {
+                self.add(parent, flags & LEFT);
+            }
+        }
+        self.parents.insert(sha.to_string(), parents);
+    }
+
+    /// With path limiting, follow only a parent that has the same content
+    /// at the paths, if there is one, and then leave the commit out
+    fn simplify(&mut self, sha: &str, commit: &GitCommit, parents: Vec<String>) -> Vec<String> {
+        let ours = self.path_ids(&commit.tree());
+        if parents.is_empty() {
+            if ours.iter().all(Option::is_none) {
+                self.set_flag(sha, TREESAME);
+            }
+            return parents;
+        }
+
+        let (mut relevant, mut relevant_change, mut irrelevant_change) = (0, false, false);
+        let compared = if self.first_parent { 1 } else { parents.len() };
+        for parent in &parents[..compared] {
+            // An excluded side branch can't take the place of the others
+            let is_relevant = self.flag(parent) & (UNINTERESTING | BOTTOM) != UNINTERESTING;
+            if is_relevant {
+                relevant += 1;
+            }
+            let same = self.path_ids(&commit_read(self.repo, parent).tree()) == ours;
+            match (same, is_relevant) {
+                (true, true) => {
+                    self.set_flag(sha, TREESAME);
+                    return vec![parent.clone()];
+                }
+                (true, false) => {}
+                (false, true) => relevant_change = true,
+                (false, false) => irrelevant_change = true,
+            }
+        }
+        if (relevant > 0 && !relevant_change) || (relevant == 0 && !irrelevant_change) {
+            self.set_flag(sha, TREESAME);
+        }
+        parents
+    }
+
+    fn path_ids(&self, tree: &str) -> Vec<Option<String>> {
+        self.paths
+            .iter()
+            .map(|path| tree_lookup(self.repo, tree, path))
+            .collect()
+    }
+
+    /// Whether a walked commit is to be shown
+    fn shows(&self, sha: &str, commit: &GitCommit) -> bool {
+        if self.flag(sha) & (UNINTERESTING | TREESAME) != 0 {
+            return false;
+        }
+        if self.until.is_some_and(|until| commit.time() > until) {
+            return false;
+        }
+        let parents = self
+            .parents
+            .get(sha)
+            .map_or(commit.parents().len(), Vec::len);
+        if parents < self.min_parents || self.max_parents.is_some_and(|max| parents > max) {
+            return false;
+        }
+        if !self.authors.is_empty() {
+            let author = commit.author().unwrap_or_default();
+            // Match the name and email, not the date after them
+            let author = author.rfind('>').map_or(&author[..], |i| &author[..=i]);
+            if !self.authors.iter().any(|re| re.is_match(author)) {
+                return false;
+            }
+        }
+        if !self.greps.is_empty() {
+            let message = commit.message();
+            let matches = |re: &Regex| message.lines().any(|line| re.is_match(line));
+            if !self.greps.iter().any(matches) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn is_limited(&self) -> bool {
+        self.sort != RevSort::Default
+            || self.ancestry_path
+            || !self.paths.is_empty()
+            || self.flags.values().any(|f| f & UNINTERESTING != 0)
+    }
+
+    /// Walk everything that might be shown, until only excluded commits
+    /// are left to walk
+    fn limit(&mut self) -> Vec<(String, GitCommit)> {
+        let mut ret = Vec::new();
+        let mut slop = SLOP;
+        let mut date = i64::MAX;
+        while let Some((_, _, sha)) = self.queue.pop() {
+            let commit = self.pending.remove(&sha).unwrap();
+            self.process(&sha, &commit);
+            if self.flag(&sha) & UNINTERESTING != 0 {
+                slop = self.still_interesting(date, slop);
+                if slop > 0 {
+                    continue;
+                }
+                break;
+            }
+            if self.until.is_some_and(|until| commit.time() > until) {
+                continue;
+            }
+            date = commit.time();
+            ret.push((sha, commit));
+        }
+
+        if self.ancestry_path {
+            self.limit_to_ancestry(&ret);
+        }
+        if self.sort != RevSort::Default {
+            ret = self.sort_topo(ret, self.sort);
+        }
+        ret.retain(|(sha, commit)| self.shows(sha, commit));
+        ret
+    }
+
+    fn still_interesting(&self, date: i64, slop: usize) -> usize {
+        let next = match self.queue.peek() {
+            Some((next, _, _)) => *next,
+            None => return 0,
+        };
+        let interesting =
+            |(_, _, sha): &(i64, Reverse<usize>, String)| self.flag(sha) & UNINTERESTING == 0;
+        if date <= next || self.queue.iter().any(interesting) {
+            return SLOP;
+        }
+        slop - 1
+    }
+
+    /// Leave out the walked commits that don't descend from an excluded one
+    fn limit_to_ancestry(&mut self, commits: &[(String, GitCommit)]) {
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (sha, _) in commits {
+            for parent in &self.parents[sha] {
+                children.entry(parent).or_default().push(sha);
+            }
+        }
+        let mut todo: Vec<&str> = self
+            .flags
+            .iter()
+            .filter(|(_, f)| *f & BOTTOM != 0)
+            .map(|(sha, _)| sha.as_str())
+            .collect();
+        let mut marked: HashSet<&str> = todo.iter().copied().collect();
+        while let Some(sha) = todo.pop() {
+            for &child in children.get(sha).into_iter().flatten() {
+                if marked.insert(child) {
+                    todo.push(child);
+                }
+            }
+        }
+        let unmarked: Vec<String> = commits
+            .iter()
+            .filter(|(sha, _)| !marked.contains(sha.as_str()))
+            .map(|(sha, _)| sha.clone())
+            .collect();
+        for sha in unmarked {
+            self.set_flag(&sha, UNINTERESTING);
+        }
+    }
+
+    /// Reorder commits so that no parent comes before any of its children.
+    /// `Topo` takes the most recently freed parent next, which keeps lines
+    /// of history together the way `log --graph` draws them.
+    fn sort_topo(
+        &self,
+        commits: Vec<(String, GitCommit)>,
+        sort: RevSort,
+    ) -> Vec<(String, GitCommit)> {
+        // One more than the number of children still to be shown
+        let mut indegree: HashMap<String, usize> =
+            commits.iter().map(|(sha, _)| (sha.clone(), 1)).collect();
+        for (sha, _) in &commits {
+            for parent in &self.parents[sha] {
+                if let Some(n) = indegree.get_mut(parent) {
+                    *n += 1;
+                }
+            }
+        }
+        let date = |commit: &GitCommit| match sort {
+            RevSort::AuthorDate => commit
+                .author()
+                .as_deref()
+                .and_then(ident_parse)
+                .map_or(0, |(_, _, secs, _)| secs),
+            _ => commit.time(),
+        };
+
+        // Topological order takes the last parent freed first, the others
+        // the youngest. The tips go first, in the order they were walked.
+        let key = |commit: &GitCommit, order: i64| match sort {
+            RevSort::Topo => (0, order),
+            _ => (date(commit), -order),
+        };
+        let tips: Vec<&(String, GitCommit)> = commits
+            .iter()
+            .filter(|(sha, _)| indegree[sha] == 1)
+            .collect();
+        let mut queue: BinaryHeap<((i64, i64), String)> = tips
+            .iter()
+            .enumerate()
+            .map(|(i, (sha, commit))| {
+                let order = match sort {
+                    RevSort::Topo => -(i as i64),
+                    _ => i as i64,
+                };
+                (key(commit, order), sha.clone())
+            })
+            .collect();
+        let mut order = tips.len() as i64;
+        let mut pending: HashMap<String, GitCommit> = commits.into_iter().collect();
+
+        let mut ret = Vec::with_capacity(pending.len());
+        while let Some((_, sha)) = queue.pop() {
+            let commit = pending.remove(&sha).unwrap();
+            for parent in &self.parents[&sha] {
+                match indegree.get_mut(parent) {
+                    Some(n) if *n > 0 => {
+                        *n -= 1;
+                        if *n == 1 {
+                            queue.push((key(&pending[parent], order), parent.clone()));
+                            order += 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            indegree.insert(sha.clone(), 0);
+            ret.push((sha, commit));
+        }
+        ret
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = (String, GitCommit);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            if self.is_limited() {
+                self.limited = Some(self.limit().into());
+            }
+        }
+        if let Some(limited) = &mut self.limited {
+            return limited.pop_front();
+        }
+
+        loop {
+            let (_, _, sha) = self.queue.pop()?;
+            let commit = self.pending.remove(&sha).unwrap();
+            self.process(&sha, &commit);
+            if self.shows(&sha, &commit) {
+                return Some((sha, commit));
+            }
+        }
+    }
+}
+
+/// Read a commit that is known to exist
+pub fn commit_read(repo: &GitRepository, sha: &str) -> GitCommit {
+    let obj = object_read(repo, sha).unwrap_or_else(|| panic!("Failed to read commit {sha}"));
+    obj.as_any()
+        .downcast_ref::<GitCommit>()
+        .unwrap_or_else(|| panic!("Object {sha} is not a commit"))
+        .clone()
+}
+
+/// Apply a `^{...}` suffix
+fn rev_peel(repo: &GitRepository, sha: &str, inner: &str) -> Option<String> {
+    match inner {
+        "" => object_peel_tags(repo, sha),
+        "object" => object_read(repo, sha).map(|_| sha.to_string()),
+        "commit" | "tree" | "blob" | "tag" => object_peel(repo, sha, inner, true),
+        _ => {
+            let pattern = inner.strip_prefix('/')?;
+            let sha = object_peel(repo, sha, "commit", true)?;
+            rev_search_message(repo, vec![sha], pattern)
+        }
+    }
+}
+
+/// The `n`th parent (starting at 1) of a commit-ish
+fn commit_parent(repo: &GitRepository, sha: &str, n: usize) -> Option<String> {
+    let sha = object_peel(repo, sha, "commit", true)?;
+    let obj = object_read(repo, &sha)?;
+    let commit = obj.as_any().downcast_ref::<GitCommit>()?;
+    commit.parents().get(n - 1).cloned()
+}
+
+/// Youngest commit reachable from `tips` whose message matches `pattern`
+fn rev_search_message(repo: &GitRepository, tips: Vec<String>, pattern: &str) -> Option<String> {
+    let re = Regex::new(pattern).ok()?;
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+
+    let read_commit = |sha: &str| {
+        let obj = object_read(repo, sha)?;
+        let commit = obj.as_any().downcast_ref::<GitCommit>()?;
+        Some((commit.time(), commit.message(), commit.parents()))
+    };
+
+    for tip in tips {
+        if let Some(sha) = object_peel(repo, &tip, "commit", true) {
+            if let Some(commit) = read_commit(&sha) {
+                queue.push((commit.0, sha));
+            }
+        }
+    }
+
+    while let Some((_, sha)) = queue.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        let (_, message, parents) = read_commit(&sha)?;
+        if re.is_match(&message) {
+            return Some(sha);
+        }
+        for parent in parents {
+            if let Some(commit) = read_commit(&parent) {
+                queue.push((commit.0, parent));
+            }
+        }
+    }
+
+    None
+}
+
+/// Find the object at `path` inside a tree
+fn tree_lookup(repo: &GitRepository, tree: &str, path: &str) -> Option<String> {
+    let mut sha = tree.to_string();
+    for name in path.split('/').filter(|n| !n.is_empty()) {
+        let obj = object_read(repo, &sha)?;
+        let tree = obj.as_any().downcast_ref::<GitTree>()?;
+        sha = tree
+            .items
+            .iter()
+            .find(|l| l.path == name.as_bytes())?
+            .sha
+            .clone();
+    }
+    Some(sha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::objects::kvlm::Dict;
+    use crate::git::objects::objects::object_write;
+    use crate::git::objects::tree::GitTreeLeaf;
+    use crate::git::refs::ref_update;
+    use crate::git::repo::{repo_path, repo_scratch};
+
+    fn commit(repo: &GitRepository, tree: &str, parents: &[&str], msg: &str, time: i64) -> String {
+        let mut kvlm = Dict::new();
+        kvlm.insert(Some(b"tree".to_vec()), vec![tree.as_bytes().to_vec()]);
+        if !parents.is_empty() {
+            let parents = parents.iter().map(|p| p.as_bytes().to_vec()).collect();
+            kvlm.insert(Some(b"parent".to_vec()), parents);
+        }
+        let ident = format!("A U Thor <a@example.com> {time} +0000");
+        kvlm.insert(Some(b"author".to_vec()), vec![ident.clone().into_bytes()]);
+        kvlm.insert(Some(b"committer".to_vec()), vec![ident.into_bytes()]);
+        kvlm.insert(None, vec![format!("{msg}\n").into_bytes()]);
+        let obj = Box::new(GitCommit {
+            fmt: b"commit".to_vec(),
+            kvlm,
+        });
+        object_write(obj, Some(repo.clone()))
+    }
+
+    #[test]
+    fn test_rev_parse_suffixes() {
+        let repo = repo_scratch("rev-parse-suffixes");
+        let blob = object_write(
+            Box::new(crate::git::objects::GitBlob {
+                fmt: b"blob".to_vec(),
+                blobdata: b"hello\n".to_vec(),
+            }),
+            Some(repo.clone()),
+        );
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![GitTreeLeaf {
+                    mode: "100644".to_string(),
+                    path: b"hello.txt".to_vec(),
+                    sha: blob.clone(),
+                }],
+            }),
+            Some(repo.clone()),
+        );
+
+        let a = commit(&repo, &tree, &[], "first", 1);
+        let b = commit(&repo, &tree, &[&a], "second", 2);
+        let c = commit(&repo, &tree, &[&a], "side", 3);
+        let d = commit(&repo, &tree, &[&b, &c], "merge", 4);
+        ref_update(&repo, "refs/heads/master", &d, None, None);
+
+        let parse = |spec: &str| rev_parse(&repo, spec);
+        assert_eq!(parse("HEAD"), Some(d.clone()));
+        assert_eq!(parse("HEAD^"), Some(b.clone()));
+        assert_eq!(parse("HEAD^2"), Some(c.clone()));
+        assert_eq!(parse("HEAD~2"), Some(a.clone()));
+        assert_eq!(parse("master^2~1"), Some(a.clone()));
+        assert_eq!(parse("HEAD^3"), None);
+        assert_eq!(parse("HEAD^{tree}"), Some(tree.clone()));
+        assert_eq!(parse("HEAD:hello.txt"), Some(blob.clone()));
+        assert_eq!(parse(":/^sec"), Some(b.clone()));
+        assert_eq!(parse("HEAD^{/first}"), Some(a.clone()));
+    }
+
+    #[test]
+    fn test_rev_parse_upstream() {
+        let mut repo = repo_scratch("rev-parse-upstream");
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![],
+            }),
+            Some(repo.clone()),
+        );
+        let a = commit(&repo, &tree, &[], "first", 1);
+        let b = commit(&repo, &tree, &[&a], "second", 2);
+        ref_update(&repo, "refs/heads/master", &a, None, None);
+        ref_update(&repo, "refs/remotes/origin/master", &b, None, None);
+        assert_eq!(rev_parse(&repo, "master@{u}"), None);
+
+        // Only the second of the remote's fetch refspecs takes branch heads
+        repo.config
+            .add("remote.origin.fetch", "+refs/tags/*:refs/tags/*");
+        repo.config
+            .add("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*");
+        repo.config.add("branch.master.remote", "origin");
+        repo.config.add("branch.master.merge", "refs/heads/master");
+        assert_eq!(rev_parse(&repo, "master@{u}"), Some(b.clone()));
+        assert_eq!(rev_parse(&repo, "@{upstream}~1"), Some(a.clone()));
+    }
+
+    #[test]
+    fn test_rev_ambiguous() {
+        let repo = repo_scratch("rev-ambiguous");
+        let mut seen: Vec<String> = Vec::new();
+        // Write blobs until two share their first four hex digits
+        for i in 0.. {
+            let sha = object_write(
+                Box::new(crate::git::objects::GitBlob {
+                    fmt: b"blob".to_vec(),
+                    blobdata: i.to_string().into_bytes(),
+                }),
+                Some(repo.clone()),
+            );
+            if let Some(other) = seen.iter().find(|s| s[..4] == sha[..4]) {
+                let mut candidates = object_resolve(&repo, &sha[..4]);
+                candidates.sort();
+                let mut expected = vec![other.clone(), sha.clone()];
+                expected.sort();
+                assert_eq!(candidates, expected);
+                assert_eq!(
+                    rev_ambiguous(&repo, &sha[..4], &candidates),
+                    format!(
+                        "error: short object ID {} is ambiguous\n\
+                         hint: The candidates are:\n\
+                         hint:   {} blob\n\
+                         hint:   {} blob\n",
+                        &sha[..4],
+                        &expected[0][..7],
+                        &expected[1][..7]
+                    )
+                );
+                break;
+            }
+            seen.push(sha);
+        }
+    }
+
+    #[test]
+    fn test_rev_relative() {
+        let rel = |spec: &str, prefix: &str| rev_relative(spec, prefix);
+        assert_eq!(
+            rel("HEAD:./f", "sub/dir").as_deref(),
+            Some("HEAD:sub/dir/f")
+        );
+        assert_eq!(rel("HEAD:../f", "sub/dir").as_deref(), Some("HEAD:sub/f"));
+        assert_eq!(rel("HEAD:./", "sub").as_deref(), Some("HEAD:sub"));
+        assert_eq!(rel("HEAD:./../top", "sub").as_deref(), Some("HEAD:top"));
+        assert_eq!(rel("HEAD:./f", "").as_deref(), Some("HEAD:f"));
+        assert_eq!(
+            rel("HEAD^{/a:b}:./f", "sub").as_deref(),
+            Some("HEAD^{/a:b}:sub/f")
+        );
+        // Paths without a leading `./` or `../` are from the top already
+        assert_eq!(rel("HEAD:f", "sub").as_deref(), Some("HEAD:f"));
+        assert_eq!(rel("HEAD~2", "sub").as_deref(), Some("HEAD~2"));
+        assert_eq!(rel("HEAD:../../f", "sub"), None);
+    }
+
+    #[test]
+    fn test_rev_walk() {
+        let repo = repo_scratch("rev-walk");
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![],
+            }),
+            Some(repo.clone()),
+        );
+        let root = commit(&repo, &tree, &[], "root", 100);
+        let side = commit(&repo, &tree, &[&root], "side", 300);
+        let main = commit(&repo, &tree, &[&root], "main", 200);
+        let merge = commit(&repo, &tree, &[&main, &side], "merge", 400);
+
+        let mut walk = RevWalk::new(&repo);
+        walk.push(&merge);
+        let order: Vec<String> = walk.map(|(sha, _)| sha).collect();
+        assert_eq!(
+            order,
+            vec![merge.clone(), side.clone(), main.clone(), root.clone()]
+        );
+
+        let mut walk = RevWalk::new(&repo);
+        walk.first_parent = true;
+        walk.push(&merge);
+        let order: Vec<String> = walk.map(|(sha, _)| sha).collect();
+        assert_eq!(order, vec![merge.clone(), main.clone(), root.clone()]);
+
+        // Topological order finishes the side branch before going on
+        let newer = commit(&repo, &tree, &[&main], "newer", 500);
+        let mut walk = RevWalk::new(&repo);
+        walk.sort = RevSort::Topo;
+        walk.push(&merge);
+        walk.push(&newer);
+        let order: Vec<String> = walk.map(|(sha, _)| sha).collect();
+        assert_eq!(
+            order,
+            vec![
+                newer.clone(),
+                merge.clone(),
+                side.clone(),
+                main.clone(),
+                root
+            ]
+        );
+
+        ref_update(&repo, "refs/heads/master", &merge, None, None);
+        ref_update(&repo, "refs/heads/newer", &newer, None, None);
+        let walk_revs = |revs: &[&str]| {
+            let mut walk = RevWalk::new(&repo);
+            let revs: Vec<String> = revs.iter().map(|r| r.to_string()).collect();
+            walk.push_revs(&revs).unwrap();
+            walk.map(|(sha, _)| sha).collect::<Vec<String>>()
+        };
+        assert_eq!(
+            walk_revs(&["newer..master"]),
+            vec![merge.clone(), side.clone()]
+        );
+        assert_eq!(
+            walk_revs(&["master", "^newer"]),
+            vec![merge.clone(), side.clone()]
+        );
+        assert_eq!(
+            walk_revs(&["master", "--not", "newer"]),
+            vec![merge.clone(), side.clone()]
+        );
+        assert_eq!(walk_revs(&["master..master"]), Vec::<String>::new());
+
+        // The symmetric difference marks the commits from the left side
+        let mut walk = RevWalk::new(&repo);
+        walk.push_revs(&["master...newer".to_string()]).unwrap();
+        let order: Vec<String> = walk.by_ref().map(|(sha, _)| sha).collect();
+        assert_eq!(order, vec![newer.clone(), merge.clone(), side.clone()]);
+        assert!(walk.is_left(&merge) && walk.is_left(&side) && !walk.is_left(&newer));
+    }
+
+    #[test]
+    fn test_branch_upstream() {
+        let mut repo = repo_scratch("branch-upstream");
+        repo.config.add("branch.topic.remote", "origin");
+        repo.config.add("branch.topic.merge", "refs/heads/main");
+        repo.config.add("branch.local.remote", ".");
+        repo.config.add("branch.local.merge", "refs/heads/topic");
+        assert_eq!(
+            branch_upstream(&repo, "local").as_deref(),
+            Some("refs/heads/topic")
+        );
+        // Without the remote configured there's nowhere the ref is kept
+        assert_eq!(branch_upstream(&repo, "topic"), None);
+
+        let fetch = |repo: &mut GitRepository, refspecs: &[&str]| {
+            repo.config
+                .values
+                .retain(|(name, _)| name != "remote.origin.fetch");
+            for refspec in refspecs {
+                repo.config.add("remote.origin.fetch", refspec);
+            }
+        };
+        fetch(&mut repo, &["+refs/heads/*:refs/remotes/origin/*"]);
+        assert_eq!(
+            branch_upstream(&repo, "topic").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+        fetch(&mut repo, &["refs/heads/main:refs/mirror/main"]);
+        assert_eq!(
+            branch_upstream(&repo, "topic").as_deref(),
+            Some("refs/mirror/main")
+        );
+        fetch(&mut repo, &["refs/heads/other:refs/mirror/other"]);
+        assert_eq!(branch_upstream(&repo, "topic"), None);
+
+        // With several fetch refspecs the first that takes the ref wins
+        fetch(
+            &mut repo,
+            &[
+                "+refs/tags/*:refs/tags/*",
+                "+refs/heads/*:refs/remotes/origin/*",
+                "refs/heads/main:refs/mirror/main",
+            ],
+        );
+        assert_eq!(
+            branch_upstream(&repo, "topic").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+    }
+
+    #[test]
+    fn test_rev_merge_bases() {
+        let repo = repo_scratch("rev-merge-bases");
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![],
+            }),
+            Some(repo.clone()),
+        );
+        let sorted = |mut v: Vec<String>| {
+            v.sort();
+            v
+        };
+
+        // A criss-cross merge has two best common ancestors
+        let m = commit(&repo, &tree, &[], "m", 1);
+        let a1 = commit(&repo, &tree, &[&m], "a1", 2);
+        let b1 = commit(&repo, &tree, &[&m], "b1", 3);
+        let a2 = commit(&repo, &tree, &[&a1, &b1], "a2", 4);
+        let b2 = commit(&repo, &tree, &[&b1, &a1], "b2", 5);
+        assert_eq!(
+            rev_merge_bases(&repo, &a2, &b2),
+            sorted(vec![a1.clone(), b1.clone()])
+        );
+        assert_eq!(rev_merge_bases(&repo, &a2, &a1), vec![a1.clone()]);
+        assert_eq!(rev_merge_bases(&repo, &a2, &a2), vec![a2.clone()]);
+
+        // With q dated after its descendant p, the walk takes q as common
+        // before p and stops before p's history makes q stale
+        let q = commit(&repo, &tree, &[], "q", 300);
+        let r = commit(&repo, &tree, &[&q], "r", 50);
+        let p = commit(&repo, &tree, &[&r], "p", 100);
+        let a = commit(&repo, &tree, &[&p, &q], "a", 400);
+        let b = commit(&repo, &tree, &[&p, &q], "b", 500);
+        let (found, _) = rev_paint_down(&repo, &a, &[b.clone()]);
+        assert_eq!(sorted(found), sorted(vec![p.clone(), q.clone()]));
+        assert_eq!(rev_merge_bases(&repo, &a, &b), vec![p]);
+    }
+
+    #[test]
+    fn test_rev_ahead_behind() {
+        let repo = repo_scratch("rev-ahead-behind");
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![],
+            }),
+            Some(repo.clone()),
+        );
+        let root = commit(&repo, &tree, &[], "root", 100);
+        let mut base = root.clone();
+        for i in 0..50 {
+            base = commit(&repo, &tree, &[&base], &format!("base{i}"), 200 + i);
+        }
+        let a1 = commit(&repo, &tree, &[&base], "a1", 1000);
+        let a2 = commit(&repo, &tree, &[&a1], "a2", 1100);
+        let b1 = commit(&repo, &tree, &[&base], "b1", 1050);
+        let b2 = commit(&repo, &tree, &[&b1, &a1], "b2", 1200);
+
+        // The walk stops above the shared history, so it is never read
+        let root_path = repo_path(&repo, &format!("objects/{}/{}", &root[..2], &root[2..]));
+        std::fs::remove_file(root_path).unwrap();
+
+        assert_eq!(rev_ahead_behind(&repo, &a2, &b2), (1, 2));
+        assert_eq!(rev_ahead_behind(&repo, &b2, &a2), (2, 1));
+        assert_eq!(rev_ahead_behind(&repo, &a1, &b2), (0, 2));
+        assert_eq!(rev_ahead_behind(&repo, &a2, &a2), (0, 0));
+    }
+
+    #[test]
+    fn test_rev_walk_ancestry_path() {
+        let repo = repo_scratch("rev-walk-ancestry-path");
+        let tree = object_write(
+            Box::new(GitTree {
+                fmt: b"tree".to_vec(),
+                items: vec![],
+            }),
+            Some(repo.clone()),
+        );
+        let root = commit(&repo, &tree, &[], "root", 100);
+        let bottom = commit(&repo, &tree, &[&root], "bottom", 200);
+        let side = commit(&repo, &tree, &[&root], "side", 300);
+        // A long chain with skewed dates, so the walk order isn't
+        // topological
+        let mut chain = vec![bottom.clone()];
+        for i in 0..2000 {
+            let parent = chain.last().unwrap().clone();
+            let time = 1000 + i * 10 - (i % 3) * 25;
+            chain.push(commit(&repo, &tree, &[&parent], &format!("c{i}"), time));
+        }
+        let tip = chain.last().unwrap().clone();
+        let merge = commit(&repo, &tree, &[&tip, &side], "merge", 100_000);
+
+        let mut walk = RevWalk::new(&repo);
+        walk.ancestry_path = true;
+        walk.push(&merge);
+        walk.hide(&bottom);
+        let shown: HashSet<String> = walk.map(|(sha, _)| sha).collect();
+        assert_eq!(shown.len(), 2001);
+        assert!(shown.contains(&merge) && shown.contains(&tip));
+        assert!(!shown.contains(&side) && !shown.contains(&bottom));
+    }
+
+    #[test]
+    fn test_rev_walk_paths() {
+        let repo = repo_scratch("rev-walk-paths");
+        let tree = |content: &str| {
+            let blob = object_write(
+                Box::new(crate::git::objects::GitBlob {
+                    fmt: b"blob".to_vec(),
+                    blobdata: content.as_bytes().to_vec(),
+                }),
+                Some(repo.clone()),
+            );
+            object_write(
+                Box::new(GitTree {
+                    fmt: b"tree".to_vec(),
+                    items: vec![GitTreeLeaf {
+                        mode: "100644".to_string(),
+                        path: b"file".to_vec(),
+                        sha: blob,
+                    }],
+                }),
+                Some(repo.clone()),
+            )
+        };
+        let (one, two) = (tree("one\n"), tree("two\n"));
+        let root = commit(&repo, &one, &[], "root", 100);
+        let change = commit(&repo, &two, &[&root], "change", 200);
+        let same = commit(&repo, &two, &[&change], "same", 300);
+        // A merge that took the content of one side leaves the other out
+        let side = commit(&repo, &one, &[&root], "side", 400);
+        let merge = commit(&repo, &two, &[&same, &side], "merge", 500);
+
+        let mut walk = RevWalk::new(&repo);
+        walk.paths = vec!["file".to_string()];
+        walk.push(&merge);
+        let order: Vec<String> = walk.by_ref().map(|(sha, _)| sha).collect();
+        assert_eq!(order, vec![change.clone(), root.clone()]);
+        assert_eq!(walk.parents(&change), vec![root]);
+    }
+}
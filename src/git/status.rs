@@ -0,0 +1,348 @@
+use crate::cli::ObjectType;
+use crate::git::helpers::object_find;
+use crate::git::ignore::GitIgnore;
+use crate::git::index::{index_read, GitIndexEntry};
+use crate::git::objects::tree::tree_flatten;
+use crate::git::refs::{ref_resolve, ref_symbolic};
+use crate::git::repo::GitRepository;
+use crate::git::revision::{branch_upstream, rev_ahead_behind, rev_parse};
+use crate::git::worktree::{
+    worktree_compare, worktree_files, worktree_mode, worktree_path, WorktreeState,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+/// Which untracked files `status` looks for (`--untracked-files`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UntrackedMode {
+    /// Do not look for untracked files
+    No,
+    /// Show untracked directories as a whole
+    #[default]
+    Normal,
+    /// Show every untracked file
+    All,
+}
+
+/// A mode and object id, as recorded in a tree or the index
+pub type StatusBlob = (u32, String);
+
+/// A path with staged or unstaged changes, or a conflict
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEntry {
+    pub path: Vec<u8>,
+    /// Change between `HEAD` and the index: `A`, `M`, `D`, `T` or `' '`. For
+    /// conflicts, the first letter of the short format code.
+    pub staged: char,
+    /// Change between the index and the worktree, like `staged`
+    pub unstaged: char,
+    pub head: Option<StatusBlob>,
+    pub index: Option<StatusBlob>,
+    /// Mode of the worktree file, 0 when it is missing
+    pub worktree_mode: u32,
+    /// Conflict stages: base, ours and theirs
+    pub stages: Option<[Option<StatusBlob>; 3]>,
+    /// Where the path was renamed from, for a staged `R`
+    pub orig: Option<Vec<u8>>,
+}
+
+impl StatusEntry {
+    pub fn is_unmerged(&self) -> bool {
+        self.stages.is_some()
+    }
+}
+
+/// Everything `status` reports about a repository
+#[derive(Debug, Clone, Default)]
+pub struct GitStatus {
+    /// Short name of the current branch, `None` when `HEAD` is detached
+    pub branch: Option<String>,
+    /// Commit `HEAD` points to, `None` before the first commit
+    pub head: Option<String>,
+    /// Full ref name of the branch's upstream, if configured
+    pub upstream: Option<String>,
+    /// Commits ahead of and behind the upstream, `None` if it is gone
+    pub ahead_behind: Option<(usize, usize)>,
+    /// Changed and conflicted paths, sorted
+    pub entries: Vec<StatusEntry>,
+    /// Untracked files, and directories ending in `/`, sorted
+    pub untracked: Vec<Vec<u8>>,
+}
+
+/// Gather the status of the repository: `HEAD` against the index, the
+/// index against the worktree, and untracked files not ignored.
+pub fn status_read(repo: &GitRepository, untracked: UntrackedMode) -> GitStatus {
+    let mut status = GitStatus {
+        branch: ref_symbolic(repo, "HEAD")
+            .map(|r| r.strip_prefix("refs/heads/").unwrap_or(&r).to_string()),
+        head: rev_parse(repo, "HEAD"),
+        ..Default::default()
+    };
+    if let Some(branch) = &status.branch {
+        status.upstream = branch_upstream(repo, branch);
+    }
+    if let (Some(head), Some(upstream)) = (&status.head, &status.upstream) {
+        status.ahead_behind =
+            ref_resolve(repo, upstream).map(|up| rev_ahead_behind(repo, head, &up));
+    }
+
+    let head_tree = object_find(repo, "HEAD", Some(ObjectType::Tree), true)
+        .map(|tree| tree_flatten(repo, &tree))
+        .unwrap_or_default();
+    let index = index_read(repo);
+
+    let mut entries: BTreeMap<Vec<u8>, StatusEntry> = BTreeMap::new();
+    let mut conflicts: BTreeMap<&[u8], [Option<StatusBlob>; 3]> = BTreeMap::new();
+    for entry in &index.entries {
+        if entry.stage > 0 {
+            conflicts.entry(&entry.name).or_default()[entry.stage as usize - 1] =
+                Some((entry.mode, entry.sha.clone()));
+            continue;
+        }
+
+        let head = head_tree
+            .get(&entry.name)
+            .map(|leaf| (leaf.mode_bits(), leaf.sha.clone()));
+        let (unstaged, worktree_mode) = status_unstaged(repo, entry);
+        // Intent-to-add entries are not staged yet
+        let index_blob = (!entry.intent_to_add).then(|| (entry.mode, entry.sha.clone()));
+        let staged = match (&head, &index_blob) {
+            (None, None) => ' ',
+            (None, Some(_)) => 'A',
+            (Some(_), None) => 'D',
+            (Some(h), Some(i)) => status_letter(h, i),
+        };
+
+        if staged != ' ' || unstaged != ' ' {
+            entries.insert(
+                entry.name.clone(),
+                StatusEntry {
+                    path: entry.name.clone(),
+                    staged,
+                    unstaged,
+                    head,
+                    index: Some((entry.mode, entry.sha.clone())),
+                    worktree_mode,
+                    stages: None,
+                    orig: None,
+                },
+            );
+        }
+    }
+
+    let tracked: HashSet<&[u8]> = index.entries.iter().map(|e| e.name.as_slice()).collect();
+    for (path, leaf) in &head_tree {
+        if !tracked.contains(path.as_slice()) {
+            entries.insert(
+                path.clone(),
+                StatusEntry {
+                    path: path.clone(),
+                    staged: 'D',
+                    unstaged: ' ',
+                    head: Some((leaf.mode_bits(), leaf.sha.clone())),
+                    index: None,
+                    worktree_mode: 0,
+                    stages: None,
+                    orig: None,
+                },
+            );
+        }
+    }
+
+    for (path, stages) in conflicts {
+        let code = match [
+            stages[0].is_some(),
+            stages[1].is_some(),
+            stages[2].is_some(),
+        ] {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        };
+        let worktree_mode = fs::symlink_metadata(worktree_path(repo, path))
+            .map(|meta| worktree_mode(&meta))
+            .unwrap_or(0);
+        let mut code = code.chars();
+        entries.insert(
+            path.to_vec(),
+            StatusEntry {
+                path: path.to_vec(),
+                staged: code.next().unwrap(),
+                unstaged: code.next().unwrap(),
+                head: head_tree
+                    .get(path)
+                    .map(|leaf| (leaf.mode_bits(), leaf.sha.clone())),
+                index: None,
+                worktree_mode,
+                stages: Some(stages),
+                orig: None,
+            },
+        );
+    }
+    status_renames(&mut entries);
+    status.entries = entries.into_values().collect();
+
+    if untracked != UntrackedMode::No {
+        let mut ignore = GitIgnore::new(repo);
+        // Directories holding tracked files are never shown as a whole
+        let mut tracked_dirs = HashSet::new();
+        for name in &tracked {
+            let mut dir = *name;
+            while let Some(i) = dir.iter().rposition(|&c| c == b'/') {
+                dir = &dir[..i];
+                tracked_dirs.insert(dir);
+            }
+        }
+
+        for path in worktree_files(repo, Some(&mut ignore)) {
+            if tracked.contains(path.as_slice()) {
+                continue;
+            }
+            let shown = match untracked {
+                UntrackedMode::All => path,
+                _ => status_collapse(&path, &tracked_dirs),
+            };
+            if status.untracked.last() != Some(&shown) {
+                status.untracked.push(shown);
+            }
+        }
+    }
+
+    status
+}
+
+/// Turn staged deletions and additions of identical content into renames
+fn status_renames(entries: &mut BTreeMap<Vec<u8>, StatusEntry>) {
+    let mut deleted: Vec<(Vec<u8>, StatusBlob)> = entries
+        .values()
+        .filter(|e| e.staged == 'D' && !e.is_unmerged())
+        .filter_map(|e| Some((e.path.clone(), e.head.clone()?)))
+        .collect();
+
+    for entry in entries.values_mut() {
+        if entry.staged != 'A' || entry.is_unmerged() {
+            continue;
+        }
+        let sha = match &entry.index {
+            Some((_, sha)) => sha,
+            None => continue,
+        };
+        if let Some(i) = deleted.iter().position(|(_, head)| head.1 == *sha) {
+            let (orig, head) = deleted.remove(i);
+            entry.staged = 'R';
+            entry.head = Some(head);
+            entry.orig = Some(orig);
+        }
+    }
+
+    let renamed: Vec<Vec<u8>> = entries.values().filter_map(|e| e.orig.clone()).collect();
+    for orig in renamed {
+        if entries.get(&orig).is_some_and(|e| e.unstaged == ' ') {
+            entries.remove(&orig);
+        } else if let Some(e) = entries.get_mut(&orig) {
+            e.staged = ' ';
+        }
+    }
+}
+
+/// Status letter for an index entry compared with the worktree, and the
+/// worktree mode
+fn status_unstaged(repo: &GitRepository, entry: &GitIndexEntry) -> (char, u32) {
+    let meta = match fs::symlink_metadata(worktree_path(repo, &entry.name)) {
+        Ok(meta) => meta,
+        Err(_) => return ('D', 0),
+    };
+    let mode = worktree_mode(&meta);
+    let letter = match worktree_compare(repo, entry) {
+        WorktreeState::Unchanged => ' ',
+        WorktreeState::Deleted => 'D',
+        _ if entry.intent_to_add => 'A',
+        _ if mode & 0o170000 != entry.mode & 0o170000 => 'T',
+        WorktreeState::Modified => 'M',
+    };
+    (letter, mode)
+}
+
+/// Letter for a change between two blobs: `T` when the kind of object
+/// changes, `M` for any other difference
+fn status_letter(a: &StatusBlob, b: &StatusBlob) -> char {
+    if a.0 & 0o170000 != b.0 & 0o170000 {
+        'T'
+    } else if a != b {
+        'M'
+    } else {
+        ' '
+    }
+}
+
+/// The outermost directory of `path` that has no tracked files, as `dir/`,
+/// or the path itself
+fn status_collapse(path: &[u8], tracked_dirs: &HashSet<&[u8]>) -> Vec<u8> {
+    let mut end = 0;
+    while let Some(i) = path[end..].iter().position(|&c| c == b'/') {
+        let dir = &path[..end + i + 1];
+        if !tracked_dirs.contains(&dir[..dir.len() - 1]) {
+            return dir.to_vec();
+        }
+        end += i + 1;
+    }
+    path.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_collapse() {
+        let tracked: HashSet<&[u8]> = [&b"src"[..], b"src/git"].into_iter().collect();
+        assert_eq!(status_collapse(b"new/a/b.rs", &tracked), b"new/");
+        assert_eq!(status_collapse(b"src/new.rs", &tracked), b"src/new.rs");
+        assert_eq!(status_collapse(b"src/git/x/y.rs", &tracked), b"src/git/x/");
+        assert_eq!(status_collapse(b"top", &tracked), b"top");
+    }
+
+    #[test]
+    fn test_status_read() {
+        let repo = crate::git::repo::repo_scratch("status");
+        let mut index = crate::git::index::GitIndex::default();
+        for name in ["kept", "changed", "old"] {
+            fs::write(repo.worktree.join(name), name).unwrap();
+            index.add(crate::git::worktree::worktree_entry(
+                &repo,
+                name.as_bytes(),
+                None,
+                true,
+            ));
+        }
+        crate::git::index::index_write(&repo, &index);
+        fs::write(repo.worktree.join("changed"), "different").unwrap();
+        fs::create_dir_all(repo.worktree.join("new/deep")).unwrap();
+        fs::write(repo.worktree.join("new/deep/file"), "x").unwrap();
+
+        let status = status_read(&repo, UntrackedMode::Normal);
+        assert_eq!(status.branch.as_deref(), Some("master"));
+        assert_eq!(status.head, None);
+        let codes: Vec<(&[u8], char, char)> = status
+            .entries
+            .iter()
+            .map(|e| (e.path.as_slice(), e.staged, e.unstaged))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (&b"changed"[..], 'A', 'M'),
+                (b"kept", 'A', ' '),
+                (b"old", 'A', ' '),
+            ]
+        );
+        assert_eq!(status.untracked, vec![b"new/".to_vec()]);
+
+        let status = status_read(&repo, UntrackedMode::All);
+        assert_eq!(status.untracked, vec![b"new/deep/file".to_vec()]);
+    }
+}
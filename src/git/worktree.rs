@@ -0,0 +1,419 @@
+use crate::git::ignore::{wildmatch_bytes, GitIgnore};
+use crate::git::index::GitIndexEntry;
+use crate::git::objects::tree::tree_path_join;
+use crate::git::objects::{
+    objects::{object_read, object_write},
+    GitBlob,
+};
+use crate::git::repo::GitRepository;
+use std::ffi::OsStr;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+/// How a worktree file compares to its index entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorktreeState {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+/// Whether the repository trusts the executable bit (`core.filemode`)
+pub fn worktree_trust_filemode(repo: &GitRepository) -> bool {
+    repo.config.core.filemode.as_deref() != Some("false")
+}
+
+/// The index mode git records for a file with the given metadata
+pub fn worktree_mode(meta: &Metadata) -> u32 {
+    let ft = meta.file_type();
+    if ft.is_symlink() {
+        0o120000
+    } else if ft.is_dir() {
+        0o160000
+    } else if meta.permissions().mode() & 0o100 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Where a path relative to the top of the worktree is on disk
+pub fn worktree_path(repo: &GitRepository, path: &[u8]) -> PathBuf {
+    repo.worktree.join(OsStr::from_bytes(path))
+}
+
+/// Read a worktree file as git stores it: symlinks become their target
+pub fn worktree_read(repo: &GitRepository, path: &[u8]) -> Vec<u8> {
+    let full = worktree_path(repo, path);
+    let meta = fs::symlink_metadata(&full).expect("Failed to stat file");
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(&full).expect("Failed to read symlink");
+        target.as_os_str().as_bytes().to_vec()
+    } else {
+        fs::read(&full).expect("Failed to read file")
+    }
+}
+
+/// Hash a worktree file as a blob, writing it to the object store if `write`
+pub fn worktree_hash(repo: &GitRepository, path: &[u8], write: bool) -> String {
+    let obj = Box::new(GitBlob {
+        fmt: b"blob".to_vec(),
+        blobdata: worktree_read(repo, path),
+    });
+    object_write(obj, write.then(|| repo.clone()))
+}
+
+/// Copy stat information into an index entry
+pub fn worktree_stat(entry: &mut GitIndexEntry, meta: &Metadata) {
+    entry.ctime = (meta.ctime() as u32, meta.ctime_nsec() as u32);
+    entry.mtime = (meta.mtime() as u32, meta.mtime_nsec() as u32);
+    entry.dev = meta.dev() as u32;
+    entry.ino = meta.ino() as u32;
+    entry.uid = meta.uid();
+    entry.gid = meta.gid();
+    entry.size = meta.size() as u32;
+}
+
+/// Build an index entry for a worktree file, hashing its content.
+///
+/// When the executable bit is not trusted, the mode of the `old` entry is
+/// kept for regular files.
+pub fn worktree_entry(
+    repo: &GitRepository,
+    path: &[u8],
+    old: Option<&GitIndexEntry>,
+    write: bool,
+) -> GitIndexEntry {
+    let meta = fs::symlink_metadata(worktree_path(repo, path)).expect("Failed to stat file");
+    let mut mode = worktree_mode(&meta);
+    if let Some(old) = old {
+        if !worktree_trust_filemode(repo) && mode != 0o120000 && old.mode != 0o120000 {
+            mode = old.mode;
+        }
+    }
+
+    let mut entry = GitIndexEntry {
+        mode,
+        sha: worktree_hash(repo, path, write),
+        name: path.to_vec(),
+        ..Default::default()
+    };
+    worktree_stat(&mut entry, &meta);
+    entry
+}
+
+/// Whether the cached stat information still describes the file
+fn stat_matches(entry: &GitIndexEntry, meta: &Metadata) -> bool {
+    let mut fresh = entry.clone();
+    worktree_stat(&mut fresh, meta);
+    fresh == *entry
+}
+
+/// Compare a worktree file with its index entry.
+///
+/// Matching stat data is trusted; otherwise the content is hashed to tell
+/// a real modification apart from a file that was merely touched.
+pub fn worktree_compare(repo: &GitRepository, entry: &GitIndexEntry) -> WorktreeState {
+    let meta = match fs::symlink_metadata(worktree_path(repo, &entry.name)) {
+        Ok(meta) if !meta.is_dir() || entry.mode == 0o160000 => meta,
+        _ => return WorktreeState::Deleted,
+    };
+    if entry.intent_to_add {
+        return WorktreeState::Modified;
+    }
+    if entry.mode == 0o160000 {
+        return WorktreeState::Unchanged;
+    }
+
+    let mode = worktree_mode(&meta);
+    let mode_changed = match worktree_trust_filemode(repo) {
+        true => mode != entry.mode,
+        // Only the file type matters when the executable bit is not trusted
+        false => (mode == 0o120000) != (entry.mode == 0o120000),
+    };
+    if mode_changed || meta.size() as u32 != entry.size {
+        return WorktreeState::Modified;
+    }
+    if stat_matches(entry, &meta) {
+        return WorktreeState::Unchanged;
+    }
+
+    match worktree_hash(repo, &entry.name, false) == entry.sha {
+        true => WorktreeState::Unchanged,
+        false => WorktreeState::Modified,
+    }
+}
+
+/// Write the blob of an index entry to the worktree and record the new
+/// file's stat data in the entry.
+///
+/// Whatever is in the way is replaced: an existing file or directory at the
+/// path, or files where parent directories are needed.
+pub fn worktree_checkout(repo: &GitRepository, entry: &mut GitIndexEntry) {
+    let full = worktree_path(repo, &entry.name);
+    let mut dir = repo.worktree.clone();
+    for part in Path::new(OsStr::from_bytes(&entry.name))
+        .parent()
+        .into_iter()
+        .flat_map(|p| p.iter())
+    {
+        dir.push(part);
+        if fs::symlink_metadata(&dir).is_ok_and(|m| !m.is_dir()) {
+            fs::remove_file(&dir).expect("Failed to remove file in the way");
+        }
+    }
+    fs::create_dir_all(&dir).expect("Failed to create directory");
+    match fs::symlink_metadata(&full) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&full),
+        Ok(_) => fs::remove_file(&full),
+        Err(_) => Ok(()),
+    }
+    .expect("Failed to remove file in the way");
+
+    if entry.mode == 0o160000 {
+        // Submodules are not checked out, only their directory is created
+        fs::create_dir_all(&full).expect("Failed to create directory");
+    } else {
+        let obj = object_read(repo, &entry.sha).expect("Failed to read blob");
+        let blob = obj
+            .as_any()
+            .downcast_ref::<GitBlob>()
+            .expect("Not a blob object");
+
+        if entry.mode == 0o120000 {
+            let target = OsStr::from_bytes(&blob.blobdata);
+            symlink(target, &full).expect("Failed to create symlink");
+        } else {
+            let perm = if entry.mode & 0o100 != 0 {
+                0o777
+            } else {
+                0o666
+            };
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(perm)
+                .open(&full)
+                .expect("Failed to create file");
+            file.write_all(&blob.blobdata)
+                .expect("Failed to write file");
+        }
+    }
+
+    let meta = fs::symlink_metadata(&full).expect("Failed to stat file");
+    worktree_stat(entry, &meta);
+}
+
+/// Delete a worktree file, then any parent directories left empty
+pub fn worktree_remove(repo: &GitRepository, path: &[u8]) {
+    let full = worktree_path(repo, path);
+    match fs::remove_file(&full) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => panic!("Failed to remove '{}': {e}", full.display()),
+    }
+
+    let mut dir = full.parent();
+    while let Some(d) = dir {
+        if d == repo.worktree || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// List files in the worktree, sorted, relative to its top.
+///
+/// The `.git` directory is skipped and nested repositories are reported as
+/// `dir/`. With `ignore`, ignored files and directories are left out.
+pub fn worktree_files(repo: &GitRepository, mut ignore: Option<&mut GitIgnore>) -> Vec<Vec<u8>> {
+    let mut ret = Vec::new();
+    worktree_walk(repo, b"", &mut ignore, &mut ret);
+    ret.sort();
+    ret
+}
+
+fn worktree_walk(
+    repo: &GitRepository,
+    dir: &[u8],
+    ignore: &mut Option<&mut GitIgnore>,
+    out: &mut Vec<Vec<u8>>,
+) {
+    let entries = match fs::read_dir(worktree_path(repo, dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries {
+        let entry = entry.expect("Failed to read directory entry");
+        let name = entry.file_name();
+        let path = tree_path_join(dir, name.as_bytes());
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+
+        if is_dir && name == ".git" {
+            continue;
+        }
+        if let Some(ignore) = ignore.as_deref_mut() {
+            if ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+        }
+
+        if !is_dir {
+            out.push(path);
+        } else if entry.path().join(".git").exists() {
+            out.push([&path[..], b"/"].concat());
+        } else {
+            worktree_walk(repo, &path, ignore, out);
+        }
+    }
+}
+
+/// Lexically normalize a path, resolving `.` and `..` components
+fn normalize(path: &Path) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                ret.pop();
+            }
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Directory of the process relative to the top of the worktree, `""` at
+/// the top itself
+pub fn worktree_prefix(repo: &GitRepository) -> String {
+    let cwd = std::env::current_dir()
+        .and_then(|p| p.canonicalize())
+        .expect("Failed to get current directory");
+    cwd.strip_prefix(&repo.worktree)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Turn a path given on the command line (relative to the current
+/// directory) into a path relative to the top of the worktree
+pub fn worktree_relpath(repo: &GitRepository, path: &str) -> String {
+    let cwd = std::env::current_dir()
+        .and_then(|p| p.canonicalize())
+        .expect("Failed to get current directory");
+    let full = normalize(&cwd.join(path));
+    match full.strip_prefix(&repo.worktree) {
+        Ok(rel) => rel.to_string_lossy().to_string(),
+        Err(_) => {
+            eprintln!(
+                "fatal: '{path}' is outside repository at '{}'",
+                repo.worktree.display()
+            );
+            process::exit(128);
+        }
+    }
+}
+
+/// Express a worktree path relative to the directory `prefix`, for display
+pub fn worktree_display(path: &[u8], prefix: &str) -> Vec<u8> {
+    if prefix.is_empty() {
+        return path.to_vec();
+    }
+    let mut base: Vec<&[u8]> = prefix.as_bytes().split(|&c| c == b'/').collect();
+    let mut rest: Vec<&[u8]> = path.split(|&c| c == b'/').collect();
+    while !base.is_empty() && rest.len() > 1 && base[0] == rest[0] {
+        base.remove(0);
+        rest.remove(0);
+    }
+
+    let mut ret = b"../".repeat(base.len());
+    ret.extend(rest.join(&b'/'));
+    ret
+}
+
+/// Quote a path for display like git does by default (`core.quotePath`):
+/// paths with control characters, `"`, `\\` or non-ASCII bytes are put in
+/// double quotes with C-style escapes, others are shown as they are
+pub fn path_quote(path: &[u8]) -> String {
+    let needs_quote = |c: u8| c < 0x20 || c == b'"' || c == b'\\' || c >= 0x7f;
+    if !path.iter().any(|&c| needs_quote(c)) {
+        return String::from_utf8(path.to_vec()).unwrap();
+    }
+
+    let mut ret = String::from("\"");
+    for &c in path {
+        match c {
+            b'\x07' => ret.push_str("\\a"),
+            b'\x08' => ret.push_str("\\b"),
+            b'\t' => ret.push_str("\\t"),
+            b'\n' => ret.push_str("\\n"),
+            b'\x0b' => ret.push_str("\\v"),
+            b'\x0c' => ret.push_str("\\f"),
+            b'\r' => ret.push_str("\\r"),
+            b'"' => ret.push_str("\\\""),
+            b'\\' => ret.push_str("\\\\"),
+            c if needs_quote(c) => ret.push_str(&format!("\\{c:03o}")),
+            c => ret.push(c as char),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Whether `path` is selected by a pathspec, both relative to the worktree.
+///
+/// A pathspec matches the path itself, anything below it when it names a
+/// directory, or acts as a glob in which `*` also matches `/`.
+pub fn pathspec_match(spec: &str, path: &[u8]) -> bool {
+    let spec = spec.trim_end_matches('/').as_bytes();
+    spec.is_empty()
+        || path == spec
+        || path
+            .strip_prefix(spec)
+            .is_some_and(|rest| rest.starts_with(b"/"))
+        || wildmatch_bytes(spec, path, false)
+}
+
+/// Whether any of `specs` selects `path`; an empty list selects everything
+pub fn pathspec_any(specs: &[String], path: &[u8]) -> bool {
+    specs.is_empty() || specs.iter().any(|s| pathspec_match(s, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pathspec_match() {
+        assert!(pathspec_match("src", b"src/git/index.rs"));
+        assert!(pathspec_match("src/", b"src/lib.rs"));
+        assert!(!pathspec_match("src", b"srcs/lib.rs"));
+        assert!(pathspec_match("*.rs", b"src/git/index.rs"));
+        assert!(pathspec_match("", b"anything"));
+        assert!(!pathspec_match("*.md", b"src/lib.rs"));
+        assert!(pathspec_match("caf*", b"caf\xe9"));
+    }
+
+    #[test]
+    fn test_path_quote() {
+        assert_eq!(path_quote(b"src/lib.rs"), "src/lib.rs");
+        assert_eq!(path_quote(b"caf\xe9"), "\"caf\\351\"");
+        assert_eq!(path_quote(b"a\tb\"c\\"), "\"a\\tb\\\"c\\\\\"");
+        assert_eq!(path_quote(b"with space"), "with space");
+    }
+
+    #[test]
+    fn test_worktree_display() {
+        assert_eq!(worktree_display(b"src/lib.rs", ""), b"src/lib.rs");
+        assert_eq!(worktree_display(b"src/lib.rs", "src"), b"lib.rs");
+        assert_eq!(
+            worktree_display(b"README.md", "src/git"),
+            b"../../README.md"
+        );
+        assert_eq!(worktree_display(b"src/git.rs", "src/git"), b"../git.rs");
+    }
+}
@@ -1,2 +1,2 @@
 pub mod cli;
-mod git;
+pub mod git;